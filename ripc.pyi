@@ -1,5 +1,6 @@
+import os
 from enum import Enum
-from typing import Callable
+from typing import Any, Callable
 
 
 class OpenMode(Enum):
@@ -8,14 +9,58 @@ class OpenMode(Enum):
     ReadWrite = 2
 
 
+class Backpressure(Enum):
+    """
+    What SharedQueue.write does when max_pending_writes elements are already waiting to be written
+    """
+    Block = 0,
+    Raise = 1,
+    DropOldest = 2,
+    DropNewest = 3
+
+
+class CorruptMessageError(Exception):
+    """
+    Raised when a payload does not match the checksum written with it,
+    the corrupt message is skipped by the next read
+    """
+    pass
+
+
+class MessageInfo:
+    """
+    Recorded by the writer alongside each message
+    """
+    version: int
+    monotonic_ns: int
+    """CLOCK_MONOTONIC timestamp of the write, comparable with time.clock_gettime_ns(time.CLOCK_MONOTONIC)"""
+    realtime_ns: int
+    """Wall-clock timestamp of the write, nanoseconds since the Unix epoch"""
+    writer_pid: int
+    writer_tid: int
+
+    def age_ns(self) -> int:
+        """
+        :returns: nanoseconds elapsed on the monotonic clock since the message was written
+        """
+        pass
+
+
 class SharedMessage(object):
 
     @staticmethod
-    def create(name: str, size: int, mode: OpenMode = OpenMode.ReadWrite) -> 'SharedMessage':
+    def create(name: str, size: int, mode: OpenMode = OpenMode.ReadWrite, checksum: bool = False,
+               stream: bool = False, priority_inheritance: bool = False) -> 'SharedMessage':
         """
         :param name: is recommended to start with a '/'
         :param size: cannot be 0
         :param mode: 
+        :param checksum: store a CRC32C of every message and verify it on every read,
+        reads raise CorruptMessageError on mismatch
+        :param stream: create a second segment named '{name}.stream', holding 8 chunks of `size` bytes,
+        required by open_stream and read_stream
+        :param priority_inheritance: lock the segment with FUTEX_LOCK_PI, so that a lower priority process holding
+        the lock is boosted while a real-time process waits for it. Readers no longer copy in parallel
         """
         pass
 
@@ -64,6 +109,51 @@ class SharedMessage(object):
         """
         pass
 
+    def read_with_info(self, blocking: bool = False) -> tuple[bytes, MessageInfo] | None:
+        """
+        Same as try_read, or blocking_read if blocking is True,
+        but also returns the metadata recorded by the writer
+        """
+        pass
+
+    def peek(self) -> bytes | None:
+        """
+        Returns the latest message without marking it as read,
+        the next try_read or blocking_read will still return it
+        :returns: the latest message, or None if nothing was written yet or if the shared memory is closed
+        """
+        pass
+
+    def open_stream(self) -> 'MessageStreamWriter':
+        """
+        Opens a stream for payloads larger than the message size, sent as sequenced chunks
+        Each stream is received by a single reader. Only one writer may stream at a time,
+        the chunks of concurrent streams interleave and their reader fails with ValueError
+        :raises ValueError: if the message was not created with stream=True
+        """
+        pass
+
+    def read_stream(self) -> 'MessageStreamReader':
+        """
+        Returns a reader for the next stream, chunks of streams started before are skipped
+        :raises ValueError: if the message was not created with stream=True
+        """
+        pass
+
+    def fileno(self) -> int:
+        """
+        Returns a file descriptor that becomes readable when a new version is written, or when the message is closed,
+        so that it can be registered with selectors, select.epoll or an event loop.
+        The file descriptor is owned by this instance and stays readable until clear is called
+        """
+        pass
+
+    def clear(self):
+        """
+        Makes the file descriptor returned by fileno not readable, until the next version is written
+        """
+        pass
+
     def is_new_version_available(self) -> bool:
         """
         Check if the next read will return a new message
@@ -89,6 +179,45 @@ class SharedMessage(object):
         """
         pass
 
+    def has_checksum(self) -> bool:
+        """
+        :returns: true if the segment was created with checksum=True
+        """
+        pass
+
+    def subscribe(self, callback: Callable[[bytes], Any], *, executor: Any = None) -> 'Subscription':
+        """
+        Calls the callback with every new payload from a background thread, until unsubscribed or closed.
+        The payloads are read with this handle, so it should not be read from elsewhere while subscribed
+        Exceptions raised by the callback are reported with sys.unraisablehook,
+        corrupt messages are skipped and counted in corrupt_reads, see stats()
+        :param executor: an object with a submit(fn, *args) method, such as a concurrent.futures.Executor,
+        to run the callback on instead of the background thread
+        """
+        pass
+
+    def stats(self) -> dict[str, int]:
+        """
+        Counters shared by every process using the message, kept in the segment header:
+        writes, reads, bytes_written, bytes_read, blocked_writes, blocked_reads, lock_contentions,
+        and corrupt_reads, the messages with a checksum mismatch that readers skipped
+        """
+        pass
+
+    def reset_stats(self) -> None:
+        """
+        Sets all the counters to 0, for every process
+        """
+        pass
+
+    def latency_stats(self) -> dict[str, dict[str, int]]:
+        """
+        Latencies of the reads made with this handle, in nanoseconds, as count, p50, p90, p99, p999 and max:
+        write_to_read from the write of a message to its read, lock_wait the time a read waited for locks.
+        Percentiles are within 12.5% of the actual value, `ripc latency <name>` aggregates all the readers
+        """
+        pass
+
     def is_closed(self) -> bool:
         """
         Check if the shared memory has been closed by the writer
@@ -101,23 +230,302 @@ class SharedMessage(object):
         """
         pass
 
+    def release(self) -> None:
+        """
+        If this handle created the message, closes it when the handle can write, and removes its name
+        and that of its '{name}.stream' segment, instead of waiting for the handle to be garbage collected.
+        Handles that only opened the message leave it open for the other processes.
+        The handle no longer counts as a reader, so it should not be used afterwards
+        """
+        pass
+
+    def __enter__(self) -> 'SharedMessage':
+        pass
+
+    def __exit__(self, *args) -> None:
+        """
+        Calls release()
+        """
+        pass
+
+    def __iter__(self) -> 'SharedMessage':
+        pass
+
+    def __next__(self) -> bytes:
+        """
+        Blocks until the next version, releasing the GIL. The iteration stops once the message is closed
+        """
+        pass
+
+    def __reduce__(self) -> tuple:
+        """
+        Pickled as its name and mode, so that it can be passed to a multiprocessing child,
+        which opens the same message again. It never unlinks the segment
+        """
+        pass
+
+
+class MessageStreamWriter:
+    """
+    Binary file-like object, buffers writes into chunks that are sent when full or flushed.
+    A writer garbage collected before close aborts its stream, which its reader raises
+    """
+    closed: bool
+
+    def write(self, data: bytes) -> int:
+        """
+        Blocks while the stream channel is full, releasing the GIL
+        :raises EOFError: if the message was closed
+        """
+        pass
+
+    def flush(self) -> None:
+        pass
+
+    def close(self) -> None:
+        """
+        Sends the remaining data and marks the end of the stream
+        """
+        pass
+
+    def readable(self) -> bool:
+        pass
+
+    def writable(self) -> bool:
+        pass
+
+    def seekable(self) -> bool:
+        pass
+
+    def __enter__(self) -> 'MessageStreamWriter':
+        pass
+
+    def __exit__(self, *args) -> None:
+        pass
+
+
+class MessageStreamReader:
+    """
+    Binary file-like object, reassembles the chunks of a single stream
+    """
+    closed: bool
+
+    def read(self, size: int = -1) -> bytes:
+        """
+        Blocks until data is available, releasing the GIL
+        :param size: maximum number of bytes to return, or everything until the end of the stream if negative
+        :returns: empty bytes once the end of the stream has been reached
+        :raises EOFError: if the message was closed before the end of the stream,
+        or if the writer was garbage collected or exited before closing the stream
+        :raises ValueError: if chunks were received out of order
+        """
+        pass
+
+    def readall(self) -> bytes:
+        pass
+
+    def close(self) -> None:
+        pass
+
+    def readable(self) -> bool:
+        pass
+
+    def writable(self) -> bool:
+        pass
+
+    def seekable(self) -> bool:
+        pass
+
+    def __enter__(self) -> 'MessageStreamReader':
+        pass
+
+    def __exit__(self, *args) -> None:
+        pass
+
+
+class Recorder:
+    """
+    Records every version written to the channels into a file, with its info.
+    The recorder counts as a reader of the channels, so that the writers of a queue wait for it
+    """
+
+    @staticmethod
+    def start(path: str | os.PathLike, messages: list[str] = [], queues: list[str] = []) -> 'Recorder':
+        """
+        :param path: file to write the recording to, overwritten if it exists
+        :param messages: names of the SharedMessage channels to record
+        :param queues: names of the SharedQueue channels to record
+        """
+        pass
+
+    def wait(self) -> int:
+        """
+        Blocks until every channel is closed, releasing the GIL
+        :return: number of records
+        :raises ValueError: if the recording was already stopped
+        """
+        pass
+
+    def stop(self) -> int:
+        """
+        Stops recording and finishes the file
+        :return: number of records
+        :raises ValueError: if the recording was already stopped
+        """
+        pass
+
+
+class Replayer:
+    """
+    Writes a recording into fresh segments, created with the sizes and options of the recorded channels
+    """
+
+    @staticmethod
+    def open(path: str | os.PathLike, names: dict[str, str] = {}) -> 'Replayer':
+        """
+        Creates a segment for every recorded channel
+        :param path: file written by a Recorder
+        :param names: new names of the segments, by recorded name. The other channels keep their name
+        """
+        pass
+
+    def channels(self) -> list[tuple[str, str]]:
+        """
+        :return: the recorded channels, as (name, kind) pairs where kind is "message" or "queue"
+        """
+        pass
+
+    def __len__(self) -> int:
+        pass
+
+    def record(self, position: int) -> tuple[str, MessageInfo, bytes | list[bytes] | None]:
+        """
+        :return: the recorded name of the channel, the info of the message and its data:
+        bytes for a message, the list of elements for a queue, None when the channel was closed
+        :raises IndexError: if there is no record at this position
+        """
+        pass
+
+    def replay(self, speed: float | None = 1.0, start: float = 0.0) -> int:
+        """
+        Writes the records into the segments, blocks until done, releasing the GIL.
+        Queues wait for their readers
+        :param speed: 1.0 keeps the original pace, 2.0 replays twice as fast, None replays as fast as possible
+        :param start: seconds into the recording to start from
+        :return: number of records written
+        """
+        pass
+
+
+class Bridge:
+    """
+    Mirrors a SharedMessage or SharedQueue over a socket, for the processes that do not share /dev/shm with the writer.
+    The mirror is a local segment that keeps the versions of the original and is closed with it.
+    Addresses are "unix:<path>" for a UNIX socket, or "<host>:<port>" for TCP
+    """
+
+    @staticmethod
+    def send(name: str, address: str, queue: bool = False, listen: bool = False) -> 'Bridge':
+        """
+        Sends every version of the local channel to the socket, the bridge counts as a reader of the channel
+        :param name: name of the local SharedMessage, or SharedQueue if queue is set
+        :param listen: waits for a connection on the address instead of connecting to it
+        """
+        pass
+
+    @staticmethod
+    def receive(name: str, address: str, listen: bool = False) -> 'Bridge':
+        """
+        Creates the local mirror with the size and options of the remote channel,
+        and writes every version received from the socket. The mirror is removed when the bridge is dropped
+        :param name: name of the mirror, to open with SharedMessage.open or SharedQueue.open
+        :param listen: waits for a connection on the address instead of connecting to it
+        """
+        pass
+
+    def wait(self) -> None:
+        """
+        Blocks until the channel is closed, releasing the GIL
+        :raises OSError: if the connection was lost before the channel was closed
+        :raises ValueError: if the bridge was already stopped
+        """
+        pass
+
+    def stop(self) -> None:
+        """
+        Shuts down the connection. A stopped receiver closes its mirror
+        :raises ValueError: if the bridge was already stopped
+        """
+        pass
+
+
+class Subscription:
+    """
+    Returned by SharedMessage.subscribe and SharedQueue.subscribe.
+    Keeps running until unsubscribe() is called or the channel is closed, even if it is garbage collected
+    """
+
+    def unsubscribe(self) -> None:
+        """
+        Stops the background thread and waits for the callback in progress, releasing the GIL
+        Does nothing if already unsubscribed, and does not wait when called from the callback
+        """
+        pass
+
+    def is_active(self) -> bool:
+        """
+        :returns: false once unsubscribed, or once the channel is closed
+        """
+        pass
+
 
 class SharedQueue:
     @staticmethod
-    def create(name: str, max_element_size: int, mode: OpenMode) -> 'SharedQueue':
+    def create(name: str, max_element_size: int, mode: OpenMode, max_pending_writes: int | None = None,
+               backpressure: Backpressure = Backpressure.Block,
+               priority_inheritance: bool = False) -> 'SharedQueue':
+        """
+        :param max_pending_writes: how many elements can wait to be written by the feeder thread, unbounded if None
+        :param backpressure: what write does once max_pending_writes is reached
+        :param priority_inheritance: lock the segment with FUTEX_LOCK_PI, so that a lower priority process holding
+        the lock is boosted while a real-time process waits for it. Readers no longer copy in parallel
+        """
         pass
 
     @staticmethod
-    def open(name: str, mode: OpenMode) -> 'SharedQueue':
+    def open(name: str, mode: OpenMode, max_pending_writes: int | None = None,
+             backpressure: Backpressure = Backpressure.Block) -> 'SharedQueue':
+        """
+        :param max_pending_writes: how many elements can wait to be written by the feeder thread, unbounded if None
+        :param backpressure: what write does once max_pending_writes is reached
+        """
         pass
 
     def write(self, data: bytes):
         """
         Writes an element to the queue
         when called for the first time it creates a feeder thread that will write the data to the shared memory
-        This method will never block the current thread
+        This method only blocks if the backpressure policy is Block and max_pending_writes elements are pending
         
         :param data: element to add to queue
+        :raises queue.Full: if the backpressure policy is Raise and max_pending_writes elements are pending
+        """
+        pass
+
+    def write_many(self, data: list[bytes]):
+        """
+        Writes several elements to the queue with a single hand-off to the feeder thread,
+        which packs as many elements as fit into each shared memory write
+        This method only blocks if the backpressure policy is Block and max_pending_writes elements are pending
+
+        :param data: elements to add to queue, in order
+        :raises queue.Full: if the backpressure policy is Raise and not all the elements fit
+        """
+        pass
+
+    def pending_writes(self) -> int:
+        """
+        :returns: the number of elements waiting to be written by the feeder thread
         """
         pass
 
@@ -135,6 +543,38 @@ class SharedQueue:
         """
         pass
 
+    def peek(self) -> bytes | None:
+        """
+        Returns the element at the head of the queue without consuming it
+        :return: the next element that will be read, or None if no element is available
+        """
+        pass
+
+    def read_many(self, max_count: int, timeout: float | None = None) -> list[bytes]:
+        """
+        Waits for at least one element, then returns up to max_count elements without blocking again
+        This function also releases the GIL, while waiting
+        :param max_count: the maximum number of elements to return
+        :param timeout: seconds to wait for the first element, or None to wait indefinitely
+        :return: the elements read, empty if the timeout elapsed or the queue is closed
+        """
+        pass
+
+    def fileno(self) -> int:
+        """
+        Returns a file descriptor that becomes readable when elements are received, or when the queue is closed,
+        so that it can be registered with selectors, select.epoll or an event loop.
+        The file descriptor is owned by this instance and stays readable until clear is called
+        """
+        pass
+
+    def clear(self):
+        """
+        Makes the file descriptor returned by fileno not readable, until the next element is received.
+        Elements that were already received must still be read with try_read or read_many
+        """
+        pass
+
     def last_written_version(self) -> int:
         """
         :returns: the latest version that was written by this instance
@@ -159,6 +599,38 @@ class SharedQueue:
         """
         pass
 
+    def subscribe(self, callback: Callable[[bytes], Any], *, executor: Any = None) -> 'Subscription':
+        """
+        Calls the callback with every new element from a background thread, until unsubscribed or closed.
+        The elements are consumed from this handle, so it should not be read from elsewhere while subscribed
+        Exceptions raised by the callback are reported with sys.unraisablehook
+        :param executor: an object with a submit(fn, *args) method, such as a concurrent.futures.Executor,
+        to run the callback on instead of the background thread
+        """
+        pass
+
+    def stats(self) -> dict[str, int]:
+        """
+        Counters shared by every process using the queue, kept in the segment header:
+        writes, reads, bytes_written, bytes_read, blocked_writes, blocked_reads, lock_contentions
+        """
+        pass
+
+    def reset_stats(self) -> None:
+        """
+        Sets all the counters to 0, for every process
+        """
+        pass
+
+    def latency_stats(self) -> dict[str, dict[str, int]]:
+        """
+        Latencies of the reads made with this handle, in nanoseconds, as count, p50, p90, p99, p999 and max:
+        write_to_read from the write of a message to its read, lock_wait the time a read waited for locks.
+        Percentiles are within 12.5% of the actual value, `ripc latency <name>` aggregates all the readers.
+        Elements are received by a background thread, before being returned by a read
+        """
+        pass
+
     def is_closed(self) -> bool:
         """
         :returns: true the queue has been marked as closed
@@ -171,11 +643,674 @@ class SharedQueue:
         """
         pass
 
+    def release(self) -> None:
+        """
+        If this handle created the queue, closes it when the handle can write, and removes its name,
+        instead of waiting for the handle to be garbage collected.
+        Handles that only opened the queue leave it open for the other processes.
+        Waits for the background reader thread to stop, after which the handle no longer counts as a reader,
+        so it should not be used afterwards
+        """
+        pass
 
-def read_all(readers: list[SharedMessage]) -> list[bytes | None]:
-    """
-    Reads all the readers and returns a list of the messages
-    Each message is read concurrently
+    def __enter__(self) -> 'SharedQueue':
+        pass
+
+    def __exit__(self, *args) -> None:
+        """
+        Calls release()
+        """
+        pass
+
+    def __iter__(self) -> 'SharedQueue':
+        pass
+
+    def __next__(self) -> bytes:
+        """
+        Blocks until the next element, releasing the GIL. The iteration stops once the queue is closed and drained
+        """
+        pass
+
+    def __reduce__(self) -> tuple:
+        """
+        Pickled as its name, mode, max_pending_writes and backpressure, so that it can be passed
+        to a multiprocessing child, which opens the same queue again. It never unlinks the segment
+        """
+        pass
+
+
+class SharedPriorityQueue:
+    @staticmethod
+    def create(name: str, max_element_size: int, capacity: int, levels: int = 2,
+               mode: OpenMode = OpenMode.ReadWrite,
+               priority_inheritance: bool = False) -> 'SharedPriorityQueue':
+        """
+        :param name: is recommended to start with a '/'
+        :param max_element_size: cannot be 0
+        :param capacity: number of elements each priority level can hold, cannot be 0
+        :param levels: number of priority levels, between 1 and 8
+        :param mode:
+        :param priority_inheritance: lock the segment with FUTEX_LOCK_PI, so that a lower priority process holding
+        the lock is boosted while a real-time process waits for it
+        """
+        pass
+
+    @staticmethod
+    def open(name: str, mode: OpenMode = OpenMode.ReadWrite) -> 'SharedPriorityQueue':
+        pass
+
+    def write(self, data: bytes, priority: int = 0) -> bool:
+        """
+        Writes an element to the queue, blocks while the ring of that priority is full
+        This function also releases the GIL, while waiting
+        :param priority: between 0 and levels - 1, higher priorities are read first
+        :returns: false if the queue has been closed
+        """
+        pass
+
+    def try_write(self, data: bytes, priority: int = 0) -> bool:
+        """
+        :param priority: between 0 and levels - 1, higher priorities are read first
+        :returns: false if the ring of that priority is full or if the queue has been closed
+        """
+        pass
+
+    def try_read(self) -> bytes | None:
+        """
+        :return: the oldest element of the highest non-empty priority, or None if the queue is empty or closed
+        """
+        pass
+
+    def blocking_read(self) -> bytes | None:
+        """
+        Blocks the current thread until an element is available
+        This function also releases the GIL, while waiting
+        :return: the oldest element of the highest non-empty priority, or None if the queue is closed
+        """
+        pass
+
+    def __len__(self) -> int:
+        pass
+
+    def levels(self) -> int:
+        pass
+
+    def stats(self) -> dict[str, int]:
+        """
+        Counters shared by every process using the queue, kept in the segment header:
+        writes, reads, bytes_written, bytes_read, blocked_writes, blocked_reads, lock_contentions and max_queue_depth
+        """
+        pass
+
+    def reset_stats(self) -> None:
+        """
+        Sets all the counters to 0, for every process
+        """
+        pass
+
+    def name(self) -> str:
+        """
+        :returns: the name of this shared memory file
+        """
+        pass
+
+    def is_closed(self) -> bool:
+        pass
+
+    def close(self) -> None:
+        """
+        Signals to the readers and writers that they should stop using the queue
+        """
+        pass
+
+    def release(self) -> None:
+        """
+        If this handle created the queue, closes it when the handle can write, and removes its name,
+        instead of waiting for the handle to be garbage collected.
+        Handles that only opened the queue leave it open for the other processes
+        """
+        pass
+
+    def __enter__(self) -> 'SharedPriorityQueue':
+        pass
+
+    def __exit__(self, *args) -> None:
+        """
+        Calls release()
+        """
+        pass
+
+    def __iter__(self) -> 'SharedPriorityQueue':
+        pass
+
+    def __next__(self) -> bytes:
+        """
+        Blocks until the next element, releasing the GIL. The iteration stops once the queue is closed
+        """
+        pass
+
+    def __reduce__(self) -> tuple:
+        """
+        Pickled as its name and mode, so that it can be passed to a multiprocessing child,
+        which opens the same queue again. It never unlinks the segment
+        """
+        pass
+
+
+class SharedPipe:
+    """
+    Binary file-like object over a shared ring of bytes, unlike the other channels data is not split into messages
+    """
+    closed: bool
+
+    @staticmethod
+    def create(name: str, capacity: int, mode: OpenMode = OpenMode.ReadWrite) -> 'SharedPipe':
+        """
+        :param name: is recommended to start with a '/'
+        :param capacity: number of bytes that can be written before writers wait for a reader, cannot be 0
+        :param mode:
+        """
+        pass
+
+    @staticmethod
+    def open(name: str, mode: OpenMode = OpenMode.ReadWrite) -> 'SharedPipe':
+        pass
+
+    def read(self, size: int = -1) -> bytes:
+        """
+        Blocks until at least one byte is available, releasing the GIL
+        :param size: maximum number of bytes to return, at most the capacity of the pipe,
+        or read until the pipe is closed if negative
+        :returns: empty bytes once the pipe has been closed and all the data has been read
+        """
+        pass
+
+    def readall(self) -> bytes:
+        pass
+
+    def readinto(self, buffer: bytearray | memoryview) -> int:
+        """
+        Same as read, but into a writable contiguous buffer
+        :returns: the number of bytes read
+        """
+        pass
+
+    def write(self, data: bytes | bytearray | memoryview) -> int:
+        """
+        Blocks until all the data has been written, releasing the GIL
+        :raises BrokenPipeError: if the pipe has been closed
+        """
+        pass
+
+    def flush(self) -> None:
+        pass
+
+    def readable(self) -> bool:
+        pass
+
+    def writable(self) -> bool:
+        pass
+
+    def seekable(self) -> bool:
+        pass
+
+    def isatty(self) -> bool:
+        pass
+
+    def __len__(self) -> int:
+        """
+        :returns: the number of bytes waiting to be read
+        """
+        pass
+
+    def capacity(self) -> int:
+        pass
+
+    def stats(self) -> dict[str, int]:
+        """
+        Counters shared by every process using the pipe, kept in the segment header:
+        writes, reads, bytes_written, bytes_read, blocked_writes, blocked_reads, lock_contentions
+        """
+        pass
+
+    def reset_stats(self) -> None:
+        """
+        Sets all the counters to 0, for every process
+        """
+        pass
+
+    def name(self) -> str:
+        """
+        :returns: the name of this shared memory file
+        """
+        pass
+
+    def close(self) -> None:
+        """
+        Closes the pipe for every process, data already written can still be read.
+        Requires a handle that can write
+        """
+        pass
+
+    def __enter__(self) -> 'SharedPipe':
+        pass
+
+    def __exit__(self, *args) -> None:
+        """
+        Calls close() if this handle can write, a read-only handle leaves the pipe open for the other processes
+        """
+        pass
+
+    def __reduce__(self) -> tuple:
+        """
+        Pickled as its name and mode, so that it can be passed to a multiprocessing child,
+        which opens the same pipe again. It never unlinks the segment
+        """
+        pass
+
+
+class SharedService:
+    """
+    Server side of a request/reply channel, clients send requests into a shared ring
+    and wait for the reply in a slot of a second segment named '{name}.replies'
+    The service is closed when it is garbage collected
+    """
+
+    @staticmethod
+    def create(name: str, max_request_size: int, max_reply_size: int, capacity: int = 16,
+               max_clients: int = 16) -> 'SharedService':
+        """
+        :param name: is recommended to start with a '/'
+        :param max_request_size: cannot be 0
+        :param max_reply_size: cannot be 0
+        :param capacity: number of requests that can wait to be received
+        :param max_clients: number of clients that can be connected at the same time
+        """
+        pass
+
+    def receive(self, timeout: float | None = None) -> 'ServiceRequest | None':
+        """
+        Blocks until a request is available, releasing the GIL. Malformed requests are skipped
+        :param timeout: maximum number of seconds to wait, or wait forever if None
+        :returns: None if the timeout elapsed or if the service has been closed
+        """
+        pass
+
+    def name(self) -> str:
+        """
+        :returns: the name of this shared memory file
+        """
+        pass
+
+    def is_closed(self) -> bool:
+        pass
+
+    def stats(self) -> dict[str, int]:
+        """
+        Counters of the requests, shared by every process using the service and its clients, kept in the segment header:
+        writes, reads, bytes_written, bytes_read, blocked_writes, blocked_reads, lock_contentions and max_queue_depth
+        """
+        pass
+
+    def reset_stats(self) -> None:
+        """
+        Sets all the counters to 0, for every process
+        """
+        pass
+
+    def close(self) -> None:
+        """
+        Stops the service, clients waiting for a reply receive None
+        """
+        pass
+
+
+class ServiceRequest:
+    data: bytes
+    correlation_id: int
+
+    def reply(self, data: bytes) -> bool:
+        """
+        Can only be called once per request
+        :returns: false if the client stopped waiting for this reply, for example after a timeout
+        """
+        pass
+
+
+class SharedClient:
+    """
+    Client side of a request/reply channel, each client holds one of the reply slots of the service
+    """
+
+    @staticmethod
+    def open(name: str) -> 'SharedClient':
+        """
+        :raises RuntimeError: if max_clients clients are already connected
+        """
+        pass
+
+    def call(self, data: bytes, timeout: float | None = None) -> bytes | None:
+        """
+        Sends the request and blocks until the reply is received, releasing the GIL
+        Concurrent calls on the same client are serialized
+        :param timeout: maximum number of seconds to wait for room in the requests queue and for the reply,
+        or wait forever if None
+        :returns: the reply, or None if the service has been closed
+        :raises TimeoutError: if the timeout elapsed, a late reply is discarded
+        """
+        pass
+
+    def max_request_size(self) -> int:
+        pass
+
+    def name(self) -> str:
+        pass
+
+    def is_closed(self) -> bool:
+        pass
+
+    def stats(self) -> dict[str, int]:
+        """
+        Counters of the requests, shared by every process using the service and its clients, kept in the segment header:
+        writes, reads, bytes_written, bytes_read, blocked_writes, blocked_reads, lock_contentions and max_queue_depth
+        """
+        pass
+
+    def reset_stats(self) -> None:
+        """
+        Sets all the counters to 0, for every process
+        """
+        pass
+
+
+class Lock:
+    """
+    Same API as threading.Lock, shared between processes through the segment `name`
+    Unlike threading.RLock, it is not reentrant, and any process may release it
+    """
+
+    @staticmethod
+    def create(name: str) -> 'Lock':
+        pass
+
+    @staticmethod
+    def open(name: str) -> 'Lock':
+        pass
+
+    def __reduce__(self) -> tuple:
+        """
+        Pickled as its name, so that it can be passed to a multiprocessing child,
+        which opens the same lock again
+        """
+        pass
+
+    def acquire(self, blocking: bool = True, timeout: float = -1) -> bool:
+        """
+        Releases the GIL while waiting
+        :param timeout: maximum number of seconds to wait, or wait forever if -1
+        :returns: false if the lock could not be acquired
+        """
+        pass
+
+    def release(self) -> None:
+        """
+        :raises RuntimeError: if the lock is not locked
+        """
+        pass
+
+    def locked(self) -> bool:
+        pass
+
+    def name(self) -> str:
+        pass
+
+    def __enter__(self) -> bool:
+        pass
+
+    def __exit__(self, *args) -> None:
+        pass
+
+
+class Condition:
+    """
+    Same API as threading.Condition, with its own lock
+    """
+
+    @staticmethod
+    def create(name: str) -> 'Condition':
+        pass
+
+    @staticmethod
+    def open(name: str) -> 'Condition':
+        pass
+
+    def __reduce__(self) -> tuple:
+        """
+        Pickled as its name, so that it can be passed to a multiprocessing child,
+        which opens the same condition again
+        """
+        pass
+
+    def acquire(self, blocking: bool = True, timeout: float = -1) -> bool:
+        pass
+
+    def release(self) -> None:
+        pass
+
+    def wait(self, timeout: float | None = None) -> bool:
+        """
+        Releases the lock until notified, then acquires it again, releasing the GIL while waiting
+        :returns: false if the timeout elapsed before being notified
+        :raises RuntimeError: if the lock is not held
+        """
+        pass
+
+    def wait_for(self, predicate: Callable[[], Any], timeout: float | None = None) -> Any:
+        """
+        :returns: the last value returned by the predicate
+        """
+        pass
+
+    def notify(self, n: int = 1) -> None:
+        """
+        :raises RuntimeError: if the lock is not held
+        """
+        pass
+
+    def notify_all(self) -> None:
+        pass
+
+    def name(self) -> str:
+        pass
+
+    def __enter__(self) -> bool:
+        pass
+
+    def __exit__(self, *args) -> None:
+        pass
+
+
+class Semaphore:
+    """
+    Same API as threading.Semaphore
+    """
+
+    @staticmethod
+    def create(name: str, value: int = 1) -> 'Semaphore':
+        pass
+
+    @staticmethod
+    def open(name: str) -> 'Semaphore':
+        pass
+
+    def __reduce__(self) -> tuple:
+        """
+        Pickled as its name, so that it can be passed to a multiprocessing child,
+        which opens the same semaphore again
+        """
+        pass
+
+    def acquire(self, blocking: bool = True, timeout: float | None = None) -> bool:
+        pass
+
+    def release(self, n: int = 1) -> None:
+        pass
+
+    def name(self) -> str:
+        pass
+
+    def __enter__(self) -> bool:
+        pass
+
+    def __exit__(self, *args) -> None:
+        pass
+
+
+class Event:
+    """
+    Same API as threading.Event
+    """
+
+    @staticmethod
+    def create(name: str) -> 'Event':
+        pass
+
+    @staticmethod
+    def open(name: str) -> 'Event':
+        pass
+
+    def __reduce__(self) -> tuple:
+        """
+        Pickled as its name, so that it can be passed to a multiprocessing child,
+        which opens the same event again
+        """
+        pass
+
+    def is_set(self) -> bool:
+        pass
+
+    def set(self) -> None:
+        pass
+
+    def clear(self) -> None:
+        pass
+
+    def wait(self, timeout: float | None = None) -> bool:
+        """
+        Releases the GIL while waiting
+        :returns: false if the timeout elapsed before the flag was set
+        """
+        pass
+
+    def name(self) -> str:
+        pass
+
+
+class Barrier:
+    """
+    Same API as threading.Barrier, without the action callback
+    """
+    parties: int
+    n_waiting: int
+    broken: bool
+
+    @staticmethod
+    def create(name: str, parties: int) -> 'Barrier':
+        pass
+
+    @staticmethod
+    def open(name: str) -> 'Barrier':
+        pass
+
+    def __reduce__(self) -> tuple:
+        """
+        Pickled as its name, so that it can be passed to a multiprocessing child,
+        which opens the same barrier again
+        """
+        pass
+
+    def wait(self, timeout: float | None = None) -> int:
+        """
+        Releases the GIL while waiting
+        :returns: the arrival index, between 0 and parties - 1
+        :raises threading.BrokenBarrierError: if the barrier is broken, reset or the timeout elapsed
+        """
+        pass
+
+    def reset(self) -> None:
+        pass
+
+    def abort(self) -> None:
+        pass
+
+    def name(self) -> str:
+        pass
+
+
+class SharedArray:
+    """
+    A SharedMessage that carries numpy arrays, along with their dtype, shape and strides
+    """
+
+    @staticmethod
+    def create(name: str, size: int, mode: OpenMode = OpenMode.ReadWrite) -> 'SharedArray':
+        """
+        :param name: is recommended to start with a '/'
+        :param size: the maximum number of bytes of the arrays that will be written, cannot be 0
+        :param mode:
+        """
+        pass
+
+    @staticmethod
+    def open(name: str, mode: OpenMode = OpenMode.ReadWrite) -> 'SharedArray':
+        pass
+
+    def write(self, array: 'numpy.ndarray') -> None:
+        """
+        Writes the array, non C-contiguous arrays are copied first
+        Arrays with an object dtype are not supported
+        """
+        pass
+
+    def try_read(self) -> 'numpy.ndarray | None':
+        """
+        :returns: a read-only array viewing the received payload, or None if it's the same version as the last time
+        or if the shared memory is closed
+        """
+        pass
+
+    def blocking_read(self) -> 'numpy.ndarray | None':
+        """
+        Keeps checking the shared memory until there is a new version to read
+        This function also releases the GIL, while waiting for a new array
+        :returns: a read-only array viewing the received payload, or None if the shared memory is closed
+        """
+        pass
+
+    def is_new_version_available(self) -> bool:
+        pass
+
+    def name(self) -> str:
+        pass
+
+    def is_closed(self) -> bool:
+        pass
+
+    def close(self) -> None:
+        pass
+
+    def __reduce__(self) -> tuple:
+        """
+        Pickled as its name and mode, so that it can be passed to a multiprocessing child,
+        which opens the same array again. It never unlinks the segment
+        """
+        pass
+
+
+def read_all(readers: list[SharedMessage]) -> list[bytes | None]:
+    """
+    Reads all the readers and returns a list of the messages
+    Each message is read concurrently
     :param readers: list of readers
     :return: list of messages
     """
@@ -191,3 +1326,14 @@ def read_all_map(readers: list[SharedMessage], map_operation: Callable[[bytes],
     :return: list of mapped messages
     """
     return [map_operation(reader.try_read()) for reader in readers]
+
+
+def cleanup(prefix: str | None = None, dry_run: bool = False) -> list[str]:
+    """
+    Removes the shared memory segments left behind by crashed processes:
+    the segments whose creator and all the processes that opened them are dead
+    :param prefix: only consider the segments whose name starts with this prefix
+    :param dry_run: only report the stale segments, without removing them
+    :return: names of the removed segments
+    """
+    pass
@@ -0,0 +1,162 @@
+use ripc::inspect::{self, MessageInfo};
+use std::io::Write;
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Inspects and manages the shared memory segments created by ripc
+
+Usage: ripc <command> [arguments]
+
+Commands:
+  list                 List the segments, their kind and size
+  show <name>          Show the state of a segment: version, closed flag, readers, lock state
+  unlink <name>        Remove the name of a segment, processes that have it open can keep using it
+  close <name>         Close a channel, waking up every process waiting on it
+  tail <name>          Print every new version of a message, until it is closed
+  dump <name> <file>   Write the payload of the latest message to a file
+  latency <name>       Show the latency percentiles measured by all the readers of a message or queue
+  cleanup [--dry-run] [prefix]
+                       Remove the segments whose creator and users are all dead";
+
+fn list() -> std::io::Result<()> {
+    let segments = inspect::list_segments()?;
+    let name_width = segments
+        .iter()
+        .map(|segment| segment.name.len())
+        .max()
+        .unwrap_or(0)
+        .max("NAME".len());
+
+    println!("{:name_width$}  {:14}  SIZE", "NAME", "KIND");
+    for segment in segments {
+        println!(
+            "{:name_width$}  {:14}  {}",
+            segment.name,
+            segment.kind.name(),
+            segment.size
+        );
+    }
+    Ok(())
+}
+
+fn show(name: &str) -> std::io::Result<()> {
+    let fields = inspect::describe(name)?;
+    let key_width = fields.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+
+    println!("{name}");
+    for (key, value) in fields {
+        println!("  {key:key_width$}  {value}");
+    }
+    Ok(())
+}
+
+fn print_message(info: &MessageInfo, data: &[u8]) {
+    let mut stdout = std::io::stdout().lock();
+    let _ = writeln!(
+        stdout,
+        "version {} from pid {} tid {}, {} bytes: {}",
+        info.version,
+        info.writer_pid,
+        info.writer_tid,
+        data.len(),
+        data.escape_ascii()
+    );
+    let _ = stdout.flush();
+}
+
+fn dump(name: &str, path: &str) -> std::io::Result<()> {
+    match inspect::dump(name)? {
+        Some((info, data)) => {
+            std::fs::write(path, &data)?;
+            println!(
+                "Wrote {} bytes of version {} to {path}",
+                data.len(),
+                info.version
+            );
+        }
+        None => println!("Nothing was written to {name} yet"),
+    }
+    Ok(())
+}
+
+fn format_ns(ns: u64) -> String {
+    match ns {
+        0..1_000 => format!("{ns}ns"),
+        1_000..1_000_000 => format!("{:.1}us", ns as f64 / 1e3),
+        1_000_000..1_000_000_000 => format!("{:.1}ms", ns as f64 / 1e6),
+        _ => format!("{:.1}s", ns as f64 / 1e9),
+    }
+}
+
+fn latency(name: &str) -> std::io::Result<()> {
+    let summaries = inspect::latency(name)?;
+
+    println!("{name}, all readers");
+    println!(
+        "  {:13}  {:>10}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}",
+        "", "COUNT", "P50", "P90", "P99", "P99.9", "MAX"
+    );
+    for (key, summary) in summaries {
+        println!(
+            "  {key:13}  {:>10}  {:>8}  {:>8}  {:>8}  {:>8}  {:>8}",
+            summary.count,
+            format_ns(summary.p50),
+            format_ns(summary.p90),
+            format_ns(summary.p99),
+            format_ns(summary.p999),
+            format_ns(summary.max)
+        );
+    }
+    Ok(())
+}
+
+fn cleanup(prefix: Option<&str>, dry_run: bool) -> std::io::Result<()> {
+    for name in inspect::cleanup(prefix, dry_run)? {
+        match dry_run {
+            true => println!("Would remove {name}"),
+            false => println!("Removed {name}"),
+        }
+    }
+    Ok(())
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+
+    let result = match args.as_slice() {
+        ["list"] => list(),
+        ["show", name] => show(name),
+        ["unlink", name] => inspect::unlink(name),
+        ["close", name] => inspect::close(name),
+        ["tail", name] => inspect::tail(name, print_message),
+        ["dump", name, path] => dump(name, path),
+        ["latency", name] => latency(name),
+        ["cleanup"] => cleanup(None, false),
+        ["cleanup", "--dry-run"] => cleanup(None, true),
+        ["cleanup", "--dry-run", prefix] => cleanup(Some(prefix), true),
+        ["cleanup", prefix] => cleanup(Some(prefix), false),
+        ["help" | "-h" | "--help"] => {
+            println!("{USAGE}");
+            Ok(())
+        }
+        _ => return Err(USAGE.to_string()),
+    };
+
+    result.map_err(|e| match args.as_slice() {
+        ["cleanup", ..] => e.to_string(),
+        [_, name, ..] => format!("{name}: {e}"),
+        _ => e.to_string(),
+    })
+}
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("ripc: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
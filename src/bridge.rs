@@ -0,0 +1,459 @@
+//! Mirrors a `SharedMessage` or `SharedQueue` to a socket, and recreates it as a local segment on the other side,
+//! for the processes that do not share /dev/shm with the writer.
+//! The mirror keeps the versions of the original and is closed with it,
+//! the info of its messages describes the bridge that wrote them.
+//!
+//! The sender starts with a handshake describing the channel, then sends a packet for every version
+//! it reads and a last one when the channel is closed. All the integers are little endian.
+
+use crate::container::message::{MessageOptions, SharedMessage};
+use crate::primitives::memory_holder::SharedMemoryHolder;
+use std::ffi::CString;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+pub use crate::helpers::record_log::ChannelKind;
+
+const BRIDGE_MAGIC: [u8; 8] = *b"RIPCBRG\0";
+const PROTOCOL_VERSION: u32 = 1;
+
+/// How often the sending thread checks if the bridge was stopped
+const SEND_TIMEOUT: Duration = Duration::from_millis(100);
+
+// 64 - magic
+// 32 - protocol version
+// 8 - kind
+// 8 - checksum
+// 16 - padding
+// 64 - max payload size
+const HANDSHAKE_SIZE: usize = 24;
+
+// 32 - packet type
+// 32 - payload size
+// 64 - version
+// N - payload
+const PACKET_HEADER_SIZE: usize = 16;
+
+const PACKET_MESSAGE: u32 = 0;
+const PACKET_CLOSE: u32 = 1;
+
+/// A connected socket, UNIX or TCP
+pub enum BridgeStream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl BridgeStream {
+    /// Connects to `unix:<path>` for a UNIX socket, otherwise to `<host>:<port>` over TCP
+    pub fn connect(address: &str) -> std::io::Result<Self> {
+        match address.strip_prefix("unix:") {
+            Some(path) => Ok(Self::Unix(UnixStream::connect(path)?)),
+            None => Self::tcp(TcpStream::connect(address)?),
+        }
+    }
+
+    /// Listens on the address, see [`Self::connect`], and accepts a single connection.
+    /// The file of a UNIX socket is removed once the connection is accepted
+    pub fn accept(address: &str) -> std::io::Result<Self> {
+        match address.strip_prefix("unix:") {
+            Some(path) => {
+                let stream = UnixListener::bind(path)?.accept();
+                // Nobody else can connect, the listener is closed
+                let _ = std::fs::remove_file(path);
+                Ok(Self::Unix(stream?.0))
+            }
+            None => Self::tcp(TcpListener::bind(address)?.accept()?.0),
+        }
+    }
+
+    /// Every message is sent as soon as it is written
+    fn tcp(stream: TcpStream) -> std::io::Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Self::Tcp(stream))
+    }
+
+    fn try_clone(&self) -> std::io::Result<Self> {
+        Ok(match self {
+            Self::Tcp(stream) => Self::Tcp(stream.try_clone()?),
+            Self::Unix(stream) => Self::Unix(stream.try_clone()?),
+        })
+    }
+
+    /// Wakes up the thread blocked on the other handle of the socket
+    fn shutdown(&self) {
+        let _ = match self {
+            Self::Tcp(stream) => stream.shutdown(Shutdown::Both),
+            Self::Unix(stream) => stream.shutdown(Shutdown::Both),
+        };
+    }
+}
+
+impl Read for BridgeStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.read(buf),
+            Self::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for BridgeStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.write(buf),
+            Self::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.flush(),
+            Self::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+fn write_packet(
+    stream: &mut BridgeStream,
+    packet_type: u32,
+    version: usize,
+    payload: &[u8],
+) -> std::io::Result<()> {
+    let mut header = [0u8; PACKET_HEADER_SIZE];
+    header[0..4].copy_from_slice(&packet_type.to_le_bytes());
+    header[4..8].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+    header[8..16].copy_from_slice(&(version as u64).to_le_bytes());
+
+    stream.write_all(&header)?;
+    stream.write_all(payload)
+}
+
+/// Sends or receives a channel until it is closed or the bridge is stopped
+pub struct Bridge {
+    stream: BridgeStream,
+    stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<std::io::Result<()>>>,
+    /// The mirror, which is unlinked when the bridge is dropped
+    _segment: Option<Arc<SharedMemoryHolder<SharedMessage>>>,
+}
+
+impl Bridge {
+    /// Sends every version of the local channel to the stream. The bridge counts as a reader of the channel
+    pub fn send(name: &str, kind: ChannelKind, mut stream: BridgeStream) -> std::io::Result<Self> {
+        let segment = unsafe { SharedMemoryHolder::<SharedMessage>::open(CString::new(name)?)? };
+        let max_size = segment.mapped_memory_size() - SharedMessage::size_of_fields();
+
+        let mut handshake = [0u8; HANDSHAKE_SIZE];
+        handshake[0..8].copy_from_slice(&BRIDGE_MAGIC);
+        handshake[8..12].copy_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+        handshake[12] = kind as u8;
+        handshake[13] = segment.has_checksum() as u8;
+        handshake[16..24].copy_from_slice(&(max_size as u64).to_le_bytes());
+        stream.write_all(&handshake)?;
+
+        // Registered before returning, so that no version written after `send` is missed
+        segment.add_reader();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stopped = stopped.clone();
+            let stream = stream.try_clone()?;
+            std::thread::spawn(move || Self::send_loop(segment, &stopped, stream))
+        };
+
+        Ok(Self {
+            stream,
+            stopped,
+            thread: Some(thread),
+            _segment: None,
+        })
+    }
+
+    fn send_loop(
+        segment: SharedMemoryHolder<SharedMessage>,
+        stopped: &AtomicBool,
+        mut stream: BridgeStream,
+    ) -> std::io::Result<()> {
+        let mut version = 0;
+        let mut payload = Vec::new();
+
+        let result = loop {
+            if stopped.load(Ordering::Relaxed) {
+                break Ok(());
+            }
+
+            let new_version = segment.wait_for_new_version(version, SEND_TIMEOUT);
+            if segment.is_closed() {
+                break write_packet(&mut stream, PACKET_CLOSE, version, &[]);
+            }
+            if new_version == version {
+                continue;
+            }
+
+            let mut read = false;
+            // Copied out, so that the lock is not held while the socket is blocked
+            let result = segment.try_read(version, |info, data| {
+                version = info.version;
+                payload.clear();
+                payload.extend_from_slice(data);
+                read = true;
+            });
+            if let Err(e) = result {
+                segment.stats().add_corrupt_read();
+                version = e.version;
+            }
+
+            if read {
+                if let Err(e) = write_packet(&mut stream, PACKET_MESSAGE, version, &payload) {
+                    break Err(e);
+                }
+            }
+        };
+
+        segment.remove_reader();
+        result
+    }
+
+    /// Creates the local channel `name` with the size and options of the remote one,
+    /// and writes every version received from the stream. Blocks until the handshake is received
+    pub fn receive(name: &str, mut stream: BridgeStream) -> std::io::Result<Self> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message);
+
+        let mut handshake = [0u8; HANDSHAKE_SIZE];
+        stream.read_exact(&mut handshake)?;
+        if handshake[0..8] != BRIDGE_MAGIC {
+            return Err(invalid("The peer is not a ripc bridge"));
+        }
+        if u32::from_le_bytes(handshake[8..12].try_into().unwrap()) != PROTOCOL_VERSION {
+            return Err(invalid("The peer uses an incompatible version of ripc"));
+        }
+        let kind = match handshake[12] {
+            0 => ChannelKind::Message,
+            1 => ChannelKind::Queue,
+            _ => return Err(invalid("Unknown channel kind")),
+        };
+        // Sent by the peer, and the size of a packet is only 32 bits anyway
+        let max_size = u64::from_le_bytes(handshake[16..24].try_into().unwrap());
+        let max_size = u32::try_from(max_size)
+            .map_err(|_| invalid("The channel is too large to be bridged"))?
+            as usize;
+
+        let segment = unsafe {
+            Arc::new(SharedMemoryHolder::<SharedMessage>::create(
+                CString::new(name)?,
+                SharedMessage::size_of_fields() + max_size,
+            )?)
+        };
+        segment.init(MessageOptions {
+            checksum: handshake[13] != 0,
+            ..MessageOptions::default()
+        });
+
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let segment = segment.clone();
+            let stopped = stopped.clone();
+            let stream = stream.try_clone()?;
+            std::thread::spawn(move || {
+                let result = Self::receive_loop(&segment, kind, max_size, stream);
+                // Nothing will be written anymore, wake up the readers
+                segment.close();
+                match stopped.load(Ordering::Relaxed) {
+                    true => Ok(()),
+                    false => result,
+                }
+            })
+        };
+
+        Ok(Self {
+            stream,
+            stopped,
+            thread: Some(thread),
+            _segment: Some(segment),
+        })
+    }
+
+    fn receive_loop(
+        segment: &SharedMemoryHolder<SharedMessage>,
+        kind: ChannelKind,
+        max_size: usize,
+        mut stream: BridgeStream,
+    ) -> std::io::Result<()> {
+        let mut header = [0u8; PACKET_HEADER_SIZE];
+        let mut payload = vec![0u8; max_size];
+
+        loop {
+            stream.read_exact(&mut header).map_err(|e| match e.kind() {
+                ErrorKind::UnexpectedEof => Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "The connection was lost before the channel was closed",
+                ),
+                _ => e,
+            })?;
+            let packet_type = u32::from_le_bytes(header[0..4].try_into().unwrap());
+            let size = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+            let version = u64::from_le_bytes(header[8..16].try_into().unwrap()) as usize;
+
+            if packet_type == PACKET_CLOSE {
+                return Ok(());
+            }
+            if packet_type != PACKET_MESSAGE || size > max_size {
+                return Err(Error::new(ErrorKind::InvalidData, "Invalid packet"));
+            }
+            stream.read_exact(&mut payload[..size])?;
+
+            // Closed by a local process, the sender notices when the connection is shut down
+            if segment.is_closed() {
+                stream.shutdown();
+                return Ok(());
+            }
+            // Like the writers of the original, queues wait for their readers and messages do not
+            segment.write_version(&payload[..size], version, kind == ChannelKind::Queue);
+        }
+    }
+
+    fn join(&mut self) -> std::io::Result<()> {
+        match self.thread.take() {
+            Some(thread) => thread.join().expect("The bridge thread panicked"),
+            None => Ok(()),
+        }
+    }
+
+    /// Waits until the channel is closed, or the connection is lost
+    pub fn wait(mut self) -> std::io::Result<()> {
+        self.join()
+    }
+
+    pub fn stop(mut self) -> std::io::Result<()> {
+        self.stopped.store(true, Ordering::Relaxed);
+        self.stream.shutdown();
+        self.join()
+    }
+}
+
+impl Drop for Bridge {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        self.stream.shutdown();
+        let _ = self.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::frames::read_frames;
+
+    fn create(name: &str, size: usize) -> SharedMemoryHolder<SharedMessage> {
+        let segment = unsafe {
+            SharedMemoryHolder::<SharedMessage>::create(
+                CString::new(name).unwrap(),
+                SharedMessage::size_of_fields() + size,
+            )
+            .unwrap()
+        };
+        segment.init(MessageOptions::default());
+        segment
+    }
+
+    fn open(name: &str) -> SharedMemoryHolder<SharedMessage> {
+        unsafe { SharedMemoryHolder::open(CString::new(name).unwrap()).unwrap() }
+    }
+
+    #[test]
+    fn reject_oversized_handshake() {
+        let (mut a, b) = UnixStream::pair().unwrap();
+        let mut handshake = [0u8; HANDSHAKE_SIZE];
+        handshake[0..8].copy_from_slice(&BRIDGE_MAGIC);
+        handshake[8..12].copy_from_slice(&PROTOCOL_VERSION.to_le_bytes());
+        handshake[16..24].copy_from_slice(&u64::MAX.to_le_bytes());
+        a.write_all(&handshake).unwrap();
+
+        let error = Bridge::receive("/bridge_oversized_mirror", BridgeStream::Unix(b))
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        let mirror = CString::new("/bridge_oversized_mirror").unwrap();
+        assert!(unsafe { SharedMemoryHolder::<SharedMessage>::open(mirror) }.is_err());
+    }
+
+    #[test]
+    fn bridge_message_over_unix_socket() {
+        let source = create("/bridge_message_source", 16);
+        for i in 1..=3u8 {
+            source.write(&[i]);
+        }
+
+        let (a, b) = UnixStream::pair().unwrap();
+        let sender = Bridge::send(
+            "/bridge_message_source",
+            ChannelKind::Message,
+            BridgeStream::Unix(a),
+        )
+        .unwrap();
+        let receiver = Bridge::receive("/bridge_message_mirror", BridgeStream::Unix(b)).unwrap();
+
+        let mirror = open("/bridge_message_mirror");
+        assert_eq!(mirror.mapped_memory_size(), source.mapped_memory_size());
+        let mut received = (0, Vec::new());
+        mirror
+            .blocking_read(0, |info, data| received = (info.version, data.to_vec()))
+            .unwrap();
+        // The latest version, with its number
+        assert_eq!(received, (3, vec![3]));
+
+        source.close();
+        sender.wait().unwrap();
+        receiver.wait().unwrap();
+        assert!(mirror.is_closed());
+    }
+
+    #[test]
+    fn bridge_queue_over_tcp() {
+        let source = create("/bridge_queue_source", 32);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let sender = Bridge::send(
+            "/bridge_queue_source",
+            ChannelKind::Queue,
+            BridgeStream::connect(&address).unwrap(),
+        )
+        .unwrap();
+        let receiver = Bridge::receive(
+            "/bridge_queue_mirror",
+            BridgeStream::Tcp(listener.accept().unwrap().0),
+        )
+        .unwrap();
+        let mirror = open("/bridge_queue_mirror");
+        mirror.add_reader();
+
+        for i in 0..3u8 {
+            source.write_frames_waiting_for_readers([[i].as_slice(), &[i, i]], None);
+        }
+
+        let mut version = 0;
+        let mut elements = Vec::new();
+        while version != 3 {
+            mirror
+                .blocking_read(version, |info, data| {
+                    version = info.version;
+                    elements.extend(read_frames(data).map(<[u8]>::to_vec));
+                })
+                .unwrap();
+        }
+        assert_eq!(elements.len(), 6);
+        assert_eq!(elements[4..], [vec![2], vec![2, 2]]);
+
+        receiver.stop().unwrap();
+        assert!(mirror.is_closed());
+        mirror.remove_reader();
+        // The connection is shut down, sending the close packet may fail
+        source.close();
+        let _ = sender.wait();
+    }
+}
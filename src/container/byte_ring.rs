@@ -0,0 +1,233 @@
+use crate::primitives::condvar::SharedCondvar;
+use crate::primitives::memory_holder::{SegmentFields, SegmentKind, SlicePtrCast};
+use crate::primitives::mutex::SharedMutex;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Unframed ring of bytes, data written in multiple calls can be read back in a single one and vice versa
+#[repr(C)]
+pub(crate) struct ByteRing<T: ?Sized = ByteRingContent> {
+    wait_for_read: SharedCondvar,
+    wait_for_write: SharedCondvar,
+    closed: AtomicBool,
+    content: SharedMutex<T>,
+}
+
+impl ByteRing {
+    pub(crate) fn init(&self, capacity: usize) {
+        let mut content = self.content.lock();
+
+        content.capacity = capacity as u64;
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        let content = self.content.lock();
+
+        content.len()
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        let content = self.content.lock();
+
+        content.capacity as usize
+    }
+
+    /// Writes all the data, waiting for readers to make room as needed,
+    /// returns the number of bytes written, which is less than requested only if the ring was closed
+    pub(crate) fn write(&self, mut data: &[u8]) -> usize {
+        let mut written = 0;
+        let mut content = self.content.lock();
+
+        while !data.is_empty() {
+            content = self.wait_for_read.wait_while(content, |guard| {
+                guard.free_space() == 0 && !self.is_closed()
+            });
+            if self.is_closed() {
+                break;
+            }
+
+            let count = content.write(data);
+            data = &data[count..];
+            written += count;
+            self.wait_for_write.notify_all();
+        }
+
+        written
+    }
+
+    /// Waits until at least one byte is available, then reads as many bytes as fit in `read_into`.
+    /// Data written before the ring was closed can still be read,
+    /// returns 0 only once the ring has been closed and drained, or if `read_into` is empty
+    pub(crate) fn read(&self, read_into: &mut [u8]) -> usize {
+        if read_into.is_empty() {
+            return 0;
+        }
+
+        let mut content = self.content.lock();
+        content = self
+            .wait_for_write
+            .wait_while(content, |guard| guard.len() == 0 && !self.is_closed());
+
+        let count = content.read(read_into);
+        if count != 0 {
+            self.wait_for_read.notify_all();
+        }
+        count
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    /// Reads the state without taking the lock, so that it also works while the lock is stuck
+    pub(crate) fn describe(&self) -> SegmentFields {
+        let content = unsafe { self.content.get_unlocked() };
+        let len = content.write_position.saturating_sub(content.read_position);
+
+        vec![
+            ("closed", self.is_closed().to_string()),
+            ("length", len.to_string()),
+            ("capacity", content.capacity.to_string()),
+            ("bytes written", content.write_position.to_string()),
+            ("lock", self.content.describe_lock()),
+        ]
+    }
+
+    pub(crate) fn close(&self) {
+        let _ = self.content.lock();
+        self.closed.store(true, Ordering::Relaxed);
+        self.wait_for_write.notify_all();
+        self.wait_for_read.notify_all();
+    }
+
+    pub(crate) fn compute_size_for(capacity: usize) -> usize {
+        ByteRing::size_of_fields() + capacity
+    }
+
+    const fn size_of_fields() -> usize {
+        #[repr(C)]
+        struct ByteRingContentSized {
+            read_position: u64,
+            write_position: u64,
+            capacity: u64,
+        }
+        size_of::<ByteRing<ByteRingContentSized>>()
+    }
+}
+
+impl SlicePtrCast for ByteRing {
+    const KIND: SegmentKind = SegmentKind::ByteRing;
+
+    fn cast_from_slice_ptr(slice_ptr: *mut [u8]) -> *const Self {
+        slice_ptr as *const Self
+    }
+}
+
+#[repr(C)]
+pub(crate) struct ByteRingContent {
+    /// Both positions only ever increase, the index in the buffer is the position modulo the capacity
+    read_position: u64,
+    write_position: u64,
+    capacity: u64,
+    buffer: [u8],
+}
+
+impl ByteRingContent {
+    fn len(&self) -> usize {
+        (self.write_position - self.read_position) as usize
+    }
+
+    fn free_space(&self) -> usize {
+        self.capacity as usize - self.len()
+    }
+
+    /// Copies as much of `data` as fits, returns the number of bytes copied
+    fn write(&mut self, data: &[u8]) -> usize {
+        let count = data.len().min(self.free_space());
+        let start = (self.write_position % self.capacity) as usize;
+        let first = count.min(self.capacity as usize - start);
+
+        self.buffer[start..start + first].copy_from_slice(&data[..first]);
+        self.buffer[..count - first].copy_from_slice(&data[first..count]);
+
+        self.write_position += count as u64;
+        count
+    }
+
+    /// Copies as many bytes as available into `read_into`, returns the number of bytes copied
+    fn read(&mut self, read_into: &mut [u8]) -> usize {
+        let count = read_into.len().min(self.len());
+        let start = (self.read_position % self.capacity) as usize;
+        let first = count.min(self.capacity as usize - start);
+
+        read_into[..first].copy_from_slice(&self.buffer[start..start + first]);
+        read_into[first..count].copy_from_slice(&self.buffer[..count - first]);
+
+        self.read_position += count as u64;
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn ring(capacity: usize) -> &'static ByteRing {
+        let memory = vec![0u8; ByteRing::compute_size_for(capacity)].leak();
+        let ring = unsafe { &*ByteRing::cast_from_slice_ptr(memory) };
+        ring.init(capacity);
+        ring
+    }
+
+    #[test]
+    fn wrap_around() {
+        let ring = ring(8);
+        let mut buffer = [0u8; 8];
+
+        assert_eq!(ring.write(&[1, 2, 3, 4, 5]), 5);
+        assert_eq!(ring.read(&mut buffer[..3]), 3);
+        assert_eq!(buffer[..3], [1, 2, 3]);
+
+        // Continues past the end of the buffer
+        assert_eq!(ring.write(&[6, 7, 8, 9, 10, 11]), 6);
+        assert_eq!(ring.len(), 8);
+        assert_eq!(ring.read(&mut buffer), 8);
+        assert_eq!(buffer, [4, 5, 6, 7, 8, 9, 10, 11]);
+        assert_eq!(ring.len(), 0);
+    }
+
+    #[test]
+    fn close_drains_remaining_data() {
+        let ring = ring(4);
+        let mut buffer = [0u8; 4];
+
+        ring.write(&[1, 2]);
+        ring.close();
+        assert_eq!(ring.write(&[3]), 0);
+        assert_eq!(ring.read(&mut buffer), 2);
+        assert_eq!(ring.read(&mut buffer), 0);
+    }
+
+    #[test]
+    fn blocking_write_larger_than_capacity() {
+        let ring = ring(16);
+        let data = (0..=255u8).cycle().take(10_000).collect::<Vec<_>>();
+
+        let reader = thread::spawn(move || {
+            let mut received = Vec::new();
+            let mut buffer = [0u8; 7];
+            loop {
+                let count = ring.read(&mut buffer);
+                if count == 0 {
+                    break received;
+                }
+                received.extend_from_slice(&buffer[..count]);
+            }
+        });
+
+        assert_eq!(ring.write(&data), data.len());
+        ring.close();
+        assert_eq!(reader.join().unwrap(), data);
+    }
+}
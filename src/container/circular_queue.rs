@@ -1,7 +1,10 @@
+use crate::helpers::trace::{trace_event, trace_span};
 use crate::primitives::condvar::SharedCondvar;
+use crate::primitives::memory_holder::{SegmentFields, SegmentKind, SlicePtrCast};
 use crate::primitives::mutex::SharedMutex;
 use std::mem::size_of;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
 #[repr(C)]
 pub(crate) struct CircularQueue<T: ?Sized = CircularQueueContent> {
@@ -11,12 +14,29 @@ pub(crate) struct CircularQueue<T: ?Sized = CircularQueueContent> {
     content: SharedMutex<T>,
 }
 
+/// What a write does when the queue is already full
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum OverflowPolicy {
+    /// `try_write` fails and `blocking_write` waits for a reader to make room
+    Block,
+    /// The oldest element is dropped to make room for the new one
+    OverwriteOldest,
+}
+
+/// Why a blocking write did not write its value
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum WriteError {
+    TimedOut,
+    Closed,
+}
+
 impl CircularQueue {
-    pub(crate) fn init(&self, max_element_size: usize, capacity: usize) {
+    pub(crate) fn init(&self, max_element_size: usize, capacity: usize, policy: OverflowPolicy) {
         let mut content = self.content.lock();
 
         content.max_element_size = max_element_size as u32;
         content.capacity = capacity as u32;
+        content.overwrite_oldest = policy == OverflowPolicy::OverwriteOldest;
     }
 
     pub(crate) fn len(&self) -> usize {
@@ -32,10 +52,17 @@ impl CircularQueue {
     }
 
     pub(crate) fn try_write(&self, value: &[u8]) -> bool {
+        trace_span!("queue_write");
         let mut content = self.content.lock();
-        if content.full || self.is_closed() {
+        if self.is_closed() {
             return false;
         }
+        if content.full {
+            if !content.overwrite_oldest {
+                return false;
+            }
+            content.drop_oldest();
+        }
 
         content.write(value);
         self.wait_for_write.notify_one();
@@ -44,22 +71,82 @@ impl CircularQueue {
     }
 
     pub(crate) fn blocking_write(&self, value: &[u8]) -> bool {
+        self.blocking_write_timeout(value, None).is_ok()
+    }
+
+    /// Waits at most `timeout` for a reader to make room, or forever if None
+    pub(crate) fn blocking_write_timeout(
+        &self,
+        value: &[u8],
+        timeout: Option<Duration>,
+    ) -> Result<(), WriteError> {
+        trace_span!("queue_write", blocking = true);
         let mut content = self.content.lock();
-        if content.full {
-            content = self
-                .wait_for_read
-                .wait_while(content, |guard| guard.full && !self.is_closed());
+        if content.full && !content.overwrite_oldest {
+            let condition = |guard: &CircularQueueContent| guard.full && !self.is_closed();
+            content = match timeout {
+                Some(timeout) => {
+                    self.wait_for_read
+                        .wait_while_timeout(content, timeout, condition)
+                        .0
+                }
+                None => self.wait_for_read.wait_while(content, condition),
+            };
         }
         if self.is_closed() {
-            return false;
+            return Err(WriteError::Closed);
+        }
+        if content.full {
+            if !content.overwrite_oldest {
+                return Err(WriteError::TimedOut);
+            }
+            content.drop_oldest();
         }
 
         content.write(value);
         self.wait_for_write.notify_one();
-        true
+        Ok(())
+    }
+
+    /// Writes all the values, only waiting for readers when the queue fills up,
+    /// returns the number of values written, which is less than requested only if the queue was closed
+    pub(crate) fn write_many<'a>(&self, values: impl IntoIterator<Item = &'a [u8]>) -> usize {
+        trace_span!("queue_write_many");
+        let mut values = values.into_iter().peekable();
+        let mut written = 0;
+        let mut unnotified = 0;
+        let mut content = self.content.lock();
+
+        while values.peek().is_some() {
+            if content.full && !content.overwrite_oldest {
+                if unnotified != 0 {
+                    self.wait_for_write.notify_all();
+                    unnotified = 0;
+                }
+                content = self
+                    .wait_for_read
+                    .wait_while(content, |guard| guard.full && !self.is_closed());
+            }
+            if self.is_closed() {
+                break;
+            }
+            if content.full {
+                content.drop_oldest();
+            }
+
+            content.write(values.next().unwrap());
+            written += 1;
+            unnotified += 1;
+        }
+
+        if unnotified != 0 {
+            self.wait_for_write.notify_all();
+        }
+        written
     }
 
     pub(crate) fn try_read(&self, read_into: impl FnMut(&[u8])) {
+        trace_span!("queue_read");
         let mut content = self.content.lock();
 
         if content.len() == 0 || self.is_closed() {
@@ -71,13 +158,30 @@ impl CircularQueue {
     }
 
     pub(crate) fn blocking_read(&self, read_into: impl FnMut(&[u8])) {
+        self.blocking_read_timeout(read_into, None);
+    }
+
+    /// Waits at most `timeout` for an element, or forever if None.
+    /// Returns without reading once the timeout elapsed, as it does once the queue is closed
+    pub(crate) fn blocking_read_timeout(
+        &self,
+        read_into: impl FnMut(&[u8]),
+        timeout: Option<Duration>,
+    ) {
+        trace_span!("queue_read", blocking = true);
         let mut content = self.content.lock();
         if content.len() == 0 {
-            content = self
-                .wait_for_write
-                .wait_while(content, |guard| guard.len() == 0 && !self.is_closed());
+            let condition = |guard: &CircularQueueContent| guard.len() == 0 && !self.is_closed();
+            content = match timeout {
+                Some(timeout) => {
+                    self.wait_for_write
+                        .wait_while_timeout(content, timeout, condition)
+                        .0
+                }
+                None => self.wait_for_write.wait_while(content, condition),
+            };
         }
-        if self.is_closed() {
+        if content.len() == 0 || self.is_closed() {
             return;
         }
 
@@ -85,15 +189,88 @@ impl CircularQueue {
         self.wait_for_read.notify_one();
     }
 
+    /// Reads the element at the head of the queue without consuming it
+    pub(crate) fn peek(&self, read_into: impl FnOnce(&[u8])) {
+        let content = self.content.lock();
+
+        if content.len() == 0 || self.is_closed() {
+            return;
+        }
+
+        content.peek(read_into);
+    }
+
+    /// Reads up to `max_count` elements, waiting at most `timeout` for the first one to arrive,
+    /// returns the number of elements read
+    pub(crate) fn read_many(
+        &self,
+        max_count: usize,
+        timeout: Option<Duration>,
+        mut read_into: impl FnMut(&[u8]),
+    ) -> usize {
+        trace_span!("queue_read_many", max_count);
+        let mut content = self.content.lock();
+        if content.len() == 0 {
+            let condition = |guard: &CircularQueueContent| guard.len() == 0 && !self.is_closed();
+            content = match timeout {
+                Some(timeout) => {
+                    self.wait_for_write
+                        .wait_while_timeout(content, timeout, condition)
+                        .0
+                }
+                None => self.wait_for_write.wait_while(content, condition),
+            };
+        }
+        if self.is_closed() {
+            return 0;
+        }
+
+        let count = max_count.min(content.len() as usize);
+        for _ in 0..count {
+            content.read(&mut read_into);
+        }
+
+        if count != 0 {
+            self.wait_for_read.notify_all();
+        }
+        count
+    }
+
     pub(crate) fn max_element_size(&self) -> usize {
         let content = self.content.lock();
         content.max_element_size as usize
     }
 
+    /// Number of elements that were overwritten before being read
+    pub(crate) fn dropped_count(&self) -> u64 {
+        let content = self.content.lock();
+        content.dropped_count
+    }
+
     pub(crate) fn is_closed(&self) -> bool {
         self.closed.load(Ordering::Relaxed)
     }
 
+    /// Reads the state without taking the lock, so that it also works while the lock is stuck
+    pub(crate) fn describe(&self) -> SegmentFields {
+        let content = unsafe { self.content.get_unlocked() };
+        let policy = if content.overwrite_oldest {
+            "overwrite oldest"
+        } else {
+            "block"
+        };
+
+        vec![
+            ("closed", self.is_closed().to_string()),
+            ("length", content.len().to_string()),
+            ("capacity", content.capacity.to_string()),
+            ("max element size", content.max_element_size.to_string()),
+            ("overflow policy", policy.to_string()),
+            ("dropped", content.dropped_count.to_string()),
+            ("lock", self.content.describe_lock()),
+        ]
+    }
+
     pub(crate) fn close(&self) {
         let _ = self.content.lock();
         self.closed.store(true, Ordering::Relaxed);
@@ -102,84 +279,145 @@ impl CircularQueue {
     }
 
     pub(crate) fn compute_size_for(max_element_size: usize, capacity: usize) -> usize {
-        CircularQueue::size_of_fields()
-            + (size_of::<ElementSizeType>() + max_element_size) * capacity
+        CircularQueue::size_of_fields() + element_slot_size(max_element_size) * capacity
     }
 
     const fn size_of_fields() -> usize {
         #[repr(C)]
         struct CircularQueueContentSized {
+            dropped_count: u64,
             writer_index: u32,
             reader_index: u32,
             max_element_size: u32,
             capacity: u32,
             full: bool,
+            overwrite_oldest: bool,
         }
         size_of::<CircularQueue<CircularQueueContentSized>>()
     }
 }
 
+impl SlicePtrCast for CircularQueue {
+    const KIND: SegmentKind = SegmentKind::CircularQueue;
+
+    fn cast_from_slice_ptr(slice_ptr: *mut [u8]) -> *const Self {
+        slice_ptr as *const Self
+    }
+}
+
 #[repr(C)]
 pub(crate) struct CircularQueueContent {
+    dropped_count: u64,
     writer_index: u32,
     reader_index: u32,
     max_element_size: u32,
     capacity: u32,
     full: bool,
+    overwrite_oldest: bool,
     buffer: [u8],
 }
 
-pub type ElementSizeType = usize;
+pub(crate) type ElementSizeType = usize;
 
-const ELEMENT_SIZE_TYPE: usize = size_of::<ElementSizeType>();
+pub(crate) const ELEMENT_SIZE_TYPE: usize = size_of::<ElementSizeType>();
 
-impl CircularQueueContent {
-    pub(crate) fn len(&self) -> u32 {
-        use std::cmp::Ordering;
+/// The size taken in the buffer by one element: its size followed by its data.
+/// Shared with the priority queue, so that both store their elements the same way
+#[inline]
+pub(crate) const fn element_slot_size(max_element_size: usize) -> usize {
+    ELEMENT_SIZE_TYPE + max_element_size
+}
 
-        if self.full {
-            return self.capacity;
-        }
+/// Stores `value` at the start of `slot`, prefixed by its size
+pub(crate) fn write_element(slot: &mut [u8], value: &[u8]) {
+    let (size, data) = slot.split_at_mut(ELEMENT_SIZE_TYPE);
+    size.clone_from_slice(&(value.len() as ElementSizeType).to_ne_bytes());
+    data[..value.len()].clone_from_slice(value);
+}
 
-        match self.writer_index.cmp(&self.reader_index) {
-            Ordering::Less => self.capacity - self.reader_index + self.writer_index,
-            Ordering::Equal => 0,
-            Ordering::Greater => self.writer_index - self.reader_index,
-        }
+/// Returns the element stored at the start of `slot` by [`write_element`]
+pub(crate) fn read_element(slot: &[u8]) -> &[u8] {
+    let (size, data) = slot.split_at(ELEMENT_SIZE_TYPE);
+    let element_size = ElementSizeType::from_ne_bytes(size.try_into().unwrap());
+    &data[..element_size]
+}
+
+/// The number of elements in a ring of `capacity` slots
+pub(crate) fn ring_len(writer_index: u32, reader_index: u32, full: bool, capacity: u32) -> u32 {
+    use std::cmp::Ordering;
+
+    if full {
+        return capacity;
+    }
+
+    match writer_index.cmp(&reader_index) {
+        Ordering::Less => capacity - reader_index + writer_index,
+        Ordering::Equal => 0,
+        Ordering::Greater => writer_index - reader_index,
+    }
+}
+
+#[inline]
+pub(crate) fn next_index(i: u32, capacity: u32) -> u32 {
+    (i + 1) % capacity
+}
+
+impl CircularQueueContent {
+    pub(crate) fn len(&self) -> u32 {
+        ring_len(
+            self.writer_index,
+            self.reader_index,
+            self.full,
+            self.capacity,
+        )
     }
 
     pub(crate) fn write(&mut self, value: &[u8]) {
         self.writer_index = self.next_inc(self.writer_index);
         self.full = self.writer_index == self.reader_index;
+        trace_event!(size = value.len(), "queue element written");
 
-        let buffer_index =
-            self.writer_index as usize * (ELEMENT_SIZE_TYPE + self.max_element_size as usize);
-        let data_index = buffer_index + ELEMENT_SIZE_TYPE;
-        let element_size = value.len();
-
-        self.buffer[buffer_index..data_index]
-            .clone_from_slice(&(element_size as ElementSizeType).to_ne_bytes());
-
-        self.buffer[data_index..data_index + element_size].clone_from_slice(value);
+        let slot = self.slot_mut(self.writer_index);
+        write_element(slot, value);
     }
 
     pub(crate) fn read(&mut self, mut read_into: impl FnMut(&[u8])) {
         self.reader_index = self.next_inc(self.reader_index);
         self.full = false;
 
-        let buffer_index =
-            self.reader_index as usize * (ELEMENT_SIZE_TYPE + self.max_element_size as usize);
-        let data_index = buffer_index + ELEMENT_SIZE_TYPE;
-        let element_size = ElementSizeType::from_ne_bytes(
-            self.buffer[buffer_index..data_index].try_into().unwrap(),
+        let element = read_element(self.slot(self.reader_index));
+        trace_event!(size = element.len(), "queue element read");
+        read_into(element);
+    }
+
+    pub(crate) fn peek(&self, read_into: impl FnOnce(&[u8])) {
+        read_into(read_element(self.slot(self.next_inc(self.reader_index))));
+    }
+
+    /// Discards the element at the head of the queue, making room for a new one
+    fn drop_oldest(&mut self) {
+        self.reader_index = self.next_inc(self.reader_index);
+        self.full = false;
+        self.dropped_count += 1;
+        trace_event!(
+            dropped_count = self.dropped_count,
+            "oldest queue element dropped"
         );
+    }
+
+    fn slot(&self, index: u32) -> &[u8] {
+        let slot_size = element_slot_size(self.max_element_size as usize);
+        &self.buffer[index as usize * slot_size..][..slot_size]
+    }
 
-        read_into(&self.buffer[data_index..data_index + element_size]);
+    fn slot_mut(&mut self, index: u32) -> &mut [u8] {
+        let slot_size = element_slot_size(self.max_element_size as usize);
+        &mut self.buffer[index as usize * slot_size..][..slot_size]
     }
 
     #[inline]
     fn next_inc(&self, i: u32) -> u32 {
-        (i + 1) % self.capacity
+        next_index(i, self.capacity)
     }
 }
 
@@ -207,7 +445,7 @@ mod tests {
         let init_buffer = init_vec.as_mut_slice() as *mut [u8];
 
         let queue = unsafe { &mut *(init_buffer as *mut CircularQueue) };
-        queue.init(ELEMENT_SIZE, capacity);
+        queue.init(ELEMENT_SIZE, capacity, OverflowPolicy::Block);
 
         let write = |data: &Data| queue.try_write(any_as_u8_slice(data));
 
@@ -248,4 +486,100 @@ mod tests {
             assert_eq!(data, read());
         }
     }
+
+    #[test]
+    fn overwrite_oldest() {
+        let capacity = 4;
+
+        let init_buffer_size = CircularQueue::compute_size_for(1, capacity);
+        let mut init_vec = vec![0u8; init_buffer_size];
+        let init_buffer = init_vec.as_mut_slice() as *mut [u8];
+
+        let queue = unsafe { &mut *(init_buffer as *mut CircularQueue) };
+        queue.init(1, capacity, OverflowPolicy::OverwriteOldest);
+
+        for i in 0..10u8 {
+            assert!(queue.try_write(&[i]));
+        }
+        assert!(queue.blocking_write(&[10]));
+        assert_eq!(queue.len(), capacity);
+        assert_eq!(queue.dropped_count(), 7);
+
+        for i in 7..=10u8 {
+            let mut value = None;
+            queue.try_read(|data| value = Some(data[0]));
+            assert_eq!(value, Some(i));
+        }
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[test]
+    fn write_many_read_many() {
+        let capacity = 8;
+
+        let init_buffer_size = CircularQueue::compute_size_for(1, capacity);
+        let mut init_vec = vec![0u8; init_buffer_size];
+        let init_buffer = init_vec.as_mut_slice() as *mut [u8];
+
+        let queue = unsafe { &mut *(init_buffer as *mut CircularQueue) };
+        queue.init(1, capacity, OverflowPolicy::Block);
+
+        let values = (0..6u8).map(|i| [i]).collect::<Vec<_>>();
+        assert_eq!(queue.write_many(values.iter().map(|v| v.as_slice())), 6);
+        assert_eq!(queue.len(), 6);
+
+        let mut read = Vec::new();
+        assert_eq!(queue.read_many(4, None, |data| read.push(data[0])), 4);
+        assert_eq!(read, [0, 1, 2, 3]);
+
+        read.clear();
+        assert_eq!(queue.read_many(10, None, |data| read.push(data[0])), 2);
+        assert_eq!(read, [4, 5]);
+
+        let timeout = Some(Duration::from_millis(10));
+        assert_eq!(queue.read_many(10, timeout, |_| unreachable!()), 0);
+
+        assert_eq!(
+            queue.write_many(values.iter().cycle().take(8).map(|v| v.as_slice())),
+            8
+        );
+        assert_eq!(
+            queue.blocking_write_timeout(&[8], timeout),
+            Err(WriteError::TimedOut)
+        );
+        queue.close();
+        assert_eq!(
+            queue.blocking_write_timeout(&[8], timeout),
+            Err(WriteError::Closed)
+        );
+    }
+
+    #[test]
+    fn peek() {
+        let capacity = 4;
+
+        let init_buffer_size = CircularQueue::compute_size_for(1, capacity);
+        let mut init_vec = vec![0u8; init_buffer_size];
+        let init_buffer = init_vec.as_mut_slice() as *mut [u8];
+
+        let queue = unsafe { &mut *(init_buffer as *mut CircularQueue) };
+        queue.init(1, capacity, OverflowPolicy::Block);
+
+        queue.peek(|_| unreachable!());
+
+        queue.try_write(&[1]);
+        queue.try_write(&[2]);
+        for _ in 0..3 {
+            let mut value = None;
+            queue.peek(|data| value = Some(data[0]));
+            assert_eq!(value, Some(1));
+        }
+        assert_eq!(queue.len(), 2);
+
+        let mut value = None;
+        queue.try_read(|data| value = Some(data[0]));
+        assert_eq!(value, Some(1));
+        queue.peek(|data| value = Some(data[0]));
+        assert_eq!(value, Some(2));
+    }
 }
@@ -1,34 +1,101 @@
+use crate::helpers::frames::write_frames;
+use crate::helpers::trace::{trace_event, trace_span};
 use crate::primitives::condvar::SharedCondvar;
-use crate::primitives::memory_holder::SlicePtrCast;
-use crate::primitives::mutex::SharedMutex;
+use crate::primitives::memory_holder::{SegmentFields, SegmentKind, SlicePtrCast};
+use crate::primitives::rwlock::SharedRwLock;
+use rustix::process::getpid;
+use rustix::thread::gettid;
+use rustix::time::{clock_gettime, ClockId, Timespec};
 use std::num::NonZeroU32;
-use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::time::Duration;
 
-// 32 - readers_count
+/// A CRC32C of the payload is stored with every message and verified on every read
+const FLAG_CHECKSUM: u32 = 1 << 0;
+
+/// Segment wide options, chosen by the creator of the segment
+#[derive(Debug, Default, Copy, Clone)]
+pub struct MessageOptions {
+    pub checksum: bool,
+    /// Lock with FUTEX_LOCK_PI, so that the writer and readers holding the lock
+    /// are boosted while a higher priority process waits for it
+    pub priority_inheritance: bool,
+}
+
+/// The payload does not match the checksum that was written with it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CorruptMessage {
+    pub version: usize,
+    pub expected_checksum: u32,
+    pub actual_checksum: u32,
+}
+
+// 64 - version
+// 8 + 24pad - closed
+// 32 - flags
 // 32 - write_condvar
 // 32 - read_condvar
-// 8 + 24pad - closed
-// 64 - version
-// 32 + 32pad - mutex
-// 32 + 32pad - reader_version_count
+// 32 - rwlock state
+// 32 - rwlock writer_notify
+// 8 + 56pad - rwlock priority_inheritance
+// 32 - consumer_count
+// 32 - read_count
 // 64 - current message size
-// 64 - max message size
-// N - bytes
+// 32 + 32pad - payload checksum
+// 256 - message info
+// N - payload, up to the end of the segment
 
 #[repr(C)]
 pub struct SharedMessage<T: ?Sized = SharedMessageData> {
     version: AtomicUsize,
     closed: AtomicBool,
+    flags: AtomicU32,
     write_condvar: SharedCondvar,
     read_condvar: SharedCondvar,
-    data: SharedMutex<T>,
+    /// Readers only take it in shared mode, so that they can copy the payload in parallel
+    data: SharedRwLock<T>,
+}
+
+/// Recorded by the writer alongside each message
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct MessageInfo {
+    pub version: usize,
+    /// CLOCK_MONOTONIC timestamp, comparable between processes on the same machine
+    pub monotonic_ns: u64,
+    /// CLOCK_REALTIME timestamp, nanoseconds since the Unix epoch
+    pub realtime_ns: u64,
+    pub writer_pid: u32,
+    pub writer_tid: u32,
+}
+
+impl MessageInfo {
+    fn current(version: usize) -> Self {
+        Self {
+            version,
+            monotonic_ns: monotonic_now_ns(),
+            realtime_ns: timespec_ns(clock_gettime(ClockId::Realtime)),
+            writer_pid: getpid().as_raw_nonzero().get() as u32,
+            writer_tid: gettid().as_raw_nonzero().get() as u32,
+        }
+    }
+}
+
+pub(crate) fn monotonic_now_ns() -> u64 {
+    timespec_ns(clock_gettime(ClockId::Monotonic))
+}
+
+fn timespec_ns(time: Timespec) -> u64 {
+    time.tv_sec as u64 * 1_000_000_000 + time.tv_nsec as u64
 }
 
 #[repr(C)]
 pub struct SharedMessageData {
     consumer_count: u32,
-    read_count: u32,
+    read_count: AtomicU32,
     size: usize,
+    checksum: u32,
+    info: MessageInfo,
     // Note: The current way the void pointer is cast to a struct, leads to len() returning an incorrect value
     payload: [u8],
 }
@@ -38,10 +105,28 @@ impl SharedMessageData {
     fn copy(&mut self, data: &[u8]) {
         let data_len = data.len();
 
-        self.read_count = 0;
+        *self.read_count.get_mut() = 0;
         self.size = data_len;
         self.payload[..data_len].copy_from_slice(data);
     }
+
+    #[inline]
+    fn copy_parts(&mut self, parts: &[&[u8]]) {
+        let mut index = 0;
+        for part in parts {
+            self.payload[index..index + part.len()].copy_from_slice(part);
+            index += part.len();
+        }
+
+        *self.read_count.get_mut() = 0;
+        self.size = index;
+    }
+
+    #[inline]
+    fn copy_frames<'a>(&mut self, frames: impl IntoIterator<Item = &'a [u8]>) {
+        *self.read_count.get_mut() = 0;
+        self.size = write_frames(&mut self.payload, frames);
+    }
 }
 
 impl SharedMessage {
@@ -49,21 +134,55 @@ impl SharedMessage {
         #[repr(C)]
         struct SharedMemoryDataSized {
             consumer_count: u32,
-            read_count: u32,
+            read_count: AtomicU32,
             size: usize,
+            checksum: u32,
+            info: MessageInfo,
         }
         size_of::<SharedMessage<SharedMemoryDataSized>>()
     }
 
+    /// Must be called by the creator of the segment, before any other operation
+    pub(crate) fn init(&self, options: MessageOptions) {
+        let mut flags = 0;
+        if options.checksum {
+            flags |= FLAG_CHECKSUM;
+        }
+        self.flags.store(flags, Ordering::Relaxed);
+        if options.priority_inheritance {
+            self.data.enable_priority_inheritance();
+        }
+    }
+
+    pub(crate) fn has_checksum(&self) -> bool {
+        self.flags.load(Ordering::Relaxed) & FLAG_CHECKSUM != 0
+    }
+
     pub(crate) fn write(&self, data: &[u8]) -> usize {
-        let mut content = self.data.lock();
+        self.write_with(None, |content| content.copy(data))
+    }
+
+    /// Writes the parts one after the other, as a single message
+    pub(crate) fn write_parts(&self, parts: &[&[u8]]) -> usize {
+        self.write_with(None, |content| content.copy_parts(parts))
+    }
+
+    /// Writes with the next version, unless `version` is given
+    fn write_with(
+        &self,
+        version: Option<usize>,
+        copy: impl FnOnce(&mut SharedMessageData),
+    ) -> usize {
+        trace_span!("message_write");
+        let mut content = self.data.write();
 
-        let old_version = self.version.fetch_add(1, Ordering::Relaxed);
-        content.copy(data);
+        let new_version = self.next_version(version);
+        copy(&mut content);
+        self.finish_write(&mut content, new_version);
 
         self.write_condvar.notify_all();
 
-        old_version + 1
+        new_version
     }
 
     pub(crate) fn write_waiting_for_readers(
@@ -71,60 +190,188 @@ impl SharedMessage {
         data: &[u8],
         wait_for: Option<NonZeroU32>,
     ) -> usize {
-        let mut content = self.data.lock();
+        self.write_waiting_for_readers_with(wait_for, None, |content| content.copy(data))
+    }
+
+    /// Packs all the frames into a single message, see [`crate::helpers::frames`]
+    pub(crate) fn write_frames_waiting_for_readers<'a>(
+        &self,
+        frames: impl IntoIterator<Item = &'a [u8]>,
+        wait_for: Option<NonZeroU32>,
+    ) -> usize {
+        self.write_waiting_for_readers_with(wait_for, None, |content| content.copy_frames(frames))
+    }
+
+    /// Writes the message with the version of the segment it mirrors, see [`crate::bridge`].
+    /// Waits for all the readers of the previous version if `wait_for_readers` is set
+    pub(crate) fn write_version(&self, data: &[u8], version: usize, wait_for_readers: bool) {
+        match wait_for_readers {
+            true => self
+                .write_waiting_for_readers_with(None, Some(version), |content| content.copy(data)),
+            false => self.write_with(Some(version), |content| content.copy(data)),
+        };
+    }
+
+    fn write_waiting_for_readers_with(
+        &self,
+        wait_for: Option<NonZeroU32>,
+        version: Option<usize>,
+        copy: impl FnOnce(&mut SharedMessageData),
+    ) -> usize {
+        trace_span!("message_write", waiting_for_readers = true);
+        let mut content = self.data.write();
         let wait_for_count = wait_for.map(|v| v.get().min(content.consumer_count));
 
         if self.version.load(Ordering::Relaxed) != 0 {
             content = self.read_condvar.wait_while(content, |lock| {
-                lock.read_count < wait_for_count.unwrap_or(lock.consumer_count)
+                lock.read_count.load(Ordering::Relaxed)
+                    < wait_for_count.unwrap_or(lock.consumer_count)
             });
         }
 
-        let old_version = self.version.fetch_add(1, Ordering::Relaxed);
-        content.copy(data);
+        let new_version = self.next_version(version);
+        copy(&mut content);
+        self.finish_write(&mut content, new_version);
 
         self.write_condvar.notify_all();
 
-        old_version + 1
+        new_version
     }
 
-    pub(crate) fn try_read(&self, current_version: usize, mut read: impl FnMut(usize, &[u8])) {
+    /// Must be called with the write lock held
+    fn next_version(&self, version: Option<usize>) -> usize {
+        match version {
+            Some(version) => {
+                self.version.store(version, Ordering::Relaxed);
+                version
+            }
+            None => self.version.fetch_add(1, Ordering::Relaxed) + 1,
+        }
+    }
+
+    fn finish_write(&self, content: &mut SharedMessageData, version: usize) {
+        content.info = MessageInfo::current(version);
+        if self.has_checksum() {
+            content.checksum = crc32c::crc32c(&content.payload[..content.size]);
+        }
+        trace_event!(version, size = content.size, "message written");
+    }
+
+    /// A corrupt message still counts as read, so that writers waiting for readers are not blocked
+    pub(crate) fn try_read(
+        &self,
+        current_version: usize,
+        read: impl FnMut(&MessageInfo, &[u8]),
+    ) -> Result<(), CorruptMessage> {
         if self.closed.load(Ordering::Relaxed) {
-            return;
+            return Ok(());
         }
 
         // Read the version to check if there is a new one
         if current_version == self.version.load(Ordering::Relaxed) {
-            return;
+            return Ok(());
         }
 
-        let mut data = self.data.lock();
-        // Read the version again after the lock has been acquired
-        let new_version = self.version.load(Ordering::Relaxed);
+        trace_span!("message_read");
+        let data = self.data.read();
 
-        read(new_version, &data.payload[..data.size]);
-        data.read_count += 1;
+        let result = self.read_payload(&data, read);
+        data.read_count.fetch_add(1, Ordering::Relaxed);
         self.read_condvar.notify_all();
+        result
     }
 
-    pub(crate) fn blocking_read(&self, current_version: usize, mut read: impl FnMut(usize, &[u8])) {
-        let mut data = self.data.lock();
-        loop {
-            if self.closed.load(Ordering::Relaxed) {
-                return;
-            }
+    pub(crate) fn blocking_read(
+        &self,
+        current_version: usize,
+        read: impl FnMut(&MessageInfo, &[u8]),
+    ) -> Result<(), CorruptMessage> {
+        self.blocking_read_timeout(current_version, None, read)
+    }
 
-            let new_version = self.version.load(Ordering::Relaxed);
-            if new_version != current_version {
-                read(new_version, &data.payload[..data.size]);
-                data.read_count += 1;
-                self.read_condvar.notify_all();
-                return;
+    /// Waits at most `timeout` for a new version, or forever if None.
+    /// Returns without reading once the timeout elapsed, as it does once the message is closed
+    pub(crate) fn blocking_read_timeout(
+        &self,
+        current_version: usize,
+        timeout: Option<Duration>,
+        read: impl FnMut(&MessageInfo, &[u8]),
+    ) -> Result<(), CorruptMessage> {
+        trace_span!("message_read", blocking = true);
+        let mut data = self.data.read();
+        let condition = |_: &SharedMessageData| {
+            !self.closed.load(Ordering::Relaxed)
+                && self.version.load(Ordering::Relaxed) == current_version
+        };
+        data = match timeout {
+            Some(timeout) => {
+                self.write_condvar
+                    .wait_while_timeout(data, timeout, condition)
+                    .0
             }
+            None => self.write_condvar.wait_while(data, condition),
+        };
+        if condition(&data) || self.closed.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let result = self.read_payload(&data, read);
+        data.read_count.fetch_add(1, Ordering::Relaxed);
+        self.read_condvar.notify_all();
+        result
+    }
 
-            // Wait for new version
-            data = self.write_condvar.wait(data);
+    /// Reads the latest message without counting as a read of it
+    pub(crate) fn peek(
+        &self,
+        read: impl FnOnce(&MessageInfo, &[u8]),
+    ) -> Result<(), CorruptMessage> {
+        if self.closed.load(Ordering::Relaxed) || self.version.load(Ordering::Relaxed) == 0 {
+            return Ok(());
         }
+
+        let data = self.data.read();
+
+        self.read_payload(&data, read)
+    }
+
+    fn read_payload(
+        &self,
+        data: &SharedMessageData,
+        read: impl FnOnce(&MessageInfo, &[u8]),
+    ) -> Result<(), CorruptMessage> {
+        let payload = &data.payload[..data.size];
+        trace_event!(
+            version = data.info.version,
+            size = data.size,
+            "message read"
+        );
+
+        if self.has_checksum() {
+            let actual_checksum = crc32c::crc32c(payload);
+            if actual_checksum != data.checksum {
+                return Err(CorruptMessage {
+                    version: data.info.version,
+                    expected_checksum: data.checksum,
+                    actual_checksum,
+                });
+            }
+        }
+
+        read(&data.info, payload);
+        Ok(())
+    }
+
+    /// Waits for a version different from `current_version` without reading it,
+    /// returns the latest version, which is still `current_version` if the timeout elapsed
+    pub(crate) fn wait_for_new_version(&self, current_version: usize, timeout: Duration) -> usize {
+        let data = self.data.read();
+        let _ = self.write_condvar.wait_while_timeout(data, timeout, |_| {
+            !self.closed.load(Ordering::Relaxed)
+                && self.version.load(Ordering::Relaxed) == current_version
+        });
+
+        self.version.load(Ordering::Relaxed)
     }
 
     pub(crate) fn is_new_version_available(&self, current_version: usize) -> bool {
@@ -134,12 +381,12 @@ impl SharedMessage {
     }
 
     pub(crate) fn add_reader(&self) {
-        let mut content = self.data.lock();
+        let mut content = self.data.write();
         content.consumer_count += 1;
     }
 
     pub(crate) fn remove_reader(&self) {
-        let mut content = self.data.lock();
+        let mut content = self.data.write();
         content.consumer_count -= 1;
     }
 
@@ -147,14 +394,38 @@ impl SharedMessage {
         self.closed.load(Ordering::Relaxed)
     }
 
+    /// Reads the state without taking the lock, so that it also works while the lock is stuck
+    pub(crate) fn describe(&self) -> SegmentFields {
+        let data = unsafe { self.data.get_unlocked() };
+
+        vec![
+            ("version", self.version.load(Ordering::Relaxed).to_string()),
+            ("closed", self.is_closed().to_string()),
+            ("checksum", self.has_checksum().to_string()),
+            ("message size", data.size.to_string()),
+            ("consumers", data.consumer_count.to_string()),
+            (
+                "reads of the version",
+                data.read_count.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "written by",
+                format!("pid {} tid {}", data.info.writer_pid, data.info.writer_tid),
+            ),
+            ("lock", self.data.describe_lock()),
+        ]
+    }
+
     pub(crate) fn close(&self) {
-        let _ = self.data.lock();
+        let _ = self.data.write();
         self.closed.store(true, Ordering::Relaxed);
         self.write_condvar.notify_all();
     }
 }
 
 impl SlicePtrCast for SharedMessage {
+    const KIND: SegmentKind = SegmentKind::Message;
+
     fn cast_from_slice_ptr(slice_ptr: *mut [u8]) -> *const Self {
         slice_ptr as *const Self
     }
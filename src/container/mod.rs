@@ -1,3 +1,7 @@
+pub mod byte_ring;
 #[allow(dead_code)]
 pub mod circular_queue;
 pub mod message;
+pub mod priority_queue;
+pub mod reply_slots;
+pub mod sync;
@@ -0,0 +1,270 @@
+use crate::container::circular_queue::{
+    element_slot_size, next_index, read_element, ring_len, write_element,
+};
+use crate::primitives::condvar::SharedCondvar;
+use crate::primitives::memory_holder::{SegmentFields, SegmentKind, SlicePtrCast};
+use crate::primitives::mutex::SharedMutex;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub(crate) const MAX_PRIORITY_LEVELS: usize = 8;
+
+/// A queue made of one ring per priority level, all stored in the same segment.
+/// Elements with a higher priority are always read first,
+/// elements with the same priority are read in FIFO order
+#[repr(C)]
+pub(crate) struct PriorityQueue<T: ?Sized = PriorityQueueContent> {
+    wait_for_read: SharedCondvar,
+    wait_for_write: SharedCondvar,
+    closed: AtomicBool,
+    content: SharedMutex<T>,
+}
+
+impl PriorityQueue {
+    pub(crate) fn init(
+        &self,
+        max_element_size: usize,
+        capacity: usize,
+        levels: usize,
+        priority_inheritance: bool,
+    ) {
+        assert!(levels > 0 && levels <= MAX_PRIORITY_LEVELS);
+        if priority_inheritance {
+            self.content.enable_priority_inheritance();
+        }
+        let mut content = self.content.lock();
+
+        content.max_element_size = max_element_size as u32;
+        content.capacity = capacity as u32;
+        content.levels = levels as u32;
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        let content = self.content.lock();
+
+        content.len() as usize
+    }
+
+    pub(crate) fn try_write(&self, value: &[u8], priority: usize) -> bool {
+        let mut content = self.content.lock();
+        if content.rings[priority].full || self.is_closed() {
+            return false;
+        }
+
+        content.write(value, priority);
+        self.wait_for_write.notify_one();
+
+        true
+    }
+
+    pub(crate) fn blocking_write(&self, value: &[u8], priority: usize) -> bool {
+        let mut content = self.content.lock();
+        if content.rings[priority].full {
+            content = self.wait_for_read.wait_while(content, |guard| {
+                guard.rings[priority].full && !self.is_closed()
+            });
+        }
+        if self.is_closed() {
+            return false;
+        }
+
+        content.write(value, priority);
+        self.wait_for_write.notify_one();
+        true
+    }
+
+    pub(crate) fn try_read(&self, read_into: impl FnMut(&[u8])) {
+        let mut content = self.content.lock();
+
+        if content.len() == 0 || self.is_closed() {
+            return;
+        }
+
+        content.read(read_into);
+        // Writers may be waiting on different levels, so wake all of them
+        self.wait_for_read.notify_all();
+    }
+
+    pub(crate) fn blocking_read(&self, read_into: impl FnMut(&[u8])) {
+        let mut content = self.content.lock();
+        if content.len() == 0 {
+            content = self
+                .wait_for_write
+                .wait_while(content, |guard| guard.len() == 0 && !self.is_closed());
+        }
+        if self.is_closed() {
+            return;
+        }
+
+        content.read(read_into);
+        self.wait_for_read.notify_all();
+    }
+
+    pub(crate) fn max_element_size(&self) -> usize {
+        let content = self.content.lock();
+        content.max_element_size as usize
+    }
+
+    pub(crate) fn levels(&self) -> usize {
+        let content = self.content.lock();
+        content.levels as usize
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    /// Reads the state without taking the lock, so that it also works while the lock is stuck
+    pub(crate) fn describe(&self) -> SegmentFields {
+        let content = unsafe { self.content.get_unlocked() };
+        let levels = (content.levels as usize).min(MAX_PRIORITY_LEVELS);
+        let lengths = content.rings[..levels]
+            .iter()
+            .map(|ring| ring.len(content.capacity).to_string())
+            .collect::<Vec<_>>();
+
+        vec![
+            ("closed", self.is_closed().to_string()),
+            ("length per level", lengths.join(", ")),
+            ("capacity per level", content.capacity.to_string()),
+            ("max element size", content.max_element_size.to_string()),
+            ("lock", self.content.describe_lock()),
+        ]
+    }
+
+    pub(crate) fn close(&self) {
+        let _ = self.content.lock();
+        self.closed.store(true, Ordering::Relaxed);
+        self.wait_for_write.notify_all();
+        self.wait_for_read.notify_all();
+    }
+
+    pub(crate) fn compute_size_for(
+        max_element_size: usize,
+        capacity: usize,
+        levels: usize,
+    ) -> usize {
+        PriorityQueue::size_of_fields() + element_slot_size(max_element_size) * capacity * levels
+    }
+
+    const fn size_of_fields() -> usize {
+        #[repr(C)]
+        struct PriorityQueueContentSized {
+            max_element_size: u32,
+            capacity: u32,
+            levels: u32,
+            rings: [Ring; MAX_PRIORITY_LEVELS],
+        }
+        size_of::<PriorityQueue<PriorityQueueContentSized>>()
+    }
+}
+
+impl SlicePtrCast for PriorityQueue {
+    const KIND: SegmentKind = SegmentKind::PriorityQueue;
+
+    fn cast_from_slice_ptr(slice_ptr: *mut [u8]) -> *const Self {
+        slice_ptr as *const Self
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct Ring {
+    writer_index: u32,
+    reader_index: u32,
+    full: bool,
+}
+
+impl Ring {
+    fn len(&self, capacity: u32) -> u32 {
+        ring_len(self.writer_index, self.reader_index, self.full, capacity)
+    }
+}
+
+#[repr(C)]
+pub(crate) struct PriorityQueueContent {
+    max_element_size: u32,
+    capacity: u32,
+    levels: u32,
+    rings: [Ring; MAX_PRIORITY_LEVELS],
+    buffer: [u8],
+}
+
+impl PriorityQueueContent {
+    pub(crate) fn len(&self) -> u32 {
+        self.rings[..self.levels as usize]
+            .iter()
+            .map(|ring| ring.len(self.capacity))
+            .sum()
+    }
+
+    pub(crate) fn write(&mut self, value: &[u8], priority: usize) {
+        let ring = &mut self.rings[priority];
+        ring.writer_index = next_index(ring.writer_index, self.capacity);
+        ring.full = ring.writer_index == ring.reader_index;
+        let writer_index = ring.writer_index;
+
+        write_element(self.slot_mut(priority, writer_index), value);
+    }
+
+    /// Reads from the highest priority ring that is not empty
+    pub(crate) fn read(&mut self, mut read_into: impl FnMut(&[u8])) {
+        let Some(priority) =
+            (0..self.levels as usize).rfind(|&i| self.rings[i].len(self.capacity) != 0)
+        else {
+            return;
+        };
+
+        let ring = &mut self.rings[priority];
+        ring.reader_index = next_index(ring.reader_index, self.capacity);
+        ring.full = false;
+        let reader_index = ring.reader_index;
+
+        read_into(read_element(self.slot(priority, reader_index)));
+    }
+
+    fn slot(&self, priority: usize, index: u32) -> &[u8] {
+        let slot_size = element_slot_size(self.max_element_size as usize);
+        let slot_index = priority * self.capacity as usize + index as usize;
+        &self.buffer[slot_index * slot_size..][..slot_size]
+    }
+
+    fn slot_mut(&mut self, priority: usize, index: u32) -> &mut [u8] {
+        let slot_size = element_slot_size(self.max_element_size as usize);
+        let slot_index = priority * self.capacity as usize + index as usize;
+        &mut self.buffer[slot_index * slot_size..][..slot_size]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn highest_priority_first() {
+        let capacity = 4;
+        let levels = 3;
+
+        let init_buffer_size = PriorityQueue::compute_size_for(1, capacity, levels);
+        let mut init_vec = vec![0u8; init_buffer_size];
+        let init_buffer = init_vec.as_mut_slice() as *mut [u8];
+
+        let queue = unsafe { &*(init_buffer as *mut PriorityQueue) };
+        queue.init(1, capacity, levels, true);
+
+        for i in 0..capacity as u8 {
+            assert!(queue.try_write(&[i], 0));
+        }
+        assert!(!queue.try_write(&[0], 0));
+        assert!(queue.try_write(&[10], 2));
+        assert!(queue.try_write(&[20], 1));
+        assert!(queue.try_write(&[11], 2));
+        assert_eq!(queue.len(), capacity + 3);
+
+        let mut read = Vec::new();
+        while queue.len() != 0 {
+            queue.try_read(|data| read.push(data[0]));
+        }
+        assert_eq!(read, [10, 11, 20, 0, 1, 2, 3]);
+    }
+}
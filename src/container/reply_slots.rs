@@ -0,0 +1,348 @@
+use crate::helpers::process::ProcessId;
+use crate::primitives::condvar::SharedCondvar;
+use crate::primitives::memory_holder::{SegmentFields, SegmentKind, SlicePtrCast};
+use crate::primitives::mutex::SharedMutex;
+use std::mem::{align_of, size_of};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+/// A slot that is not waiting for a reply, or whose reply has already been taken
+const STATE_EMPTY: u64 = 0;
+const STATE_PENDING: u64 = 1;
+const STATE_REPLIED: u64 = 2;
+
+// 64 - in use
+// 64 - state
+// 64 - correlation id
+// 64 - reply size
+// 64 - owner pid
+// 64 - owner start time
+// N - reply, max_reply_size bytes
+// N - padding, up to the alignment of the next slot
+
+/// Only modified with the lock held, but read without it by [`ReplySlots::describe`]
+#[repr(C)]
+struct ReplySlotHeader {
+    in_use: AtomicU64,
+    state: AtomicU64,
+    correlation_id: AtomicU64,
+    size: AtomicU64,
+    owner_pid: AtomicU64,
+    owner_start_time: AtomicU64,
+}
+
+const SLOT_HEADER_SIZE: usize = size_of::<ReplySlotHeader>();
+
+/// Why no reply was received
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum ReplyError {
+    TimedOut,
+    Closed,
+}
+
+/// Fixed number of reply slots, each one owned by a single client at a time.
+/// A client marks its slot as pending with the correlation id of the request,
+/// the server only stores a reply if the correlation id still matches
+#[repr(C)]
+pub(crate) struct ReplySlots<T: ?Sized = ReplySlotsContent> {
+    reply_condvar: SharedCondvar,
+    closed: AtomicBool,
+    content: SharedMutex<T>,
+}
+
+impl ReplySlots {
+    pub(crate) fn init(&self, slot_count: usize, max_reply_size: usize) {
+        let mut content = self.content.lock();
+
+        content.slot_count = slot_count as u32;
+        content.max_reply_size = max_reply_size as u32;
+    }
+
+    /// Reserves a free slot for this process, returns None if all the slots are in use.
+    /// The slots of clients that died without releasing them are reused
+    pub(crate) fn acquire(&self) -> Option<u32> {
+        let owner = ProcessId::current();
+        let content = self.content.lock();
+
+        let slot = (0..content.slot_count).find(|&slot| {
+            let header = content.header(slot);
+            header.in_use.load(Ordering::Relaxed) == 0 || !header.owner().is_alive()
+        })?;
+        let header = content.header(slot);
+        header.in_use.store(1, Ordering::Relaxed);
+        header.state.store(STATE_EMPTY, Ordering::Relaxed);
+        header.owner_pid.store(owner.pid.into(), Ordering::Relaxed);
+        header
+            .owner_start_time
+            .store(owner.start_time, Ordering::Relaxed);
+        Some(slot)
+    }
+
+    pub(crate) fn release(&self, slot: u32) {
+        let content = self.content.lock();
+        let header = content.header(slot);
+
+        header.in_use.store(0, Ordering::Relaxed);
+        header.state.store(STATE_EMPTY, Ordering::Relaxed);
+    }
+
+    /// Must be called before sending the request, so that the reply cannot arrive before the slot expects it
+    pub(crate) fn expect_reply(&self, slot: u32, correlation_id: u64) {
+        let content = self.content.lock();
+        let header = content.header(slot);
+
+        header
+            .correlation_id
+            .store(correlation_id, Ordering::Relaxed);
+        header.state.store(STATE_PENDING, Ordering::Relaxed);
+    }
+
+    /// Waits for the reply to the expected request, a reply arriving after the timeout is discarded
+    pub(crate) fn wait_reply(
+        &self,
+        slot: u32,
+        timeout: Option<Duration>,
+        read: impl FnOnce(&[u8]),
+    ) -> Result<(), ReplyError> {
+        let mut content = self.content.lock();
+        let condition = |guard: &ReplySlotsContent| {
+            guard.header(slot).state.load(Ordering::Relaxed) != STATE_REPLIED && !self.is_closed()
+        };
+        content = match timeout {
+            Some(timeout) => {
+                self.reply_condvar
+                    .wait_while_timeout(content, timeout, condition)
+                    .0
+            }
+            None => self.reply_condvar.wait_while(content, condition),
+        };
+
+        let state = &content.header(slot).state;
+        let replied = state.load(Ordering::Relaxed) == STATE_REPLIED;
+        state.store(STATE_EMPTY, Ordering::Relaxed);
+
+        if replied {
+            read(content.slot_data(slot));
+            Ok(())
+        } else if self.is_closed() {
+            Err(ReplyError::Closed)
+        } else {
+            Err(ReplyError::TimedOut)
+        }
+    }
+
+    /// Stores the reply if the slot is still waiting for this request,
+    /// returns false if the client gave up or if the queue was closed
+    pub(crate) fn reply(&self, slot: u32, correlation_id: u64, data: &[u8]) -> bool {
+        let mut content = self.content.lock();
+        if self.is_closed()
+            || slot >= content.slot_count
+            || content.header(slot).state.load(Ordering::Relaxed) != STATE_PENDING
+            || content.header(slot).correlation_id.load(Ordering::Relaxed) != correlation_id
+        {
+            return false;
+        }
+
+        content.slot_data_mut(slot)[..data.len()].copy_from_slice(data);
+        let header = content.header(slot);
+        header.size.store(data.len() as u64, Ordering::Relaxed);
+        header.state.store(STATE_REPLIED, Ordering::Relaxed);
+        self.reply_condvar.notify_all();
+
+        true
+    }
+
+    pub(crate) fn max_reply_size(&self) -> usize {
+        let content = self.content.lock();
+
+        content.max_reply_size as usize
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    /// Reads the state without taking the lock, so that it also works while the lock is stuck
+    pub(crate) fn describe(&self) -> SegmentFields {
+        let content = unsafe { self.content.get_unlocked() };
+        let mut in_use = 0;
+        let mut pending = 0;
+        for slot in 0..content.slot_count {
+            let header = content.header(slot);
+            in_use += header.in_use.load(Ordering::Relaxed);
+            pending += u64::from(header.state.load(Ordering::Relaxed) == STATE_PENDING);
+        }
+
+        vec![
+            ("closed", self.is_closed().to_string()),
+            ("slots", content.slot_count.to_string()),
+            ("slots in use", in_use.to_string()),
+            ("waiting for a reply", pending.to_string()),
+            ("max reply size", content.max_reply_size.to_string()),
+            ("lock", self.content.describe_lock()),
+        ]
+    }
+
+    pub(crate) fn close(&self) {
+        let _guard = self.content.lock();
+        self.closed.store(true, Ordering::Relaxed);
+        self.reply_condvar.notify_all();
+    }
+
+    pub(crate) fn compute_size_for(slot_count: usize, max_reply_size: usize) -> usize {
+        ReplySlots::size_of_fields() + slot_count * slot_size(max_reply_size as u32)
+    }
+
+    const fn size_of_fields() -> usize {
+        #[repr(C, align(8))]
+        struct ReplySlotsContentSized {
+            slot_count: u32,
+            max_reply_size: u32,
+        }
+        size_of::<ReplySlots<ReplySlotsContentSized>>()
+    }
+}
+
+impl SlicePtrCast for ReplySlots {
+    const KIND: SegmentKind = SegmentKind::ReplySlots;
+
+    fn cast_from_slice_ptr(slice_ptr: *mut [u8]) -> *const Self {
+        slice_ptr as *const Self
+    }
+}
+
+/// Aligned for the header of the first slot
+#[repr(C, align(8))]
+pub(crate) struct ReplySlotsContent {
+    slot_count: u32,
+    max_reply_size: u32,
+    buffer: [u8],
+}
+
+/// Rounded up, so that the header of every slot is aligned
+fn slot_size(max_reply_size: u32) -> usize {
+    (SLOT_HEADER_SIZE + max_reply_size as usize).next_multiple_of(align_of::<ReplySlotHeader>())
+}
+
+impl ReplySlotHeader {
+    fn owner(&self) -> ProcessId {
+        ProcessId {
+            pid: self.owner_pid.load(Ordering::Relaxed) as u32,
+            start_time: self.owner_start_time.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl ReplySlotsContent {
+    fn slot_index(&self, slot: u32) -> usize {
+        slot as usize * slot_size(self.max_reply_size)
+    }
+
+    fn header(&self, slot: u32) -> &ReplySlotHeader {
+        let header = &self.buffer[self.slot_index(slot)..][..SLOT_HEADER_SIZE];
+        // The buffer and the slot size are aligned for the header
+        unsafe { &*(header.as_ptr() as *const ReplySlotHeader) }
+    }
+
+    fn slot_data(&self, slot: u32) -> &[u8] {
+        let index = self.slot_index(slot) + SLOT_HEADER_SIZE;
+        let size = self.header(slot).size.load(Ordering::Relaxed) as usize;
+        &self.buffer[index..index + size]
+    }
+
+    fn slot_data_mut(&mut self, slot: u32) -> &mut [u8] {
+        let index = self.slot_index(slot) + SLOT_HEADER_SIZE;
+        let max_reply_size = self.max_reply_size as usize;
+        &mut self.buffer[index..index + max_reply_size]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn reply_slots(slot_count: usize, max_reply_size: usize) -> &'static ReplySlots {
+        let memory = vec![0u8; ReplySlots::compute_size_for(slot_count, max_reply_size)].leak();
+        let slots = unsafe { &*ReplySlots::cast_from_slice_ptr(memory) };
+        slots.init(slot_count, max_reply_size);
+        slots
+    }
+
+    #[test]
+    fn acquire_and_release() {
+        let slots = reply_slots(2, 8);
+
+        assert_eq!(slots.acquire(), Some(0));
+        assert_eq!(slots.acquire(), Some(1));
+        assert_eq!(slots.acquire(), None);
+        slots.release(0);
+        assert_eq!(slots.acquire(), Some(0));
+
+        // The slot of a client that died is reclaimed
+        slots
+            .content
+            .lock()
+            .header(1)
+            .owner_pid
+            .store(u32::MAX.into(), Ordering::Relaxed);
+        assert_eq!(slots.acquire(), Some(1));
+        assert_eq!(slots.acquire(), None);
+    }
+
+    #[test]
+    fn slot_headers_are_aligned() {
+        // The reply size is not a multiple of the alignment of the headers
+        let slots = reply_slots(3, 5);
+        let content = slots.content.lock();
+        for slot in 0..3 {
+            let header = content.header(slot) as *const ReplySlotHeader;
+            assert!(header.is_aligned());
+        }
+        drop(content);
+
+        let slot = slots.acquire().unwrap();
+        slots.expect_reply(slot, 1);
+        assert!(slots.reply(slot, 1, &[1, 2, 3, 4, 5]));
+        let mut reply = Vec::new();
+        slots
+            .wait_reply(slot, None, |data| reply = data.to_vec())
+            .unwrap();
+        assert_eq!(reply, [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn reply_matches_correlation_id() {
+        let slots = reply_slots(1, 8);
+        let slot = slots.acquire().unwrap();
+
+        // Nothing is expected yet
+        assert!(!slots.reply(slot, 1, &[1]));
+
+        slots.expect_reply(slot, 2);
+        assert!(!slots.reply(slot, 1, &[1]));
+
+        let server = thread::spawn(move || assert!(slots.reply(slot, 2, &[2, 3])));
+        let mut reply = Vec::new();
+        slots
+            .wait_reply(slot, None, |data| reply = data.to_vec())
+            .unwrap();
+        server.join().unwrap();
+        assert_eq!(reply, [2, 3]);
+
+        // A late reply is discarded
+        slots.expect_reply(slot, 3);
+        assert_eq!(
+            slots.wait_reply(slot, Some(Duration::from_millis(10)), |_| {}),
+            Err(ReplyError::TimedOut)
+        );
+        assert!(!slots.reply(slot, 3, &[4]));
+
+        slots.close();
+        slots.expect_reply(slot, 4);
+        assert_eq!(
+            slots.wait_reply(slot, None, |_| {}),
+            Err(ReplyError::Closed)
+        );
+    }
+}
@@ -0,0 +1,463 @@
+//! Synchronization primitives matching the `threading` module, each one living in its own segment.
+//! Their state is protected by a [`SharedMutex`], which is only held for short periods,
+//! waiting is done on [`SharedCondvar`]s, so that every wait can have a timeout.
+//! A timeout of zero makes an operation non-blocking.
+
+use crate::primitives::condvar::SharedCondvar;
+use crate::primitives::memory_holder::{SegmentFields, SegmentKind, SlicePtrCast};
+use crate::primitives::mutex::{SharedMutex, SharedMutexGuard};
+use std::time::Duration;
+
+fn wait_while<'a, T: ?Sized>(
+    condvar: &SharedCondvar,
+    guard: SharedMutexGuard<'a, T>,
+    timeout: Option<Duration>,
+    condition: impl FnMut(&T) -> bool,
+) -> (SharedMutexGuard<'a, T>, bool) {
+    match timeout {
+        Some(timeout) => condvar.wait_while_timeout(guard, timeout, condition),
+        None => (condvar.wait_while(guard, condition), false),
+    }
+}
+
+macro_rules! impl_slice_ptr_cast {
+    ($($t:ty => $kind:ident),*) => {
+        $(impl SlicePtrCast for $t {
+            const KIND: SegmentKind = SegmentKind::$kind;
+
+            fn cast_from_slice_ptr(slice_ptr: *mut [u8]) -> *const Self {
+                slice_ptr as *const u8 as *const Self
+            }
+        })*
+    };
+}
+
+impl_slice_ptr_cast!(
+    SharedLock => Lock,
+    SharedCondition => Condition,
+    SharedSemaphore => Semaphore,
+    SharedEvent => Event,
+    SharedBarrier => Barrier
+);
+
+/// Unlike a [`SharedMutex`], it is not tied to a guard and can be released by any process
+#[repr(C)]
+#[derive(Default)]
+pub(crate) struct SharedLock {
+    released: SharedCondvar,
+    locked: SharedMutex<bool>,
+}
+
+impl SharedLock {
+    /// Returns false if the lock could not be acquired before the timeout elapsed
+    pub(crate) fn acquire(&self, timeout: Option<Duration>) -> bool {
+        let locked = self.locked.lock();
+        let (mut locked, timed_out) = wait_while(&self.released, locked, timeout, |locked| *locked);
+        if timed_out {
+            return false;
+        }
+
+        *locked = true;
+        true
+    }
+
+    /// Returns false if the lock was not locked
+    pub(crate) fn release(&self) -> bool {
+        let mut locked = self.locked.lock();
+        if !*locked {
+            return false;
+        }
+
+        *locked = false;
+        self.released.notify_one();
+        true
+    }
+
+    pub(crate) fn is_locked(&self) -> bool {
+        *self.locked.lock()
+    }
+
+    pub(crate) fn describe(&self) -> SegmentFields {
+        let locked = unsafe { self.locked.get_unlocked() };
+
+        vec![
+            ("locked", locked.to_string()),
+            ("lock", self.locked.describe_lock()),
+        ]
+    }
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct ConditionState {
+    locked: bool,
+    waiters: u32,
+    /// Number of waiters that were notified, but have not woken up yet
+    signals: u32,
+}
+
+/// A condition variable with its own lock
+#[repr(C)]
+#[derive(Default)]
+pub(crate) struct SharedCondition {
+    released: SharedCondvar,
+    notified: SharedCondvar,
+    state: SharedMutex<ConditionState>,
+}
+
+impl SharedCondition {
+    pub(crate) fn acquire(&self, timeout: Option<Duration>) -> bool {
+        let state = self.state.lock();
+        let (mut state, timed_out) =
+            wait_while(&self.released, state, timeout, |state| state.locked);
+        if timed_out {
+            return false;
+        }
+
+        state.locked = true;
+        true
+    }
+
+    pub(crate) fn release(&self) -> bool {
+        let mut state = self.state.lock();
+        if !state.locked {
+            return false;
+        }
+
+        state.locked = false;
+        self.released.notify_one();
+        true
+    }
+
+    /// Releases the lock, waits to be notified and acquires the lock again,
+    /// returns false if the timeout elapsed before being notified, or None if the lock was not held
+    pub(crate) fn wait(&self, timeout: Option<Duration>) -> Option<bool> {
+        let mut state = self.state.lock();
+        if !state.locked {
+            return None;
+        }
+
+        state.locked = false;
+        state.waiters += 1;
+        self.released.notify_one();
+
+        let (mut state, _) = wait_while(&self.notified, state, timeout, |state| state.signals == 0);
+        let notified = state.signals != 0;
+        if notified {
+            state.signals -= 1;
+        }
+        state.waiters -= 1;
+
+        let mut state = self.released.wait_while(state, |state| state.locked);
+        state.locked = true;
+        Some(notified)
+    }
+
+    /// Wakes up at most `count` waiters, returns false if the lock was not held
+    pub(crate) fn notify(&self, count: u32) -> bool {
+        let mut state = self.state.lock();
+        if !state.locked {
+            return false;
+        }
+
+        let unsignaled = state.waiters - state.signals;
+        state.signals += count.min(unsignaled);
+        self.notified.notify_all();
+        true
+    }
+
+    pub(crate) fn notify_all(&self) -> bool {
+        self.notify(u32::MAX)
+    }
+
+    pub(crate) fn describe(&self) -> SegmentFields {
+        let state = unsafe { self.state.get_unlocked() };
+
+        vec![
+            ("locked", state.locked.to_string()),
+            ("waiters", state.waiters.to_string()),
+            ("notified", state.signals.to_string()),
+            ("lock", self.state.describe_lock()),
+        ]
+    }
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub(crate) struct SharedSemaphore {
+    released: SharedCondvar,
+    value: SharedMutex<u32>,
+}
+
+impl SharedSemaphore {
+    pub(crate) fn init(&self, value: u32) {
+        *self.value.lock() = value;
+    }
+
+    pub(crate) fn acquire(&self, timeout: Option<Duration>) -> bool {
+        let value = self.value.lock();
+        let (mut value, timed_out) =
+            wait_while(&self.released, value, timeout, |value| *value == 0);
+        if timed_out {
+            return false;
+        }
+
+        *value -= 1;
+        true
+    }
+
+    pub(crate) fn release(&self, count: u32) {
+        let mut value = self.value.lock();
+
+        *value += count;
+        self.released.notify_all();
+    }
+
+    pub(crate) fn describe(&self) -> SegmentFields {
+        let value = unsafe { self.value.get_unlocked() };
+
+        vec![
+            ("value", value.to_string()),
+            ("lock", self.value.describe_lock()),
+        ]
+    }
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub(crate) struct SharedEvent {
+    changed: SharedCondvar,
+    flag: SharedMutex<bool>,
+}
+
+impl SharedEvent {
+    pub(crate) fn is_set(&self) -> bool {
+        *self.flag.lock()
+    }
+
+    pub(crate) fn set(&self) {
+        let mut flag = self.flag.lock();
+
+        *flag = true;
+        self.changed.notify_all();
+    }
+
+    pub(crate) fn clear(&self) {
+        *self.flag.lock() = false;
+    }
+
+    /// Returns the flag once it is set or the timeout elapsed
+    pub(crate) fn wait(&self, timeout: Option<Duration>) -> bool {
+        let flag = self.flag.lock();
+        let (flag, _) = wait_while(&self.changed, flag, timeout, |flag| !*flag);
+
+        *flag
+    }
+
+    pub(crate) fn describe(&self) -> SegmentFields {
+        let flag = unsafe { self.flag.get_unlocked() };
+
+        vec![
+            ("set", flag.to_string()),
+            ("lock", self.flag.describe_lock()),
+        ]
+    }
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct BarrierState {
+    parties: u32,
+    /// Number of parties waiting for the current generation
+    count: u32,
+    /// Incremented every time all the parties have arrived
+    generation: u32,
+    /// Incremented every time the barrier is reset, waking up the waiting parties with an error
+    resets: u32,
+    broken: bool,
+}
+
+/// The barrier is broken
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct BrokenBarrier;
+
+#[repr(C)]
+#[derive(Default)]
+pub(crate) struct SharedBarrier {
+    changed: SharedCondvar,
+    state: SharedMutex<BarrierState>,
+}
+
+impl SharedBarrier {
+    pub(crate) fn init(&self, parties: u32) {
+        self.state.lock().parties = parties;
+    }
+
+    /// Returns the arrival index of this party, between 0 and parties - 1.
+    /// The barrier is broken if the timeout elapses before all the parties have arrived
+    pub(crate) fn wait(&self, timeout: Option<Duration>) -> Result<u32, BrokenBarrier> {
+        let mut state = self.state.lock();
+        if state.broken {
+            return Err(BrokenBarrier);
+        }
+
+        let index = state.count;
+        state.count += 1;
+        if state.count == state.parties {
+            state.count = 0;
+            state.generation = state.generation.wrapping_add(1);
+            self.changed.notify_all();
+            return Ok(index);
+        }
+
+        let (generation, resets) = (state.generation, state.resets);
+        let (mut state, timed_out) = wait_while(&self.changed, state, timeout, |state| {
+            state.generation == generation && state.resets == resets && !state.broken
+        });
+
+        if state.generation != generation {
+            Ok(index)
+        } else {
+            if timed_out && state.resets == resets {
+                state.broken = true;
+                self.changed.notify_all();
+            }
+            Err(BrokenBarrier)
+        }
+    }
+
+    /// Returns the barrier to its initial state, the parties currently waiting fail with [`BrokenBarrier`]
+    pub(crate) fn reset(&self) {
+        let mut state = self.state.lock();
+
+        state.count = 0;
+        state.broken = false;
+        state.resets = state.resets.wrapping_add(1);
+        self.changed.notify_all();
+    }
+
+    /// Breaks the barrier, the current and future calls to [`Self::wait`] fail until it is reset
+    pub(crate) fn abort(&self) {
+        let mut state = self.state.lock();
+
+        state.broken = true;
+        self.changed.notify_all();
+    }
+
+    pub(crate) fn parties(&self) -> u32 {
+        self.state.lock().parties
+    }
+
+    pub(crate) fn n_waiting(&self) -> u32 {
+        let state = self.state.lock();
+        if state.broken {
+            0
+        } else {
+            state.count
+        }
+    }
+
+    pub(crate) fn is_broken(&self) -> bool {
+        self.state.lock().broken
+    }
+
+    pub(crate) fn describe(&self) -> SegmentFields {
+        let state = unsafe { self.state.get_unlocked() };
+
+        vec![
+            ("parties", state.parties.to_string()),
+            ("waiting", state.count.to_string()),
+            ("broken", state.broken.to_string()),
+            ("lock", self.state.describe_lock()),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::thread;
+
+    const SHORT: Option<Duration> = Some(Duration::from_millis(10));
+
+    #[test]
+    fn lock_and_semaphore() {
+        let lock = SharedLock::default();
+        assert!(lock.acquire(None));
+        assert!(!lock.acquire(Some(Duration::ZERO)));
+        assert!(!lock.acquire(SHORT));
+        assert!(lock.release());
+        assert!(!lock.release());
+
+        let semaphore = SharedSemaphore::default();
+        semaphore.init(2);
+        assert!(semaphore.acquire(None));
+        assert!(semaphore.acquire(None));
+        assert!(!semaphore.acquire(SHORT));
+        semaphore.release(2);
+        assert!(semaphore.acquire(Some(Duration::ZERO)));
+        assert!(semaphore.acquire(Some(Duration::ZERO)));
+    }
+
+    #[test]
+    fn condition_notify() {
+        let condition = SharedCondition::default();
+        let ready = AtomicU32::new(0);
+
+        assert_eq!(condition.wait(None), None);
+        assert!(condition.acquire(None));
+        assert_eq!(condition.wait(SHORT), Some(false));
+        assert!(!condition.acquire(Some(Duration::ZERO)));
+        assert!(condition.release());
+
+        thread::scope(|s| {
+            for _ in 0..3 {
+                s.spawn(|| {
+                    assert!(condition.acquire(None));
+                    ready.fetch_add(1, Ordering::Relaxed);
+                    assert_eq!(condition.wait(None), Some(true));
+                    assert!(condition.release());
+                });
+            }
+
+            while ready.load(Ordering::Relaxed) != 3 {
+                thread::yield_now();
+            }
+            assert!(condition.acquire(None));
+            assert!(condition.notify_all());
+            assert!(condition.release());
+        });
+    }
+
+    #[test]
+    fn event_and_barrier() {
+        let event = SharedEvent::default();
+        assert!(!event.wait(SHORT));
+        event.set();
+        assert!(event.wait(None));
+        event.clear();
+        assert!(!event.is_set());
+
+        let barrier = SharedBarrier::default();
+        barrier.init(3);
+        let mut indices = thread::scope(|s| {
+            let parties = (0..3)
+                .map(|_| s.spawn(|| barrier.wait(None).unwrap()))
+                .collect::<Vec<_>>();
+            parties
+                .into_iter()
+                .map(|party| party.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        indices.sort();
+        assert_eq!(indices, [0, 1, 2]);
+
+        assert_eq!(barrier.wait(SHORT), Err(BrokenBarrier));
+        assert!(barrier.is_broken());
+        assert_eq!(barrier.wait(None), Err(BrokenBarrier));
+        barrier.reset();
+        assert!(!barrier.is_broken());
+        assert_eq!(barrier.n_waiting(), 0);
+    }
+}
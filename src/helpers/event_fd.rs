@@ -0,0 +1,59 @@
+use rustix::event::{eventfd, EventfdFlags};
+use rustix::fd::{AsRawFd, OwnedFd, RawFd};
+use rustix::io::{read, write, Errno};
+
+/// Process local file descriptor that becomes readable once signaled, until it is cleared.
+/// It lets `select`, `epoll` and event loops wait for new messages, which are otherwise only
+/// announced through a futex
+pub struct EventFd(OwnedFd);
+
+impl EventFd {
+    pub fn new() -> std::io::Result<Self> {
+        let fd = eventfd(0, EventfdFlags::CLOEXEC | EventfdFlags::NONBLOCK)?;
+        Ok(Self(fd))
+    }
+
+    pub fn signal(&self) {
+        // Only fails if the counter would overflow, in which case it is already readable
+        let _ = write(&self.0, &1u64.to_ne_bytes());
+    }
+
+    /// Makes the file descriptor not readable again, until the next signal
+    pub fn clear(&self) {
+        let mut counter = [0u8; size_of::<u64>()];
+        match read(&self.0, &mut counter) {
+            Ok(_) | Err(Errno::AGAIN) => {}
+            Err(e) => panic!("Failed to clear eventfd: {e}"),
+        }
+    }
+
+    pub fn raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustix::event::{poll, PollFd, PollFlags};
+
+    fn is_readable(event_fd: &EventFd) -> bool {
+        let mut fds = [PollFd::new(&event_fd.0, PollFlags::IN)];
+        poll(&mut fds, 0).unwrap() == 1
+    }
+
+    #[test]
+    fn signal_and_clear() {
+        let event_fd = EventFd::new().unwrap();
+        assert!(!is_readable(&event_fd));
+
+        event_fd.signal();
+        event_fd.signal();
+        assert!(is_readable(&event_fd));
+
+        event_fd.clear();
+        assert!(!is_readable(&event_fd));
+        // Clearing twice does not block
+        event_fd.clear();
+    }
+}
@@ -0,0 +1,59 @@
+//! Length-prefixed framing, used to pack several queue elements into a single message
+
+use std::mem::size_of;
+
+pub type FrameSizeType = u32;
+
+pub const FRAME_HEADER_SIZE: usize = size_of::<FrameSizeType>();
+
+/// The number of bytes needed to store an element of `len` bytes as a frame
+#[inline]
+pub const fn frame_size(len: usize) -> usize {
+    FRAME_HEADER_SIZE + len
+}
+
+/// Writes the values one after the other into `buffer`, returns the number of bytes used
+pub fn write_frames<'a>(buffer: &mut [u8], values: impl IntoIterator<Item = &'a [u8]>) -> usize {
+    let mut index = 0;
+
+    for value in values {
+        let data_index = index + FRAME_HEADER_SIZE;
+        buffer[index..data_index].copy_from_slice(&(value.len() as FrameSizeType).to_ne_bytes());
+        buffer[data_index..data_index + value.len()].copy_from_slice(value);
+        index = data_index + value.len();
+    }
+
+    index
+}
+
+/// Iterates over the frames previously written with [`write_frames`].
+/// A frame longer than the rest of the buffer, from a corrupt or truncated batch, ends the iteration
+pub fn read_frames(mut buffer: &[u8]) -> impl Iterator<Item = &[u8]> {
+    std::iter::from_fn(move || {
+        if buffer.len() < FRAME_HEADER_SIZE {
+            return None;
+        }
+
+        let (header, rest) = buffer.split_at(FRAME_HEADER_SIZE);
+        let len = FrameSizeType::from_ne_bytes(header.try_into().unwrap()) as usize;
+        let (value, rest) = rest.split_at_checked(len)?;
+        buffer = rest;
+
+        Some(value)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncated_frames() {
+        let values: [&[u8]; 2] = [&[1, 2], &[3, 4, 5]];
+        let mut buffer = vec![0u8; frame_size(2) + frame_size(3)];
+        assert_eq!(write_frames(&mut buffer, values), buffer.len());
+        assert!(read_frames(&buffer).eq(values));
+
+        assert!(read_frames(&buffer[..buffer.len() - 1]).eq([&[1, 2][..]]));
+    }
+}
@@ -1,2 +1,8 @@
 pub mod bytes;
+pub mod event_fd;
+pub mod frames;
+pub mod process;
 pub mod queue_data;
+pub mod record_log;
+pub mod trace;
+pub mod write_buffer;
@@ -0,0 +1,71 @@
+use rustix::process::getpid;
+use std::cell::Cell;
+
+/// A process, identified by its PID and its start time, so that a reused PID is not mistaken for it
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ProcessId {
+    pub pid: u32,
+    /// Clock ticks since boot, as found in /proc/<pid>/stat, or 0 if unknown
+    pub start_time: u64,
+}
+
+impl ProcessId {
+    /// Cached per thread, and computed again when the PID changed, in the child of a `fork`
+    pub fn current() -> Self {
+        thread_local! {
+            static CURRENT: Cell<Option<ProcessId>> = const { Cell::new(None) };
+        }
+
+        let pid = getpid().as_raw_nonzero().get() as u32;
+        CURRENT.with(|current| match current.get() {
+            Some(process) if process.pid == pid => process,
+            _ => {
+                let process = ProcessId {
+                    pid,
+                    start_time: process_start_time(pid).unwrap_or(0),
+                };
+                current.set(Some(process));
+                process
+            }
+        })
+    }
+
+    pub fn is_alive(&self) -> bool {
+        match process_start_time(self.pid) {
+            Some(start_time) => self.start_time == 0 || start_time == self.start_time,
+            None => false,
+        }
+    }
+}
+
+/// Returns None if the process does not exist
+fn process_start_time(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?;
+    // The command name is between parentheses and can contain spaces,
+    // the start time is the 22nd field, the 20th after the command name
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn current_process_is_alive() {
+        let current = ProcessId::current();
+        assert_ne!(current.start_time, 0);
+        assert!(current.is_alive());
+
+        let restarted = ProcessId {
+            start_time: current.start_time + 1,
+            ..current
+        };
+        assert!(!restarted.is_alive());
+        assert!(!ProcessId {
+            pid: u32::MAX,
+            start_time: 0
+        }
+        .is_alive());
+    }
+}
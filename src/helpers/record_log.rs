@@ -0,0 +1,484 @@
+//! File format of the recordings, see [`crate::record`].
+//!
+//! A header describing the recorded channels, the records one after the other,
+//! then an index of the records and a trailer pointing to it.
+//! The index is written when the recording is finished, if it is missing because the recorder crashed,
+//! it is rebuilt by scanning the records. All the integers are little endian.
+//!
+//! The channels are recorded by separate threads, so the records are not written in the order of
+//! their timestamps. The index is sorted by timestamp, and so are the positions of the records.
+
+use crate::container::message::MessageInfo;
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+const LOG_MAGIC: [u8; 8] = *b"RIPCLOG\0";
+const INDEX_MAGIC: [u8; 8] = *b"RIPCIDX\0";
+const FORMAT_VERSION: u32 = 1;
+
+// 64 - magic
+// 32 - format version
+// 32 - channel count
+const FILE_HEADER_SIZE: usize = 16;
+
+// 8 - kind
+// 8 - checksum
+// 16 - name length
+// 32 - max payload size
+// N - name
+const CHANNEL_HEADER_SIZE: usize = 8;
+
+// 32 - channel
+// 32 - flags
+// 64 - version
+// 64 - monotonic_ns
+// 64 - realtime_ns
+// 32 - writer_pid
+// 32 - writer_tid
+// 64 - payload size
+// N - payload
+const RECORD_HEADER_SIZE: usize = 48;
+
+// 64 - offset of the record
+// 64 - monotonic_ns of the record
+const INDEX_ENTRY_SIZE: usize = 16;
+
+// 64 - offset of the index
+// 64 - record count
+// 64 - magic
+const TRAILER_SIZE: usize = 24;
+
+/// The channel was closed, the record has no payload
+const FLAG_CLOSED: u32 = 1 << 0;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ChannelKind {
+    /// A `SharedMessage`, every record is the whole message
+    Message = 0,
+    /// A `SharedQueue`, every record is a batch of elements packed as frames
+    Queue = 1,
+}
+
+impl ChannelKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ChannelKind::Message => "message",
+            ChannelKind::Queue => "queue",
+        }
+    }
+
+    fn from_raw(raw: u8) -> Option<Self> {
+        match raw {
+            0 => Some(ChannelKind::Message),
+            1 => Some(ChannelKind::Queue),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelInfo {
+    pub name: String,
+    pub kind: ChannelKind,
+    /// Size of the payload of the segment, so that it can be created again with the same size
+    pub max_size: usize,
+    pub checksum: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Index of the channel in the header of the log
+    pub channel: usize,
+    pub info: MessageInfo,
+    /// The channel was closed after the previous record, `payload` is empty
+    pub closed: bool,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct IndexEntry {
+    offset: u64,
+    monotonic_ns: u64,
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+pub struct LogWriter {
+    file: BufWriter<File>,
+    offset: u64,
+    index: Vec<IndexEntry>,
+}
+
+impl LogWriter {
+    pub fn create(path: impl AsRef<Path>, channels: &[ChannelInfo]) -> std::io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        let mut header = Vec::with_capacity(FILE_HEADER_SIZE);
+        header.extend_from_slice(&LOG_MAGIC);
+        header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        header.extend_from_slice(&(channels.len() as u32).to_le_bytes());
+
+        for channel in channels {
+            let name_len = u16::try_from(channel.name.len())
+                .map_err(|_| Error::new(ErrorKind::InvalidInput, "The channel name is too long"))?;
+            header.push(channel.kind as u8);
+            header.push(channel.checksum as u8);
+            header.extend_from_slice(&name_len.to_le_bytes());
+            header.extend_from_slice(&(channel.max_size as u32).to_le_bytes());
+            header.extend_from_slice(channel.name.as_bytes());
+        }
+        file.write_all(&header)?;
+
+        Ok(Self {
+            file,
+            offset: header.len() as u64,
+            index: Vec::new(),
+        })
+    }
+
+    pub fn append(&mut self, record: &Record) -> std::io::Result<()> {
+        let flags = match record.closed {
+            true => FLAG_CLOSED,
+            false => 0,
+        };
+
+        let mut header = [0u8; RECORD_HEADER_SIZE];
+        header[0..4].copy_from_slice(&(record.channel as u32).to_le_bytes());
+        header[4..8].copy_from_slice(&flags.to_le_bytes());
+        header[8..16].copy_from_slice(&(record.info.version as u64).to_le_bytes());
+        header[16..24].copy_from_slice(&record.info.monotonic_ns.to_le_bytes());
+        header[24..32].copy_from_slice(&record.info.realtime_ns.to_le_bytes());
+        header[32..36].copy_from_slice(&record.info.writer_pid.to_le_bytes());
+        header[36..40].copy_from_slice(&record.info.writer_tid.to_le_bytes());
+        header[40..48].copy_from_slice(&(record.payload.len() as u64).to_le_bytes());
+
+        self.file.write_all(&header)?;
+        self.file.write_all(&record.payload)?;
+
+        self.index.push(IndexEntry {
+            offset: self.offset,
+            monotonic_ns: record.info.monotonic_ns,
+        });
+        self.offset += (RECORD_HEADER_SIZE + record.payload.len()) as u64;
+        Ok(())
+    }
+
+    /// Writes the index and flushes the file, returns the number of records
+    pub fn finish(mut self) -> std::io::Result<usize> {
+        self.index.sort_by_key(|entry| entry.monotonic_ns);
+        for entry in &self.index {
+            self.file.write_all(&entry.offset.to_le_bytes())?;
+            self.file.write_all(&entry.monotonic_ns.to_le_bytes())?;
+        }
+
+        self.file.write_all(&self.offset.to_le_bytes())?;
+        self.file
+            .write_all(&(self.index.len() as u64).to_le_bytes())?;
+        self.file.write_all(&INDEX_MAGIC)?;
+
+        self.file
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        Ok(self.index.len())
+    }
+}
+
+pub struct LogReader {
+    file: File,
+    file_size: u64,
+    channels: Vec<ChannelInfo>,
+    index: Vec<IndexEntry>,
+}
+
+impl LogReader {
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = File::open(path)?;
+        let file_size = file.metadata()?.len();
+
+        let mut header = [0u8; FILE_HEADER_SIZE];
+        file.read_exact_at(&mut header, 0)
+            .map_err(|_| invalid("Not a ripc recording"))?;
+        if header[0..8] != LOG_MAGIC {
+            return Err(invalid("Not a ripc recording"));
+        }
+        if u32_at(&header, 8) != FORMAT_VERSION {
+            return Err(invalid(
+                "The recording was made by an incompatible version of ripc",
+            ));
+        }
+
+        let mut offset = FILE_HEADER_SIZE as u64;
+        let mut channels = Vec::new();
+        for _ in 0..u32_at(&header, 12) {
+            let mut channel = [0u8; CHANNEL_HEADER_SIZE];
+            file.read_exact_at(&mut channel, offset)?;
+            let mut name = vec![0u8; u16::from_le_bytes([channel[2], channel[3]]) as usize];
+            file.read_exact_at(&mut name, offset + CHANNEL_HEADER_SIZE as u64)?;
+
+            channels.push(ChannelInfo {
+                name: String::from_utf8(name).map_err(|_| invalid("Invalid channel name"))?,
+                kind: ChannelKind::from_raw(channel[0])
+                    .ok_or_else(|| invalid("Unknown channel kind"))?,
+                max_size: u32_at(&channel, 4) as usize,
+                checksum: channel[1] != 0,
+            });
+            offset += (CHANNEL_HEADER_SIZE + channels.last().unwrap().name.len()) as u64;
+        }
+
+        let index = match Self::read_index(&file, file_size)? {
+            Some(index) => index,
+            None => Self::scan_records(&file, offset, file_size)?,
+        };
+
+        Ok(Self {
+            file,
+            file_size,
+            channels,
+            index,
+        })
+    }
+
+    /// Returns None if the trailer is missing
+    fn read_index(file: &File, file_size: u64) -> std::io::Result<Option<Vec<IndexEntry>>> {
+        if file_size < (FILE_HEADER_SIZE + TRAILER_SIZE) as u64 {
+            return Ok(None);
+        }
+
+        let mut trailer = [0u8; TRAILER_SIZE];
+        file.read_exact_at(&mut trailer, file_size - TRAILER_SIZE as u64)?;
+        if trailer[16..24] != INDEX_MAGIC {
+            return Ok(None);
+        }
+
+        let index_offset = u64_at(&trailer, 0);
+        let index_size = u64_at(&trailer, 8)
+            .checked_mul(INDEX_ENTRY_SIZE as u64)
+            .filter(|size| {
+                index_offset
+                    .checked_add(*size)
+                    .is_some_and(|end| end <= file_size - TRAILER_SIZE as u64)
+            })
+            .ok_or_else(|| invalid("The index is larger than the recording"))?;
+        let mut entries = vec![0u8; index_size as usize];
+        file.read_exact_at(&mut entries, index_offset)?;
+
+        Ok(Some(
+            entries
+                .chunks_exact(INDEX_ENTRY_SIZE)
+                .map(|entry| IndexEntry {
+                    offset: u64_at(entry, 0),
+                    monotonic_ns: u64_at(entry, 8),
+                })
+                .collect(),
+        ))
+    }
+
+    /// Rebuilds the index of a recording that was not finished, a truncated last record is ignored
+    fn scan_records(
+        file: &File,
+        mut offset: u64,
+        file_size: u64,
+    ) -> std::io::Result<Vec<IndexEntry>> {
+        let mut index = Vec::new();
+        let mut header = [0u8; RECORD_HEADER_SIZE];
+
+        while offset + RECORD_HEADER_SIZE as u64 <= file_size {
+            file.read_exact_at(&mut header, offset)?;
+            let end = (offset + RECORD_HEADER_SIZE as u64).checked_add(u64_at(&header, 40));
+            let Some(end) = end.filter(|end| *end <= file_size) else {
+                break;
+            };
+
+            index.push(IndexEntry {
+                offset,
+                monotonic_ns: u64_at(&header, 16),
+            });
+            offset = end;
+        }
+
+        index.sort_by_key(|entry| entry.monotonic_ns);
+        Ok(index)
+    }
+
+    pub fn channels(&self) -> &[ChannelInfo] {
+        &self.channels
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// The monotonic timestamp of the record, without reading it
+    pub fn monotonic_ns(&self, position: usize) -> u64 {
+        self.index[position].monotonic_ns
+    }
+
+    /// The position of the first record written at or after the monotonic timestamp
+    pub fn position_at(&self, monotonic_ns: u64) -> usize {
+        self.index
+            .partition_point(|entry| entry.monotonic_ns < monotonic_ns)
+    }
+
+    pub fn read(&self, position: usize) -> std::io::Result<Record> {
+        let offset = self.index[position].offset;
+
+        let mut header = [0u8; RECORD_HEADER_SIZE];
+        self.file.read_exact_at(&mut header, offset)?;
+        let payload_size = u64_at(&header, 40);
+        if payload_size
+            > self
+                .file_size
+                .saturating_sub(offset + RECORD_HEADER_SIZE as u64)
+        {
+            return Err(invalid("The record is larger than the recording"));
+        }
+        let mut payload = vec![0u8; payload_size as usize];
+        self.file
+            .read_exact_at(&mut payload, offset + RECORD_HEADER_SIZE as u64)?;
+
+        let channel = u32_at(&header, 0) as usize;
+        if channel >= self.channels.len() {
+            return Err(invalid("The record belongs to an unknown channel"));
+        }
+
+        Ok(Record {
+            channel,
+            info: MessageInfo {
+                version: u64_at(&header, 8) as usize,
+                monotonic_ns: u64_at(&header, 16),
+                realtime_ns: u64_at(&header, 24),
+                writer_pid: u32_at(&header, 32),
+                writer_tid: u32_at(&header, 36),
+            },
+            closed: u32_at(&header, 4) & FLAG_CLOSED != 0,
+            payload,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(channel: usize, version: usize, payload: &[u8]) -> Record {
+        Record {
+            channel,
+            info: MessageInfo {
+                version,
+                monotonic_ns: version as u64 * 1000,
+                realtime_ns: 7,
+                writer_pid: 1,
+                writer_tid: 2,
+            },
+            closed: false,
+            payload: payload.to_vec(),
+        }
+    }
+
+    #[test]
+    fn write_and_read_log() {
+        let path = std::env::temp_dir().join("ripc_write_and_read_log");
+        let channels = [
+            ChannelInfo {
+                name: "/message".to_string(),
+                kind: ChannelKind::Message,
+                max_size: 64,
+                checksum: true,
+            },
+            ChannelInfo {
+                name: "/queue".to_string(),
+                kind: ChannelKind::Queue,
+                max_size: 128,
+                checksum: false,
+            },
+        ];
+        let records = [
+            record(0, 1, &[1, 2, 3]),
+            record(1, 2, &[]),
+            Record {
+                closed: true,
+                ..record(0, 3, &[])
+            },
+        ];
+
+        let mut writer = LogWriter::create(&path, &channels).unwrap();
+        for record in &records[..2] {
+            writer.append(record).unwrap();
+        }
+        // Not finished yet, the index is rebuilt from the records
+        writer.file.flush().unwrap();
+        let reader = LogReader::open(&path).unwrap();
+        assert_eq!(reader.channels(), channels);
+        assert_eq!(reader.len(), 2);
+
+        writer.append(&records[2]).unwrap();
+        assert_eq!(writer.finish().unwrap(), 3);
+
+        let reader = LogReader::open(&path).unwrap();
+        assert_eq!(reader.len(), 3);
+        for (position, record) in records.iter().enumerate() {
+            assert_eq!(&reader.read(position).unwrap(), record);
+        }
+        assert_eq!(reader.position_at(1500), 1);
+        assert_eq!(reader.position_at(5000), 3);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn records_sorted_by_timestamp() {
+        let path = std::env::temp_dir().join("ripc_records_sorted_by_timestamp");
+        let channels = [0, 1].map(|channel| ChannelInfo {
+            name: format!("/channel{channel}"),
+            kind: ChannelKind::Message,
+            max_size: 64,
+            checksum: false,
+        });
+        // Appended by the thread of each channel, out of order
+        let records = [record(0, 3, &[3]), record(1, 1, &[1]), record(0, 2, &[2])];
+
+        let mut writer = LogWriter::create(&path, &channels).unwrap();
+        for record in &records {
+            writer.append(record).unwrap();
+        }
+        writer.file.flush().unwrap();
+        let scanned = LogReader::open(&path).unwrap();
+        assert_eq!(writer.finish().unwrap(), 3);
+        let indexed = LogReader::open(&path).unwrap();
+
+        for reader in [scanned, indexed] {
+            let versions = (0..reader.len())
+                .map(|position| reader.read(position).unwrap().info.version)
+                .collect::<Vec<_>>();
+            assert_eq!(versions, [1, 2, 3]);
+            assert_eq!(reader.position_at(1500), 1);
+        }
+
+        // A record count larger than the file is rejected, instead of being allocated
+        let file = File::options().write(true).open(&path).unwrap();
+        let file_size = file.metadata().unwrap().len();
+        file.write_all_at(&u64::MAX.to_le_bytes(), file_size - 16)
+            .unwrap();
+        let error = LogReader::open(&path).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,20 @@
+//! Spans and events emitted through the `tracing` crate when the `tracing` feature is enabled.
+//! Without the feature the macros expand to nothing, and their arguments are not evaluated
+
+/// Enters a trace span until the end of the enclosing block
+macro_rules! trace_span {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!($($arg)*).entered();
+    };
+}
+
+/// Emits a trace event
+macro_rules! trace_event {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::trace!($($arg)*);
+    };
+}
+
+pub(crate) use {trace_event, trace_span};
@@ -0,0 +1,205 @@
+use std::collections::VecDeque;
+use std::num::NonZeroUsize;
+use std::sync::{Condvar, Mutex, MutexGuard};
+
+/// In-process buffer between the threads producing elements and the thread writing them to shared memory
+pub struct WriteBuffer<T> {
+    state: Mutex<WriteBufferState<T>>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: Option<NonZeroUsize>,
+}
+
+struct WriteBufferState<T> {
+    items: VecDeque<T>,
+    closed: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PushError {
+    Full,
+    Closed,
+}
+
+impl<T> WriteBuffer<T> {
+    /// Creates a buffer holding at most `capacity` elements, or an unbounded one if `None`
+    pub fn new(capacity: Option<NonZeroUsize>) -> Self {
+        Self {
+            state: Mutex::new(WriteBufferState {
+                items: VecDeque::new(),
+                closed: false,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            capacity,
+        }
+    }
+
+    /// Waits for room as needed, until all the items have been added
+    pub fn push_blocking(&self, items: Vec<T>) -> Result<(), PushError> {
+        let mut state = self.lock()?;
+
+        for item in items {
+            while self.free_space(&state) == 0 {
+                state = self.not_full.wait(state).unwrap();
+                if state.closed {
+                    return Err(PushError::Closed);
+                }
+            }
+            state.items.push_back(item);
+            self.not_empty.notify_one();
+        }
+
+        Ok(())
+    }
+
+    /// Adds all the items, or none of them if they don't all fit
+    pub fn try_push(&self, items: Vec<T>) -> Result<(), PushError> {
+        let mut state = self.lock()?;
+        if self.free_space(&state) < items.len() {
+            return Err(PushError::Full);
+        }
+
+        state.items.extend(items);
+        self.not_empty.notify_one();
+        Ok(())
+    }
+
+    /// Adds all the items, discarding the oldest pending ones to make room
+    pub fn push_drop_oldest(&self, items: Vec<T>) -> Result<(), PushError> {
+        let mut state = self.lock()?;
+
+        state.items.extend(items);
+        if let Some(capacity) = self.capacity {
+            let excess = state.items.len().saturating_sub(capacity.get());
+            state.items.drain(..excess);
+        }
+        self.not_empty.notify_one();
+        Ok(())
+    }
+
+    /// Adds as many items as fit, discarding the rest
+    pub fn push_drop_newest(&self, items: Vec<T>) -> Result<(), PushError> {
+        let mut state = self.lock()?;
+
+        let free_space = self.free_space(&state);
+        state.items.extend(items.into_iter().take(free_space));
+        self.not_empty.notify_one();
+        Ok(())
+    }
+
+    /// Waits until at least one item is available, then removes items while `accept` returns true,
+    /// the first item is always removed.
+    /// Returns None once the buffer has been closed and all the items have been removed
+    pub fn pop_while(&self, mut accept: impl FnMut(&T) -> bool) -> Option<Vec<T>> {
+        let mut state = self.state.lock().unwrap();
+        while state.items.is_empty() {
+            if state.closed {
+                return None;
+            }
+            state = self.not_empty.wait(state).unwrap();
+        }
+
+        let mut batch = vec![state.items.pop_front().unwrap()];
+        while let Some(item) = state.items.pop_front() {
+            if !accept(&item) {
+                state.items.push_front(item);
+                break;
+            }
+            batch.push(item);
+        }
+
+        self.not_full.notify_all();
+        Some(batch)
+    }
+
+    pub fn capacity(&self) -> Option<NonZeroUsize> {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().items.len()
+    }
+
+    /// Wakes up all the waiting threads and rejects any new items
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, WriteBufferState<T>>, PushError> {
+        let state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(PushError::Closed);
+        }
+        Ok(state)
+    }
+
+    fn free_space(&self, state: &WriteBufferState<T>) -> usize {
+        match self.capacity {
+            Some(capacity) => capacity.get().saturating_sub(state.items.len()),
+            None => usize::MAX,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn buffer(capacity: usize) -> WriteBuffer<u32> {
+        WriteBuffer::new(NonZeroUsize::new(capacity))
+    }
+
+    fn pop_all(buffer: &WriteBuffer<u32>) -> Vec<u32> {
+        buffer.pop_while(|_| true).unwrap()
+    }
+
+    #[test]
+    fn overflow_policies() {
+        let raise = buffer(3);
+        raise.try_push(vec![1, 2]).unwrap();
+        assert_eq!(raise.try_push(vec![3, 4]), Err(PushError::Full));
+        raise.try_push(vec![3]).unwrap();
+        assert_eq!(pop_all(&raise), [1, 2, 3]);
+
+        let drop_oldest = buffer(3);
+        drop_oldest.push_drop_oldest(vec![1, 2]).unwrap();
+        drop_oldest.push_drop_oldest(vec![3, 4, 5]).unwrap();
+        assert_eq!(drop_oldest.len(), 3);
+        assert_eq!(pop_all(&drop_oldest), [3, 4, 5]);
+
+        let drop_newest = buffer(3);
+        drop_newest.push_drop_newest(vec![1, 2]).unwrap();
+        drop_newest.push_drop_newest(vec![3, 4, 5]).unwrap();
+        assert_eq!(pop_all(&drop_newest), [1, 2, 3]);
+
+        let unbounded = WriteBuffer::new(None);
+        unbounded.try_push((0..1000).collect()).unwrap();
+        assert_eq!(unbounded.len(), 1000);
+    }
+
+    #[test]
+    fn blocking_push_and_close() {
+        let buffer = Arc::new(buffer(2));
+
+        let consumer = {
+            let buffer = buffer.clone();
+            thread::spawn(move || {
+                let mut read = Vec::new();
+                while let Some(batch) = buffer.pop_while(|_| true) {
+                    read.extend(batch);
+                }
+                read
+            })
+        };
+
+        buffer.push_blocking((0..100).collect()).unwrap();
+        buffer.close();
+        assert_eq!(buffer.push_blocking(vec![0]), Err(PushError::Closed));
+        assert_eq!(consumer.join().unwrap(), (0..100).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,404 @@
+//! Inspection and management of the segments created by this library, used by the `ripc` command line tool.
+//! Listing, describing and cleaning up segments does not take their locks,
+//! so that it can be used while a process is stuck holding one.
+//! `tail`, `dump` and `close` go through the locks like any reader or writer, and block while they are held.
+
+use crate::container::byte_ring::ByteRing;
+use crate::container::circular_queue::CircularQueue;
+use crate::container::message::SharedMessage;
+use crate::container::priority_queue::PriorityQueue;
+use crate::container::reply_slots::ReplySlots;
+use crate::container::sync::{
+    SharedBarrier, SharedCondition, SharedEvent, SharedLock, SharedSemaphore,
+};
+use crate::helpers::process::ProcessId;
+use crate::primitives::memory_holder::{SegmentHeader, SharedMemoryHolder, SlicePtrCast};
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub use crate::container::message::MessageInfo;
+pub use crate::primitives::latency::LatencySummary;
+pub use crate::primitives::memory_holder::{SegmentFields, SegmentKind};
+
+/// Where Linux stores the POSIX shared memory objects
+const SHM_DIRECTORY: &str = "/dev/shm";
+
+/// How often `tail` checks if the message was closed
+const TAIL_TIMEOUT: Duration = Duration::from_millis(500);
+
+pub struct SegmentSummary {
+    /// Name to use with the other functions, starting with a '/'
+    pub name: String,
+    pub kind: SegmentKind,
+    /// Size of the file, including the header
+    pub size: u64,
+}
+
+fn shm_path(name: &str) -> PathBuf {
+    Path::new(SHM_DIRECTORY).join(name.trim_start_matches('/'))
+}
+
+/// Returns the header, its kind and the size of the file
+fn read_header(path: &Path) -> std::io::Result<(SegmentHeader, SegmentKind, u64)> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let header = SegmentHeader::read_from(&mut file)?;
+    let kind = header.kind()?;
+
+    Ok((header, kind, size))
+}
+
+fn open<T: SlicePtrCast + ?Sized>(name: &str) -> std::io::Result<SharedMemoryHolder<T>> {
+    unsafe { SharedMemoryHolder::open(CString::new(name)?) }
+}
+
+/// Returns the segments created by this library, sorted by name.
+/// The other files and the files that cannot be read are skipped
+pub fn list_segments() -> std::io::Result<Vec<SegmentSummary>> {
+    let mut segments = Vec::new();
+
+    for entry in std::fs::read_dir(SHM_DIRECTORY)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let Ok((_, kind, size)) = read_header(&entry.path()) else {
+            continue;
+        };
+
+        segments.push(SegmentSummary {
+            name: format!("/{}", entry.file_name().to_string_lossy()),
+            kind,
+            size,
+        });
+    }
+
+    segments.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(segments)
+}
+
+pub fn segment_kind(name: &str) -> std::io::Result<SegmentKind> {
+    read_header(&shm_path(name)).map(|(_, kind, _)| kind)
+}
+
+/// Describes the state of the segment: sizes, versions, closed flag, lock state and waiters
+pub fn describe(name: &str) -> std::io::Result<SegmentFields> {
+    let (header, kind, size) = read_header(&shm_path(name))?;
+    let users = header.users();
+    let mut fields = vec![
+        ("kind", kind.name().to_string()),
+        ("size", size.to_string()),
+        ("creator", describe_process(header.creator())),
+        (
+            "users",
+            match users.is_empty() {
+                true => "none".to_string(),
+                false => users
+                    .into_iter()
+                    .map(describe_process)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            },
+        ),
+        (
+            "handles without a user slot",
+            header.overflowed_handles().to_string(),
+        ),
+    ];
+
+    fields.extend(match kind {
+        SegmentKind::Message => {
+            let message = open::<SharedMessage>(name)?;
+            let max_size = message.mapped_memory_size() - SharedMessage::size_of_fields();
+            let mut fields = message.describe();
+            fields.insert(0, ("max message size", max_size.to_string()));
+            fields
+        }
+        SegmentKind::CircularQueue => open::<CircularQueue>(name)?.describe(),
+        SegmentKind::PriorityQueue => open::<PriorityQueue>(name)?.describe(),
+        SegmentKind::ByteRing => open::<ByteRing>(name)?.describe(),
+        SegmentKind::ReplySlots => open::<ReplySlots>(name)?.describe(),
+        SegmentKind::Lock => open::<SharedLock>(name)?.describe(),
+        SegmentKind::Condition => open::<SharedCondition>(name)?.describe(),
+        SegmentKind::Semaphore => open::<SharedSemaphore>(name)?.describe(),
+        SegmentKind::Event => open::<SharedEvent>(name)?.describe(),
+        SegmentKind::Barrier => open::<SharedBarrier>(name)?.describe(),
+    });
+    if let SegmentKind::Message
+    | SegmentKind::CircularQueue
+    | SegmentKind::PriorityQueue
+    | SegmentKind::ByteRing = kind
+    {
+        fields.extend(
+            header
+                .stats()
+                .values()
+                .into_iter()
+                .map(|(key, value)| (key, value.to_string())),
+        );
+    }
+
+    Ok(fields)
+}
+
+/// Percentiles of the latencies measured by all the readers of a message or a queue, in nanoseconds:
+/// from the write of a message to its read, and the time reads spent waiting for locks
+pub fn latency(name: &str) -> std::io::Result<[(&'static str, LatencySummary); 2]> {
+    let (header, kind, _) = read_header(&shm_path(name))?;
+    if kind != SegmentKind::Message {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Latencies are only measured for messages and queues",
+        ));
+    }
+
+    Ok(header.latency().summaries())
+}
+
+fn describe_process(process: ProcessId) -> String {
+    match process.is_alive() {
+        true => format!("pid {}", process.pid),
+        false => format!("pid {} (dead)", process.pid),
+    }
+}
+
+/// Removes the name of the segment, the processes that have it open can keep using it
+pub fn unlink(name: &str) -> std::io::Result<()> {
+    segment_kind(name)?;
+    rustix::shm::shm_unlink(CString::new(name)?.as_c_str())?;
+    Ok(())
+}
+
+/// Unlinks the segments whose creator and users are all dead, left behind by crashed processes.
+/// Only the segments whose name starts with `prefix` are considered, when it is given.
+/// Returns the names of the stale segments, which are only reported with `dry_run`
+pub fn cleanup(prefix: Option<&str>, dry_run: bool) -> std::io::Result<Vec<String>> {
+    let prefix = prefix.map(|prefix| format!("/{}", prefix.trim_start_matches('/')));
+    let mut removed = Vec::new();
+
+    for segment in list_segments()? {
+        if prefix
+            .as_ref()
+            .is_some_and(|prefix| !segment.name.starts_with(prefix.as_str()))
+        {
+            continue;
+        }
+        // Read again, a process may have opened the segment since it was listed
+        let Ok((header, _, _)) = read_header(&shm_path(&segment.name)) else {
+            continue;
+        };
+        if !header.is_stale() {
+            continue;
+        }
+
+        if !dry_run {
+            match rustix::shm::shm_unlink(CString::new(segment.name.as_str())?.as_c_str()) {
+                // Removed by another process in the meantime
+                Err(rustix::io::Errno::NOENT) => continue,
+                result => result?,
+            }
+        }
+        removed.push(segment.name);
+    }
+
+    Ok(removed)
+}
+
+/// Closes the channel, waking up every process waiting on it
+pub fn close(name: &str) -> std::io::Result<()> {
+    match segment_kind(name)? {
+        SegmentKind::Message => open::<SharedMessage>(name)?.close(),
+        SegmentKind::CircularQueue => open::<CircularQueue>(name)?.close(),
+        SegmentKind::PriorityQueue => open::<PriorityQueue>(name)?.close(),
+        SegmentKind::ByteRing => open::<ByteRing>(name)?.close(),
+        SegmentKind::ReplySlots => open::<ReplySlots>(name)?.close(),
+        kind => {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("Segments of kind {} cannot be closed", kind.name()),
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// Calls `on_message` with the latest message and then with every new version, until the message is closed.
+/// Versions written faster than they can be read are skipped, the readers of the message are not affected.
+/// Corrupt versions are skipped too, and counted in the statistics of the message.
+/// Takes the read lock for every version
+pub fn tail(name: &str, mut on_message: impl FnMut(&MessageInfo, &[u8])) -> std::io::Result<()> {
+    let message = open::<SharedMessage>(name)?;
+    let mut version = 0;
+
+    while !message.is_closed() {
+        if message.wait_for_new_version(version, TAIL_TIMEOUT) == version {
+            continue;
+        }
+
+        let result = message.peek(|info, data| {
+            version = info.version;
+            on_message(info, data);
+        });
+        if let Err(e) = result {
+            message.stats().add_corrupt_read();
+            version = e.version;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the latest message, or None if nothing was written yet.
+/// Takes the read lock, so that the payload is not copied in the middle of a write
+pub fn dump(name: &str) -> std::io::Result<Option<(MessageInfo, Vec<u8>)>> {
+    let message = open::<SharedMessage>(name)?;
+
+    let mut result = None;
+    message
+        .peek(|info, data| result = Some((*info, data.to_vec())))
+        .map_err(|e| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("The message with version {} is corrupt", e.version),
+            )
+        })?;
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::message::MessageOptions;
+    use std::os::unix::fs::FileExt;
+
+    #[test]
+    fn inspect_message() {
+        let name = "/inspect_message";
+        let message = unsafe {
+            SharedMemoryHolder::<SharedMessage>::create(
+                CString::new(name).unwrap(),
+                SharedMessage::size_of_fields() + 16,
+            )
+            .unwrap()
+        };
+        message.init(MessageOptions::default());
+        message.write(&[1, 2, 3]);
+
+        let summary = list_segments().unwrap();
+        let summary = summary.iter().find(|s| s.name == name).unwrap();
+        assert_eq!(summary.kind, SegmentKind::Message);
+
+        let fields = describe(name).unwrap();
+        let field = |key| &fields.iter().find(|(k, _)| *k == key).unwrap().1;
+        assert_eq!(field("kind"), "message");
+        assert_eq!(field("version"), "1");
+        assert_eq!(field("max message size"), "16");
+        assert_eq!(field("lock"), "unlocked");
+        // The raw container writes are not counted, only those of the Python channels
+        assert_eq!(field("writes"), "0");
+
+        let (info, data) = dump(name).unwrap().unwrap();
+        assert_eq!((info.version, data.as_slice()), (1, [1, 2, 3].as_slice()));
+
+        assert!(open::<CircularQueue>(name).is_err());
+        close(name).unwrap();
+        assert!(message.is_closed());
+        // Returns immediately, the message is closed
+        tail(name, |_, _| panic!()).unwrap();
+
+        unlink(name).unwrap();
+        assert!(segment_kind(name).is_err());
+    }
+
+    #[test]
+    fn cleanup_stale_segments() {
+        let name = "/cleanup_stale_segments";
+        let message = unsafe {
+            SharedMemoryHolder::<SharedMessage>::create(
+                CString::new(name).unwrap(),
+                SharedMessage::size_of_fields() + 16,
+            )
+            .unwrap()
+        };
+        message.init(MessageOptions::default());
+
+        let (header, _, _) = read_header(&shm_path(name)).unwrap();
+        assert_eq!(header.creator(), ProcessId::current());
+        assert!(header.users().is_empty());
+        assert!(cleanup(Some("cleanup_stale"), true).unwrap().is_empty());
+
+        // Readers keep the segment alive and unregister when they are dropped
+        let reader = open::<SharedMessage>(name).unwrap();
+        let other_reader = open::<SharedMessage>(name).unwrap();
+        assert_eq!(reader.header().users(), [ProcessId::current()]);
+        drop(reader);
+        assert_eq!(other_reader.header().users(), [ProcessId::current()]);
+        drop(other_reader);
+        assert!(message.header().users().is_empty());
+
+        // Same PID, but started later: the creator is dead.
+        // The start time of the creator follows the magic, the layout version and the kind
+        let start_time = ProcessId::current().start_time + 1;
+        File::options()
+            .write(true)
+            .open(shm_path(name))
+            .unwrap()
+            .write_all_at(&start_time.to_ne_bytes(), 16)
+            .unwrap();
+        assert!(message.header().is_stale());
+
+        assert!(cleanup(Some("other_prefix"), false).unwrap().is_empty());
+        assert_eq!(cleanup(Some("/cleanup_stale"), true).unwrap(), [name]);
+        assert!(segment_kind(name).is_ok());
+        assert_eq!(cleanup(Some("cleanup_stale"), false).unwrap(), [name]);
+        assert!(segment_kind(name).is_err());
+    }
+
+    #[test]
+    fn users_without_a_slot_keep_the_segment() {
+        let name = "/users_without_a_slot";
+        let message = unsafe {
+            SharedMemoryHolder::<SharedMessage>::create(
+                CString::new(name).unwrap(),
+                SharedMessage::size_of_fields() + 16,
+            )
+            .unwrap()
+        };
+        message.init(MessageOptions::default());
+
+        // Every slot is taken by init, which is alive. The 32 slots of 16 bytes
+        // follow the magic, the layout version, the kind, the creator and the overflowed handles
+        let file = File::options().write(true).open(shm_path(name)).unwrap();
+        for slot in 0..32 {
+            let offset = 32 + slot * 16;
+            file.write_all_at(&1u64.to_ne_bytes(), offset).unwrap();
+            file.write_all_at(&1u32.to_ne_bytes(), offset + 8).unwrap();
+        }
+
+        let reader = open::<SharedMessage>(name).unwrap();
+        assert!(!reader.header().users().contains(&ProcessId::current()));
+        assert_eq!(reader.header().overflowed_handles(), 1);
+
+        // The creator is dead, but the reader without a slot still uses the segment
+        let start_time = ProcessId::current().start_time + 1;
+        file.write_all_at(&start_time.to_ne_bytes(), 16).unwrap();
+        assert!(!reader.header().is_stale());
+        assert!(cleanup(Some("users_without_a_slot"), false)
+            .unwrap()
+            .is_empty());
+
+        drop(reader);
+        assert_eq!(message.header().overflowed_handles(), 0);
+        // Only init is left, which is alive
+        assert!(!message.header().is_stale());
+        for slot in 0..32 {
+            file.write_all_at(&0u64.to_ne_bytes(), 32 + slot * 16)
+                .unwrap();
+        }
+        assert!(message.header().is_stale());
+    }
+}
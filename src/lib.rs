@@ -1,4 +1,7 @@
+pub mod bridge;
 mod container;
 mod helpers;
+pub mod inspect;
 mod primitives;
 mod python;
+pub mod record;
@@ -0,0 +1,147 @@
+use crate::primitives::wait_counters::WaitCounters;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+
+// 64 - writes
+// 64 - reads
+// 64 - bytes written
+// 64 - bytes read
+// 64 - blocked writes
+// 64 - blocked reads
+// 64 - lock contentions
+// 64 - max queue depth
+// 64 - corrupt reads
+
+/// Counters shared by every process using the segment, stored in its header.
+/// A queue counts each element, a message each version
+#[repr(C, align(64))]
+#[derive(Default)]
+pub struct ChannelStats {
+    writes: AtomicU64,
+    reads: AtomicU64,
+    bytes_written: AtomicU64,
+    bytes_read: AtomicU64,
+    /// Writes that waited for a reader to make room, or to read the previous version
+    blocked_writes: AtomicU64,
+    /// Reads that waited for a writer
+    blocked_reads: AtomicU64,
+    lock_contentions: AtomicU64,
+    max_queue_depth: AtomicU64,
+    /// Messages whose checksum did not match, skipped by the reader
+    corrupt_reads: AtomicU64,
+}
+
+impl ChannelStats {
+    fn observe<R>(&self, blocked: &AtomicU64, operation: impl FnOnce() -> R) -> R {
+        let counters = WaitCounters::current();
+        let result = operation();
+        let waits = counters.elapsed();
+
+        if waits.condvar_waits != 0 {
+            blocked.fetch_add(1, Relaxed);
+        }
+        if waits.lock_contentions != 0 {
+            self.lock_contentions
+                .fetch_add(waits.lock_contentions, Relaxed);
+        }
+        result
+    }
+
+    /// Runs a write, counting whether it blocked and the lock contentions it met
+    #[inline]
+    pub(crate) fn observe_write<R>(&self, write: impl FnOnce() -> R) -> R {
+        self.observe(&self.blocked_writes, write)
+    }
+
+    /// Runs a read, counting whether it blocked and the lock contentions it met
+    #[inline]
+    pub(crate) fn observe_read<R>(&self, read: impl FnOnce() -> R) -> R {
+        self.observe(&self.blocked_reads, read)
+    }
+
+    #[inline]
+    pub(crate) fn add_writes(&self, count: usize, bytes: usize) {
+        self.writes.fetch_add(count as u64, Relaxed);
+        self.bytes_written.fetch_add(bytes as u64, Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn add_reads(&self, count: usize, bytes: usize) {
+        self.reads.fetch_add(count as u64, Relaxed);
+        self.bytes_read.fetch_add(bytes as u64, Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn add_queue_depth(&self, depth: usize) {
+        self.max_queue_depth.fetch_max(depth as u64, Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn add_corrupt_read(&self) {
+        self.corrupt_reads.fetch_add(1, Relaxed);
+    }
+
+    pub fn values(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("writes", self.writes.load(Relaxed)),
+            ("reads", self.reads.load(Relaxed)),
+            ("bytes_written", self.bytes_written.load(Relaxed)),
+            ("bytes_read", self.bytes_read.load(Relaxed)),
+            ("blocked_writes", self.blocked_writes.load(Relaxed)),
+            ("blocked_reads", self.blocked_reads.load(Relaxed)),
+            ("lock_contentions", self.lock_contentions.load(Relaxed)),
+            ("max_queue_depth", self.max_queue_depth.load(Relaxed)),
+            ("corrupt_reads", self.corrupt_reads.load(Relaxed)),
+        ]
+    }
+
+    pub fn reset(&self) {
+        for counter in [
+            &self.writes,
+            &self.reads,
+            &self.bytes_written,
+            &self.bytes_read,
+            &self.blocked_writes,
+            &self.blocked_reads,
+            &self.lock_contentions,
+            &self.max_queue_depth,
+            &self.corrupt_reads,
+        ] {
+            counter.store(0, Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives::condvar::SharedCondvar;
+    use crate::primitives::mutex::SharedMutex;
+    use std::time::Duration;
+
+    #[test]
+    fn observe_blocked_operations() {
+        let stats = ChannelStats::default();
+        let mutex = SharedMutex::<()>::default();
+        let condvar = SharedCondvar::default();
+
+        stats.observe_write(|| drop(mutex.lock()));
+        stats.observe_read(|| condvar.wait_timeout(mutex.lock(), Duration::from_millis(1)));
+        stats.add_writes(2, 10);
+        stats.add_queue_depth(3);
+        stats.add_queue_depth(1);
+        stats.add_corrupt_read();
+
+        let values = stats.values();
+        let value = |key| values.iter().find(|(k, _)| *k == key).unwrap().1;
+        assert_eq!(value("writes"), 2);
+        assert_eq!(value("bytes_written"), 10);
+        assert_eq!(value("blocked_writes"), 0);
+        assert_eq!(value("blocked_reads"), 1);
+        assert_eq!(value("max_queue_depth"), 3);
+        assert_eq!(value("corrupt_reads"), 1);
+
+        stats.reset();
+        assert!(stats.values().iter().all(|(_, value)| *value == 0));
+    }
+}
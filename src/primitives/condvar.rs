@@ -1,7 +1,17 @@
-use crate::primitives::mutex::{guard_lock, SharedMutexGuard};
-use crate::primitives::shared_futex::SharedFutex;
-use linux_futex::{Futex, Shared};
+use crate::helpers::trace::trace_span;
+use crate::primitives::wait_counters::count_condvar_wait;
+use linux_futex::{Futex, Shared, TimedWaitError};
+use std::ops::Deref;
 use std::sync::atomic::Ordering::Relaxed;
+use std::time::{Duration, Instant};
+
+/// A guard whose lock can be released while waiting on a [`SharedCondvar`]
+pub trait CondvarGuard {
+    /// The guard must not be used until [`Self::relock`] is called
+    unsafe fn unlock(&self);
+
+    fn relock(&self);
+}
 
 #[derive(Default)]
 #[repr(transparent)]
@@ -10,50 +20,120 @@ pub struct SharedCondvar {
 }
 
 impl SharedCondvar {
-    pub fn wait<'a, T: ?Sized>(&self, guard: SharedMutexGuard<'a, T>) -> SharedMutexGuard<'a, T> {
-        let lock = guard_lock(&guard);
+    pub fn wait<G: CondvarGuard>(&self, guard: G) -> G {
         unsafe {
-            self.futex_wait(lock);
+            self.futex_wait(&guard);
         }
         guard
     }
 
-    pub fn wait_while<'a, T: ?Sized, F>(
-        &self,
-        mut guard: SharedMutexGuard<'a, T>,
-        mut condition: F,
-    ) -> SharedMutexGuard<'a, T>
+    pub fn wait_while<G, F>(&self, mut guard: G, mut condition: F) -> G
     where
-        F: FnMut(&mut T) -> bool,
+        G: CondvarGuard + Deref,
+        F: FnMut(&G::Target) -> bool,
     {
-        while condition(&mut *guard) {
+        while condition(&*guard) {
             guard = self.wait(guard);
         }
         guard
     }
 
+    /// Waits for a notification or until the timeout elapses,
+    /// returns true if the timeout elapsed
+    pub fn wait_timeout<G: CondvarGuard>(&self, guard: G, timeout: Duration) -> (G, bool) {
+        let timed_out = unsafe { !self.futex_wait_timeout(&guard, Some(timeout)) };
+        (guard, timed_out)
+    }
+
+    /// Same as [`Self::wait_while`], but gives up once the timeout elapses,
+    /// returns true if the condition was still true when the timeout elapsed
+    pub fn wait_while_timeout<G, F>(
+        &self,
+        mut guard: G,
+        timeout: Duration,
+        mut condition: F,
+    ) -> (G, bool)
+    where
+        G: CondvarGuard + Deref,
+        F: FnMut(&G::Target) -> bool,
+    {
+        let deadline = Instant::now() + timeout;
+        while condition(&*guard) {
+            let now = Instant::now();
+            if now >= deadline {
+                return (guard, true);
+            }
+            guard = self.wait_timeout(guard, deadline - now).0;
+        }
+        (guard, false)
+    }
+
     pub fn notify_one(&self) {
+        // Changing the value makes waiters that are about to sleep return immediately
+        self.futex.value.fetch_add(1, Relaxed);
         self.futex.wake(1);
     }
 
     pub fn notify_all(&self) {
+        self.futex.value.fetch_add(1, Relaxed);
         self.futex.wake(i32::MAX);
     }
 
-    unsafe fn futex_wait(&self, mutex: &SharedFutex) -> bool {
+    unsafe fn futex_wait(&self, guard: &impl CondvarGuard) -> bool {
+        self.futex_wait_timeout(guard, None)
+    }
+
+    unsafe fn futex_wait_timeout(
+        &self,
+        guard: &impl CondvarGuard,
+        timeout: Option<Duration>,
+    ) -> bool {
+        trace_span!("condvar_wait", ?timeout);
+        count_condvar_wait();
+
         // Examine the notification counter _before_ we unlock the mutex.
         let futex_value = self.futex.value.load(Relaxed);
 
         // Unlock the mutex before going to sleep.
-        mutex.unlock();
+        guard.unlock();
 
         // Wait, but only if there hasn't been any
         // notification since we unlocked the mutex.
-        let r = self.futex.wait(futex_value).is_ok();
+        let r = match timeout {
+            Some(timeout) => !matches!(
+                self.futex.wait_for(futex_value, timeout),
+                Err(TimedWaitError::TimedOut)
+            ),
+            None => self.futex.wait(futex_value).is_ok(),
+        };
 
         // Lock the mutex again.
-        mutex.lock();
+        guard.relock();
 
         r
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Notifies the condition variable once the lock is released, before the waiter sleeps,
+    /// as another thread can do in that window
+    struct NotifyOnUnlock<'a>(&'a SharedCondvar);
+
+    impl CondvarGuard for NotifyOnUnlock<'_> {
+        unsafe fn unlock(&self) {
+            self.0.notify_one();
+        }
+
+        fn relock(&self) {}
+    }
+
+    #[test]
+    fn notification_before_sleeping_is_not_lost() {
+        let condvar = SharedCondvar::default();
+        let (_, timed_out) = condvar.wait_timeout(NotifyOnUnlock(&condvar), Duration::from_secs(1));
+        assert!(!timed_out);
+    }
+}
@@ -0,0 +1,198 @@
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+
+/// Each power of two is split into this many buckets, so a bucket is at most 12.5% wide
+const SUB_BUCKET_BITS: u32 = 3;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+/// Larger values, about 18 minutes in nanoseconds, are counted in the last bucket
+const MAX_VALUE: u64 = (1 << 40) - 1;
+const BUCKETS: usize = bucket(MAX_VALUE) + 1;
+
+/// Values below `SUB_BUCKETS` have a bucket each,
+/// larger ones are bucketed by their highest bit and the `SUB_BUCKET_BITS` bits after it
+const fn bucket(value: u64) -> usize {
+    let value = if value > MAX_VALUE { MAX_VALUE } else { value };
+    if value < SUB_BUCKETS {
+        return value as usize;
+    }
+
+    let exponent = u64::BITS - 1 - value.leading_zeros();
+    let sub_bucket = (value >> (exponent - SUB_BUCKET_BITS)) & (SUB_BUCKETS - 1);
+    ((exponent + 1 - SUB_BUCKET_BITS) as u64 * SUB_BUCKETS + sub_bucket) as usize
+}
+
+/// The largest value counted in the bucket
+fn bucket_max(bucket: usize) -> u64 {
+    let bucket = bucket as u64;
+    if bucket < SUB_BUCKETS {
+        return bucket;
+    }
+
+    let exponent = (bucket / SUB_BUCKETS) as u32 - 1 + SUB_BUCKET_BITS;
+    let sub_bucket = bucket % SUB_BUCKETS;
+    ((SUB_BUCKETS + sub_bucket + 1) << (exponent - SUB_BUCKET_BITS)) - 1
+}
+
+/// Percentiles of a histogram, in nanoseconds.
+/// A percentile is the largest value of its bucket, the max is exact
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub p999: u64,
+    pub max: u64,
+}
+
+impl LatencySummary {
+    pub fn values(&self) -> [(&'static str, u64); 6] {
+        [
+            ("count", self.count),
+            ("p50", self.p50),
+            ("p90", self.p90),
+            ("p99", self.p99),
+            ("p999", self.p999),
+            ("max", self.max),
+        ]
+    }
+}
+
+/// Histogram of durations in nanoseconds, with logarithmic buckets like an HDR histogram.
+/// It only uses atomics, so that it can be shared between processes
+#[repr(C)]
+pub struct LatencyHistogram {
+    counts: [AtomicU64; BUCKETS],
+    max: AtomicU64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            counts: std::array::from_fn(|_| AtomicU64::new(0)),
+            max: AtomicU64::new(0),
+        }
+    }
+}
+
+impl LatencyHistogram {
+    #[inline]
+    pub(crate) fn record(&self, value: u64, count: u64) {
+        self.counts[bucket(value)].fetch_add(count, Relaxed);
+        self.max.fetch_max(value, Relaxed);
+    }
+
+    pub fn summary(&self) -> LatencySummary {
+        let counts = self
+            .counts
+            .iter()
+            .map(|count| count.load(Relaxed))
+            .collect::<Vec<_>>();
+        let count = counts.iter().sum::<u64>();
+        let max = self.max.load(Relaxed);
+
+        let percentile = |percentile: f64| {
+            if count == 0 {
+                return 0;
+            }
+            let rank = ((count as f64 * percentile).ceil() as u64).max(1);
+            let mut seen = 0;
+            for (bucket, bucket_count) in counts.iter().enumerate() {
+                seen += bucket_count;
+                if seen >= rank {
+                    return bucket_max(bucket).min(max);
+                }
+            }
+            max
+        };
+
+        LatencySummary {
+            count,
+            p50: percentile(0.5),
+            p90: percentile(0.9),
+            p99: percentile(0.99),
+            p999: percentile(0.999),
+            max,
+        }
+    }
+
+    pub fn reset(&self) {
+        for count in &self.counts {
+            count.store(0, Relaxed);
+        }
+        self.max.store(0, Relaxed);
+    }
+}
+
+/// Latencies measured by the readers of a channel
+#[repr(C, align(64))]
+#[derive(Default)]
+pub struct ReaderLatency {
+    /// From the write of a message to its read, using the monotonic clock shared by all processes
+    write_to_read: LatencyHistogram,
+    /// Time a read spent waiting for locks held by other threads
+    lock_wait: LatencyHistogram,
+}
+
+impl ReaderLatency {
+    /// Records the read of `count` elements written at `written_ns`, see [`crate::container::message::MessageInfo`]
+    #[inline]
+    pub(crate) fn record_read(&self, written_ns: u64, now_ns: u64, lock_wait_ns: u64, count: u64) {
+        self.write_to_read
+            .record(now_ns.saturating_sub(written_ns), count);
+        self.lock_wait.record(lock_wait_ns, 1);
+    }
+
+    pub fn summaries(&self) -> [(&'static str, LatencySummary); 2] {
+        [
+            ("write_to_read", self.write_to_read.summary()),
+            ("lock_wait", self.lock_wait.summary()),
+        ]
+    }
+
+    pub fn reset(&self) {
+        self.write_to_read.reset();
+        self.lock_wait.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_cover_all_values() {
+        let mut previous = 0;
+        for value in (0..10_000).chain([MAX_VALUE - 1, MAX_VALUE, u64::MAX]) {
+            let bucket = bucket(value);
+            assert!(bucket >= previous && bucket < BUCKETS);
+            assert!(value.min(MAX_VALUE) <= bucket_max(bucket));
+            assert!(bucket == 0 || value.min(MAX_VALUE) > bucket_max(bucket - 1));
+            // Within 12.5% of the value
+            assert!(bucket_max(bucket) - value.min(MAX_VALUE) <= value / SUB_BUCKETS);
+            previous = bucket;
+        }
+    }
+
+    #[test]
+    fn summary_percentiles() {
+        let histogram = LatencyHistogram::default();
+        assert_eq!(histogram.summary(), LatencySummary::default());
+
+        for value in 1..=1000 {
+            histogram.record(value * 1000, 1);
+        }
+        histogram.record(5_000_000, 1);
+
+        let summary = histogram.summary();
+        assert_eq!(summary.count, 1001);
+        assert_eq!(summary.max, 5_000_000);
+        for (percentile, expected) in [(summary.p50, 500_000), (summary.p99, 990_000)] {
+            assert!(percentile >= expected && percentile <= expected + expected / 8);
+        }
+        assert!(summary.p999 >= 1_000_000 && summary.p999 <= summary.max);
+
+        histogram.reset();
+        assert_eq!(histogram.summary().count, 0);
+    }
+}
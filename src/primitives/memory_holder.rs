@@ -1,21 +1,295 @@
+use crate::helpers::process::ProcessId;
+use crate::primitives::channel_stats::ChannelStats;
+use crate::primitives::latency::ReaderLatency;
 use rustix::fs::Mode;
 use rustix::mm::{MapFlags, ProtFlags};
 use rustix::shm::ShmOFlags;
 use std::ffi::{c_void, CString};
+use std::io::{Error, ErrorKind};
 use std::ops::Deref;
 use std::os::fd::OwnedFd;
 use std::ptr::slice_from_raw_parts_mut;
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Relaxed, Release};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64};
+use std::sync::Mutex;
+
+/// Tells the segments created by this library apart from the other files in /dev/shm
+const SEGMENT_MAGIC: [u8; 8] = *b"RIPCSHM\0";
+/// Must be incremented whenever the layout of a segment changes
+const LAYOUT_VERSION: u32 = 9;
+/// Processes that open the segment after this many are only counted, not identified.
+/// The segment is never considered stale while any of them has it open
+const MAX_USERS: usize = 32;
+
+/// The handles of this process register and unregister one at a time,
+/// other processes never modify the slot of this process
+static REGISTRATION: Mutex<()> = Mutex::new(());
+
+/// The container stored in a segment, written in its header by the creator
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[repr(u32)]
+pub enum SegmentKind {
+    Message = 1,
+    CircularQueue = 2,
+    PriorityQueue = 3,
+    ByteRing = 4,
+    ReplySlots = 5,
+    Lock = 6,
+    Condition = 7,
+    Semaphore = 8,
+    Event = 9,
+    Barrier = 10,
+}
+
+impl SegmentKind {
+    const ALL: [SegmentKind; 10] = [
+        SegmentKind::Message,
+        SegmentKind::CircularQueue,
+        SegmentKind::PriorityQueue,
+        SegmentKind::ByteRing,
+        SegmentKind::ReplySlots,
+        SegmentKind::Lock,
+        SegmentKind::Condition,
+        SegmentKind::Semaphore,
+        SegmentKind::Event,
+        SegmentKind::Barrier,
+    ];
+
+    fn from_raw(kind: u32) -> Option<Self> {
+        Self::ALL.into_iter().find(|k| *k as u32 == kind)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SegmentKind::Message => "message",
+            SegmentKind::CircularQueue => "circular_queue",
+            SegmentKind::PriorityQueue => "priority_queue",
+            SegmentKind::ByteRing => "byte_ring",
+            SegmentKind::ReplySlots => "reply_slots",
+            SegmentKind::Lock => "lock",
+            SegmentKind::Condition => "condition",
+            SegmentKind::Semaphore => "semaphore",
+            SegmentKind::Event => "event",
+            SegmentKind::Barrier => "barrier",
+        }
+    }
+}
+
+/// PIDs are below 2^22 on Linux, which leaves the other bits of a slot to the start time
+const PID_BITS: u32 = 22;
+const PID_MASK: u64 = (1 << PID_BITS) - 1;
+
+/// A process that opened the segment, with the number of handles it has open.
+/// The PID and the start time share a word, so that other processes never see a PID
+/// paired with the start time of the previous owner of the slot
+#[repr(C)]
+struct UserSlot {
+    process: AtomicU64,
+    handles: AtomicU32,
+}
+
+impl UserSlot {
+    fn pack(process: ProcessId) -> u64 {
+        (process.start_time << PID_BITS) | (process.pid as u64 & PID_MASK)
+    }
+
+    fn unpack(word: u64) -> ProcessId {
+        ProcessId {
+            pid: (word & PID_MASK) as u32,
+            start_time: word >> PID_BITS,
+        }
+    }
+
+    fn process(&self) -> ProcessId {
+        Self::unpack(self.process.load(Acquire))
+    }
+}
+
+// 64 - magic
+// 32 - layout version
+// 32 - kind
+// 64 - creator start time
+// 32 - creator pid
+// 32 - handles of the users that found no free slot
+// 32 * (22 pid + 42 start time + 32 handles + 32 pad) - users
+// N - padding
+// 1024 - stats, on their own cache lines
+// 2 * (304 * 64 buckets + 64 max) - latency histograms of the readers, on their own cache line
+
+#[repr(C, align(64))]
+pub struct SegmentHeader {
+    magic: [u8; 8],
+    layout_version: u32,
+    kind: u32,
+    creator_start_time: u64,
+    creator_pid: u32,
+    overflowed_handles: AtomicU32,
+    users: [UserSlot; MAX_USERS],
+    stats: ChannelStats,
+    latency: ReaderLatency,
+}
+
+pub const SEGMENT_HEADER_SIZE: usize = size_of::<SegmentHeader>();
+
+impl SegmentHeader {
+    fn init(&mut self, kind: SegmentKind) {
+        let creator = ProcessId::current();
+
+        self.magic = SEGMENT_MAGIC;
+        self.layout_version = LAYOUT_VERSION;
+        self.kind = kind as u32;
+        self.creator_pid = creator.pid;
+        self.creator_start_time = creator.start_time;
+    }
+
+    /// Reads the header from the start of a file, without mapping it
+    pub fn read_from(file: &mut impl std::io::Read) -> std::io::Result<Self> {
+        // Every field is an integer, for which all zeroes is a valid value
+        let mut header = unsafe { std::mem::zeroed::<Self>() };
+        let bytes = unsafe {
+            std::slice::from_raw_parts_mut(&mut header as *mut Self as *mut u8, SEGMENT_HEADER_SIZE)
+        };
+
+        file.read_exact(bytes).map_err(|e| match e.kind() {
+            ErrorKind::UnexpectedEof => not_a_segment(),
+            _ => e,
+        })?;
+        Ok(header)
+    }
+
+    /// Fails if the segment was not created by this library
+    pub fn kind(&self) -> std::io::Result<SegmentKind> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message);
+
+        if self.magic != SEGMENT_MAGIC {
+            return Err(not_a_segment());
+        }
+        if self.layout_version != LAYOUT_VERSION {
+            return Err(invalid(
+                "The segment was created by an incompatible version of ripc",
+            ));
+        }
+        SegmentKind::from_raw(self.kind).ok_or_else(|| invalid("Unknown segment kind"))
+    }
+
+    pub fn creator(&self) -> ProcessId {
+        ProcessId {
+            pid: self.creator_pid,
+            start_time: self.creator_start_time,
+        }
+    }
+
+    /// The processes that currently have the segment open, apart from the creator
+    pub fn users(&self) -> Vec<ProcessId> {
+        self.users
+            .iter()
+            .map(UserSlot::process)
+            .filter(|process| process.pid != 0)
+            .collect()
+    }
+
+    /// The handles opened by processes that found no free user slot
+    pub fn overflowed_handles(&self) -> u32 {
+        self.overflowed_handles.load(Relaxed)
+    }
+
+    pub fn stats(&self) -> &ChannelStats {
+        &self.stats
+    }
+
+    /// Aggregated over all the readers of the segment
+    pub fn latency(&self) -> &ReaderLatency {
+        &self.latency
+    }
+
+    /// A segment is stale once its creator and all its users are dead, nobody will ever unlink it.
+    /// The users without a slot cannot be checked, so they are assumed to be alive
+    pub fn is_stale(&self) -> bool {
+        !self.creator().is_alive()
+            && !self.users().iter().any(ProcessId::is_alive)
+            && self.overflowed_handles() == 0
+    }
+
+    /// Counts the handle in the overflowed handles if there is no free slot
+    fn register(&self, process: ProcessId) {
+        let _guard = REGISTRATION.lock().unwrap();
+
+        // Compared once packed, as a start time too large for the slot is truncated
+        let word = UserSlot::pack(process);
+        if let Some(slot) = self
+            .users
+            .iter()
+            .find(|slot| slot.process.load(Acquire) == word)
+        {
+            slot.handles.fetch_add(1, Relaxed);
+            return;
+        }
+
+        // The slots of dead processes are reused
+        for slot in &self.users {
+            let previous = slot.process.load(Acquire);
+            let user = UserSlot::unpack(previous);
+            if user.pid != 0 && user.is_alive() {
+                continue;
+            }
+            if slot
+                .process
+                .compare_exchange(previous, word, AcqRel, Relaxed)
+                .is_ok()
+            {
+                slot.handles.store(1, Release);
+                return;
+            }
+        }
+        self.overflowed_handles.fetch_add(1, Relaxed);
+    }
+
+    fn unregister(&self, process: ProcessId) {
+        let _guard = REGISTRATION.lock().unwrap();
+
+        let word = UserSlot::pack(process);
+        if let Some(slot) = self
+            .users
+            .iter()
+            .find(|slot| slot.process.load(Acquire) == word)
+        {
+            if slot.handles.fetch_sub(1, Relaxed) == 1 {
+                slot.process.store(0, Release);
+            }
+        } else {
+            // Only the counts matter: once its slot is freed,
+            // the handles left to a process are the ones counted in the overflowed handles
+            self.overflowed_handles.fetch_sub(1, Relaxed);
+        }
+    }
+}
+
+fn not_a_segment() -> Error {
+    Error::new(ErrorKind::InvalidData, "Not a ripc shared memory segment")
+}
+
+/// Named values describing the state of a segment, for diagnostics
+pub type SegmentFields = Vec<(&'static str, String)>;
 
 pub trait SlicePtrCast {
+    /// Stored in the header, so that a segment cannot be opened as another container
+    const KIND: SegmentKind;
+
     fn cast_from_slice_ptr(slice_ptr: *mut [u8]) -> *const Self;
 }
 
 pub struct SharedMemoryHolder<T: 'static + ?Sized> {
     name: CString,
     _fd: OwnedFd,
+    header: &'static SegmentHeader,
     mapped_struct: &'static T,
+    /// Size of the container, without the header
     mapped_size: usize,
+    /// Set for the handle that created the segment, until [`Self::unlink`]
+    unlink_on_drop: AtomicBool,
     created: bool,
+    /// Whether this handle is counted in the users of the segment
+    registered: bool,
 }
 
 impl<T: ?Sized + SlicePtrCast> SharedMemoryHolder<T> {
@@ -28,22 +302,25 @@ impl<T: ?Sized + SlicePtrCast> SharedMemoryHolder<T> {
         )?;
 
         // Resize shared memory
-        if let Err(e) = rustix::fs::ftruncate(&shm, size as u64) {
+        if let Err(e) = rustix::fs::ftruncate(&shm, (SEGMENT_HEADER_SIZE + size) as u64) {
             let _ = rustix::shm::shm_unlink(name.as_c_str());
             return Err(e.into());
         }
 
         match unsafe { Self::map_memory(&shm, true) } {
-            Ok((mapped_struct, mapped_size)) => Ok(Self {
+            Ok((header, mapped_struct, mapped_size)) => Ok(Self {
                 name,
                 _fd: shm,
+                header,
                 mapped_struct,
                 mapped_size,
+                unlink_on_drop: AtomicBool::new(true),
                 created: true,
+                registered: false,
             }),
             Err(e) => {
                 let _ = rustix::shm::shm_unlink(name);
-                Err(e.into())
+                Err(e)
             }
         }
     }
@@ -51,21 +328,32 @@ impl<T: ?Sized + SlicePtrCast> SharedMemoryHolder<T> {
     pub unsafe fn open(name: CString) -> std::io::Result<Self> {
         // Open shared memory
         let shm = rustix::shm::shm_open(&name, ShmOFlags::RDWR, Mode::all())?;
-        let (mapped_struct, mapped_size) = unsafe { Self::map_memory(&shm, false)? };
+        let (header, mapped_struct, mapped_size) = unsafe { Self::map_memory(&shm, false)? };
+        header.register(ProcessId::current());
 
         Ok(Self {
             name,
+            header,
             mapped_struct,
             mapped_size,
             _fd: shm,
+            unlink_on_drop: AtomicBool::new(false),
             created: false,
+            registered: true,
         })
     }
 
-    unsafe fn map_memory(shm: &OwnedFd, create: bool) -> rustix::io::Result<(&'static T, usize)> {
+    #[allow(clippy::type_complexity)]
+    unsafe fn map_memory(
+        shm: &OwnedFd,
+        create: bool,
+    ) -> std::io::Result<(&'static SegmentHeader, &'static T, usize)> {
         // Read actual size
         let stats = rustix::fs::fstat(shm)?;
         let size = stats.st_size as usize;
+        if size < SEGMENT_HEADER_SIZE {
+            return Err(not_a_segment());
+        }
 
         // Map shared memory
         let void_ptr = unsafe {
@@ -80,19 +368,70 @@ impl<T: ?Sized + SlicePtrCast> SharedMemoryHolder<T> {
         };
 
         let slice_ptr: *mut [u8] = slice_from_raw_parts_mut(void_ptr.cast(), size);
+        let header = unsafe { &mut *(void_ptr as *mut SegmentHeader) };
         if create {
             unsafe {
                 (*slice_ptr).fill(0);
             }
+            header.init(T::KIND);
+        } else {
+            let kind_error = match header.kind() {
+                Ok(kind) if kind == T::KIND => None,
+                Ok(kind) => Some(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "The segment kind is {}, expected {}",
+                        kind.name(),
+                        T::KIND.name()
+                    ),
+                )),
+                Err(e) => Some(e),
+            };
+            if let Some(e) = kind_error {
+                let _ = unsafe { rustix::mm::munmap(void_ptr, size) };
+                return Err(e);
+            }
         }
-        let ptr = T::cast_from_slice_ptr(slice_ptr);
 
-        Ok((unsafe { &*ptr }, size))
+        let container_size = size - SEGMENT_HEADER_SIZE;
+        let container_ptr: *mut [u8] = slice_from_raw_parts_mut(
+            unsafe { void_ptr.cast::<u8>().add(SEGMENT_HEADER_SIZE) },
+            container_size,
+        );
+        let ptr = T::cast_from_slice_ptr(container_ptr);
+
+        Ok((header, unsafe { &*ptr }, container_size))
     }
 
     pub fn mapped_memory_size(&self) -> usize {
         self.mapped_size
     }
+
+    pub fn header(&self) -> &SegmentHeader {
+        self.header
+    }
+
+    pub fn stats(&self) -> &ChannelStats {
+        self.header.stats()
+    }
+
+    pub fn latency(&self) -> &ReaderLatency {
+        self.header.latency()
+    }
+
+    /// Whether this handle created the segment, rather than opening it
+    pub fn is_creator(&self) -> bool {
+        self.created
+    }
+
+    /// Removes the name of the segment now rather than when dropped, if this handle created it.
+    /// Processes that have it open can keep using it
+    pub fn unlink(&self) -> std::io::Result<()> {
+        if self.unlink_on_drop.swap(false, Relaxed) {
+            rustix::shm::shm_unlink(&self.name)?;
+        }
+        Ok(())
+    }
 }
 
 impl<T: ?Sized> Deref for SharedMemoryHolder<T> {
@@ -105,12 +444,17 @@ impl<T: ?Sized> Deref for SharedMemoryHolder<T> {
 
 impl<T: ?Sized> Drop for SharedMemoryHolder<T> {
     fn drop(&mut self) {
-        let ptr = self.mapped_struct as *const T as *mut c_void;
-        if let Err(e) = unsafe { rustix::mm::munmap(ptr, self.mapped_size) } {
+        if self.registered {
+            self.header.unregister(ProcessId::current());
+        }
+
+        let ptr = self.header as *const SegmentHeader as *mut c_void;
+        let size = SEGMENT_HEADER_SIZE + self.mapped_size;
+        if let Err(e) = unsafe { rustix::mm::munmap(ptr, size) } {
             eprintln!("Failed to unmap shared memory: {}", e);
         }
 
-        if self.created {
+        if *self.unlink_on_drop.get_mut() {
             if let Err(e) = rustix::shm::shm_unlink(&self.name) {
                 eprintln!("Failed to unlink shared memory: {}", e);
             }
@@ -1,4 +1,8 @@
+pub mod channel_stats;
 pub mod condvar;
+pub mod latency;
 pub mod memory_holder;
 pub mod mutex;
+pub mod rwlock;
 mod shared_futex;
+pub mod wait_counters;
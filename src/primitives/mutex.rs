@@ -1,3 +1,4 @@
+use crate::primitives::condvar::CondvarGuard;
 use crate::primitives::shared_futex::SharedFutex;
 use std::cell::UnsafeCell;
 use std::ops::{Deref, DerefMut};
@@ -9,11 +10,26 @@ pub struct SharedMutex<T: ?Sized> {
 }
 
 impl<T: ?Sized> SharedMutex<T> {
+    /// Locks with FUTEX_LOCK_PI from now on, must be called while the mutex is unlocked
+    pub fn enable_priority_inheritance(&self) {
+        self.futex.enable_priority_inheritance();
+    }
+
     pub fn lock(&self) -> SharedMutexGuard<'_, T> {
         self.futex.lock();
         SharedMutexGuard { lock: self }
     }
 
+    pub fn describe_lock(&self) -> String {
+        self.futex.describe()
+    }
+
+    /// Reads the data without taking the lock, for diagnostics of a lock that might never be released.
+    /// The data can be modified at the same time
+    pub unsafe fn get_unlocked(&self) -> &T {
+        &*self.data.get()
+    }
+
     #[allow(dead_code)]
     pub fn try_lock(&self) -> Option<SharedMutexGuard<'_, T>> {
         if self.futex.try_lock() {
@@ -54,6 +70,12 @@ impl<T: ?Sized> Drop for SharedMutexGuard<'_, T> {
 unsafe impl<T: ?Sized + Send> Send for SharedMutexGuard<'_, T> {}
 unsafe impl<T: ?Sized + Send + Sync> Sync for SharedMutexGuard<'_, T> {}
 
-pub(crate) fn guard_lock<'a, T: ?Sized>(guard: &SharedMutexGuard<'a, T>) -> &'a SharedFutex {
-    &guard.lock.futex
+impl<T: ?Sized> CondvarGuard for SharedMutexGuard<'_, T> {
+    unsafe fn unlock(&self) {
+        self.lock.futex.unlock();
+    }
+
+    fn relock(&self) {
+        self.lock.futex.lock();
+    }
 }
@@ -0,0 +1,523 @@
+use crate::helpers::trace::trace_span;
+use crate::primitives::condvar::CondvarGuard;
+use crate::primitives::shared_futex::{describe_pi, lock_pi, unlock_pi};
+use crate::primitives::wait_counters::LockContention;
+use linux_futex::{Futex, Shared};
+use std::cell::UnsafeCell;
+use std::hint::spin_loop;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+
+// The lowest 30 bits of the state count the readers, all of them set means write locked
+const READ_LOCKED: u32 = 1;
+const MASK: u32 = (1 << 30) - 1;
+const WRITE_LOCKED: u32 = MASK;
+const MAX_READERS: u32 = MASK - 1;
+const READERS_WAITING: u32 = 1 << 30;
+const WRITERS_WAITING: u32 = 1 << 31;
+
+#[inline]
+fn is_unlocked(state: u32) -> bool {
+    state & MASK == 0
+}
+
+#[inline]
+fn is_write_locked(state: u32) -> bool {
+    state & MASK == WRITE_LOCKED
+}
+
+#[inline]
+fn has_readers_waiting(state: u32) -> bool {
+    state & READERS_WAITING != 0
+}
+
+#[inline]
+fn has_writers_waiting(state: u32) -> bool {
+    state & WRITERS_WAITING != 0
+}
+
+/// New readers also wait while a writer is waiting, so that writers are never starved
+#[inline]
+fn is_read_lockable(state: u32) -> bool {
+    state & MASK < MAX_READERS && !has_readers_waiting(state) && !has_writers_waiting(state)
+}
+
+/// This code is largely taken from std::sync::RwLock
+#[repr(C)]
+#[derive(Default)]
+pub struct SharedRwLock<T: ?Sized> {
+    state: Futex<Shared>,
+    /// Incremented every time a writer is woken up
+    writer_notify: Futex<Shared>,
+    /// The kernel cannot boost more than one owner, so readers are exclusive in this mode
+    priority_inheritance: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for SharedRwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for SharedRwLock<T> {}
+
+impl<T: ?Sized> SharedRwLock<T> {
+    /// Locks with FUTEX_LOCK_PI from now on, must be called while the lock is unlocked
+    pub fn enable_priority_inheritance(&self) {
+        self.priority_inheritance.store(true, Relaxed);
+    }
+
+    #[inline]
+    fn is_priority_inheritance(&self) -> bool {
+        self.priority_inheritance.load(Relaxed)
+    }
+
+    /// Reads the state without taking the lock, for diagnostics
+    pub fn describe_lock(&self) -> String {
+        let state = self.state.value.load(Relaxed);
+        if self.is_priority_inheritance() {
+            return describe_pi(state);
+        }
+
+        let mut description = if is_unlocked(state) {
+            "unlocked".to_string()
+        } else if is_write_locked(state) {
+            "write locked".to_string()
+        } else {
+            format!("read locked by {}", state & MASK)
+        };
+        if has_readers_waiting(state) {
+            description.push_str(", readers waiting");
+        }
+        if has_writers_waiting(state) {
+            description.push_str(", writers waiting");
+        }
+        description
+    }
+
+    /// Reads the data without taking the lock, for diagnostics of a lock that might never be released.
+    /// The data can be modified at the same time
+    pub unsafe fn get_unlocked(&self) -> &T {
+        &*self.data.get()
+    }
+
+    pub fn read(&self) -> SharedRwLockReadGuard<'_, T> {
+        self.lock_read();
+        SharedRwLockReadGuard { lock: self }
+    }
+
+    pub fn write(&self) -> SharedRwLockWriteGuard<'_, T> {
+        self.lock_write();
+        SharedRwLockWriteGuard { lock: self }
+    }
+
+    #[inline]
+    fn lock_read(&self) {
+        if self.is_priority_inheritance() {
+            return lock_pi(&self.state);
+        }
+        let state = self.state.value.load(Relaxed);
+        if !is_read_lockable(state)
+            || self
+                .state
+                .value
+                .compare_exchange_weak(state, state + READ_LOCKED, Acquire, Relaxed)
+                .is_err()
+        {
+            self.read_contended();
+        }
+    }
+
+    #[inline]
+    unsafe fn unlock_read(&self) {
+        if self.is_priority_inheritance() {
+            return unlock_pi(&self.state);
+        }
+        let state = self.state.value.fetch_sub(READ_LOCKED, Release) - READ_LOCKED;
+
+        // Readers never wait while the lock is read locked, so only writers need to be woken up
+        if is_unlocked(state) && has_writers_waiting(state) {
+            self.wake_writer_or_readers(state);
+        }
+    }
+
+    #[cold]
+    fn read_contended(&self) {
+        trace_span!("read_lock_contended");
+        let _contention = LockContention::start();
+        let mut state = self.spin_read();
+
+        loop {
+            if is_read_lockable(state) {
+                match self.state.value.compare_exchange_weak(
+                    state,
+                    state + READ_LOCKED,
+                    Acquire,
+                    Relaxed,
+                ) {
+                    Ok(_) => return, // Locked!
+                    Err(s) => {
+                        state = s;
+                        continue;
+                    }
+                }
+            }
+
+            assert_ne!(
+                state & MASK,
+                MAX_READERS,
+                "Too many readers on SharedRwLock"
+            );
+
+            // Make sure the readers waiting bit is set before we go to sleep
+            if !has_readers_waiting(state) {
+                if let Err(s) = self.state.value.compare_exchange(
+                    state,
+                    state | READERS_WAITING,
+                    Relaxed,
+                    Relaxed,
+                ) {
+                    state = s;
+                    continue;
+                }
+            }
+
+            let _ = self.state.wait(state | READERS_WAITING);
+
+            state = self.spin_read();
+        }
+    }
+
+    #[inline]
+    fn lock_write(&self) {
+        if self.is_priority_inheritance() {
+            return lock_pi(&self.state);
+        }
+        if self
+            .state
+            .value
+            .compare_exchange_weak(0, WRITE_LOCKED, Acquire, Relaxed)
+            .is_err()
+        {
+            self.write_contended();
+        }
+    }
+
+    #[inline]
+    unsafe fn unlock_write(&self) {
+        if self.is_priority_inheritance() {
+            return unlock_pi(&self.state);
+        }
+        let state = self.state.value.fetch_sub(WRITE_LOCKED, Release) - WRITE_LOCKED;
+
+        if has_writers_waiting(state) || has_readers_waiting(state) {
+            self.wake_writer_or_readers(state);
+        }
+    }
+
+    #[cold]
+    fn write_contended(&self) {
+        trace_span!("write_lock_contended");
+        let _contention = LockContention::start();
+        let mut state = self.spin_write();
+        let mut other_writers_waiting = 0;
+
+        loop {
+            // If it's unlocked, we try to lock it, keeping the waiting bit of the other writers
+            if is_unlocked(state) {
+                match self.state.value.compare_exchange_weak(
+                    state,
+                    state | WRITE_LOCKED | other_writers_waiting,
+                    Acquire,
+                    Relaxed,
+                ) {
+                    Ok(_) => return, // Locked!
+                    Err(s) => {
+                        state = s;
+                        continue;
+                    }
+                }
+            }
+
+            // Set the waiting bit indicating that we're waiting on it
+            if !has_writers_waiting(state) {
+                if let Err(s) = self.state.value.compare_exchange(
+                    state,
+                    state | WRITERS_WAITING,
+                    Relaxed,
+                    Relaxed,
+                ) {
+                    state = s;
+                    continue;
+                }
+            }
+
+            // Other writers might be waiting now too, so we should make sure
+            // we keep that bit on once we manage to lock it
+            other_writers_waiting = WRITERS_WAITING;
+
+            // Examine the notification counter before we check if the lock is still locked
+            let seq = self.writer_notify.value.load(Acquire);
+
+            // Don't go to sleep if the lock has become available,
+            // or if the writers waiting bit is no longer set
+            state = self.state.value.load(Relaxed);
+            if is_unlocked(state) || !has_writers_waiting(state) {
+                continue;
+            }
+
+            let _ = self.writer_notify.wait(seq);
+
+            state = self.spin_write();
+        }
+    }
+
+    /// Wakes up waiting threads after unlocking, writers are given priority over readers
+    #[cold]
+    fn wake_writer_or_readers(&self, mut state: u32) {
+        debug_assert!(is_unlocked(state));
+
+        // Only writers are waiting
+        if state == WRITERS_WAITING {
+            match self
+                .state
+                .value
+                .compare_exchange(state, 0, Relaxed, Relaxed)
+            {
+                Ok(_) => {
+                    self.wake_writer();
+                    return;
+                }
+                Err(s) => {
+                    // Maybe some readers are now waiting too, so fall through
+                    state = s;
+                }
+            }
+        }
+
+        // Both writers and readers are waiting, leave the readers waiting and only wake up one writer
+        if state == READERS_WAITING + WRITERS_WAITING {
+            if self
+                .state
+                .value
+                .compare_exchange(state, READERS_WAITING, Relaxed, Relaxed)
+                .is_err()
+            {
+                // The lock got locked, not our problem anymore
+                return;
+            }
+            if self.wake_writer() {
+                return;
+            }
+            // No writers were actually blocked on the futex, so wake up the readers instead
+            state = READERS_WAITING;
+        }
+
+        // Only readers are waiting, wake them all up
+        if state == READERS_WAITING
+            && self
+                .state
+                .value
+                .compare_exchange(state, 0, Relaxed, Relaxed)
+                .is_ok()
+        {
+            self.state.wake(i32::MAX);
+        }
+    }
+
+    /// Returns true if a writer was woken up
+    fn wake_writer(&self) -> bool {
+        self.writer_notify.value.fetch_add(1, Release);
+        self.writer_notify.wake(1) != 0
+    }
+
+    fn spin_until(&self, f: impl Fn(u32) -> bool) -> u32 {
+        let mut spin = 100;
+        loop {
+            let state = self.state.value.load(Relaxed);
+            if f(state) || spin == 0 {
+                return state;
+            }
+            spin_loop();
+            spin -= 1;
+        }
+    }
+
+    fn spin_write(&self) -> u32 {
+        // Stop spinning when it's unlocked or when there's waiting writers, to keep things somewhat fair
+        self.spin_until(|state| is_unlocked(state) || has_writers_waiting(state))
+    }
+
+    fn spin_read(&self) -> u32 {
+        // Stop spinning when it's unlocked or read locked, or when there's waiting threads
+        self.spin_until(|state| {
+            !is_write_locked(state) || has_readers_waiting(state) || has_writers_waiting(state)
+        })
+    }
+}
+
+pub struct SharedRwLockReadGuard<'a, T: ?Sized> {
+    lock: &'a SharedRwLock<T>,
+}
+
+impl<T: ?Sized> Deref for SharedRwLockReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for SharedRwLockReadGuard<'_, T> {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe { self.lock.unlock_read() }
+    }
+}
+
+impl<T: ?Sized> CondvarGuard for SharedRwLockReadGuard<'_, T> {
+    unsafe fn unlock(&self) {
+        self.lock.unlock_read();
+    }
+
+    fn relock(&self) {
+        self.lock.lock_read();
+    }
+}
+
+pub struct SharedRwLockWriteGuard<'a, T: ?Sized> {
+    lock: &'a SharedRwLock<T>,
+}
+
+impl<T: ?Sized> Deref for SharedRwLockWriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for SharedRwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for SharedRwLockWriteGuard<'_, T> {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe { self.lock.unlock_write() }
+    }
+}
+
+impl<T: ?Sized> CondvarGuard for SharedRwLockWriteGuard<'_, T> {
+    unsafe fn unlock(&self) {
+        self.lock.unlock_write();
+    }
+
+    fn relock(&self) {
+        self.lock.lock_write();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn readers_share_the_lock() {
+        let lock = SharedRwLock::<u32>::default();
+        let readers = AtomicU32::new(0);
+
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let _guard = lock.read();
+                    readers.fetch_add(1, Ordering::Relaxed);
+                    // Only returns if all the readers hold the lock at the same time
+                    while readers.load(Ordering::Relaxed) != 4 {
+                        thread::yield_now();
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn writers_are_exclusive() {
+        let lock = SharedRwLock::<u64>::default();
+
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        let mut value = lock.write();
+                        let current = *value;
+                        thread::yield_now();
+                        *value = current + 1;
+                    }
+                });
+            }
+            s.spawn(|| {
+                for _ in 0..1000 {
+                    let value = lock.read();
+                    assert!(*value <= 4000);
+                }
+            });
+        });
+
+        assert_eq!(*lock.read(), 4000);
+    }
+
+    #[test]
+    fn waiting_writer_blocks_new_readers() {
+        let lock = SharedRwLock::<u32>::default();
+
+        thread::scope(|s| {
+            let reader = lock.read();
+            let writer = s.spawn(|| *lock.write() += 1);
+
+            while !has_writers_waiting(lock.state.value.load(Relaxed)) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            let late_reader = s.spawn(|| *lock.read());
+            while !has_readers_waiting(lock.state.value.load(Relaxed)) {
+                thread::sleep(Duration::from_millis(1));
+            }
+
+            drop(reader);
+            writer.join().unwrap();
+            assert_eq!(late_reader.join().unwrap(), 1);
+        });
+    }
+
+    #[test]
+    fn priority_inheritance_stores_the_owner() {
+        let lock = SharedRwLock::<u64>::default();
+        lock.enable_priority_inheritance();
+
+        {
+            let _guard = lock.read();
+            let owner = lock.state.value.load(Relaxed);
+            assert_eq!(
+                owner,
+                rustix::thread::gettid().as_raw_nonzero().get() as u32
+            );
+        }
+        assert_eq!(lock.state.value.load(Relaxed), 0);
+
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        let mut value = lock.write();
+                        let current = *value;
+                        thread::yield_now();
+                        *value = current + 1;
+                    }
+                });
+            }
+        });
+
+        assert_eq!(*lock.read(), 4000);
+        assert_eq!(lock.state.value.load(Relaxed), 0);
+    }
+}
@@ -1,20 +1,154 @@
-use linux_futex::{Futex, Shared};
+use crate::helpers::trace::trace_span;
+use crate::primitives::wait_counters::LockContention;
+use linux_futex::{AsFutex, Futex, PiFutex, Shared};
+use rustix::io::Errno;
+use rustix::process::{test_kill_process, Pid};
+use rustix::thread::{futex, gettid};
 use std::hint::spin_loop;
+use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering::{Acquire, Relaxed, Release};
 
 const UNLOCKED: u32 = 0;
 const LOCKED: u32 = 1; // locked, no other threads waiting
 const CONTENDED: u32 = 2; // locked, and other threads waiting (contended)
 
+/// The futex word of a priority inheritance lock holds the TID of its owner,
+/// so that the kernel can boost the owner while a higher priority thread waits for it
+#[inline]
+fn current_tid() -> u32 {
+    gettid().as_raw_nonzero().get() as u32
+}
+
+/// Both are a transparent wrapper over the same futex word
+#[inline]
+fn as_pi_futex(futex: &Futex<Shared>) -> &PiFutex<Shared> {
+    futex.value.as_pi_futex()
+}
+
+#[inline]
+pub(crate) fn try_lock_pi(futex: &Futex<Shared>) -> bool {
+    futex
+        .value
+        .compare_exchange(UNLOCKED, current_tid(), Acquire, Relaxed)
+        .is_ok()
+}
+
+#[inline]
+pub(crate) fn lock_pi(futex: &Futex<Shared>) {
+    if !try_lock_pi(futex) {
+        trace_span!("lock_contended", priority_inheritance = true);
+        let _contention = LockContention::start();
+        lock_pi_contended(futex);
+    }
+}
+
+/// Nothing registers a robust list, so the kernel never marks the lock of a thread that died
+/// while holding it, and fails with ESRCH when asked to wait for that thread.
+/// The lock is then taken over the way the robust list would have done it:
+/// the dead TID is replaced by the owner died bit, and the kernel hands the lock to the next locker
+#[cold]
+fn lock_pi_contended(futex: &Futex<Shared>) {
+    loop {
+        match futex::lock_pi(&futex.value, futex::Flags::empty(), None) {
+            Ok(()) => return,
+            // The owner is exiting, the kernel asks us to try again
+            Err(Errno::AGAIN) | Err(Errno::INTR) => {}
+            Err(Errno::SRCH) => {
+                let value = futex.value.load(Relaxed);
+                if !is_thread_alive(value & PI_TID_MASK) {
+                    let _ = futex.value.compare_exchange(
+                        value,
+                        (value & PI_WAITERS) | PI_OWNER_DIED,
+                        Relaxed,
+                        Relaxed,
+                    );
+                }
+            }
+            Err(error) => panic!("FUTEX_LOCK_PI failed: {error}"),
+        }
+    }
+}
+
+/// Signal 0 only checks that the thread exists, EPERM means it belongs to another user
+fn is_thread_alive(tid: u32) -> bool {
+    match Pid::from_raw(tid as i32) {
+        Some(pid) => test_kill_process(pid) != Err(Errno::SRCH),
+        None => false,
+    }
+}
+
+#[inline]
+pub(crate) unsafe fn unlock_pi(futex: &Futex<Shared>) {
+    // The kernel sets the waiters bit when someone is blocked on the lock
+    if futex
+        .value
+        .compare_exchange(current_tid(), UNLOCKED, Release, Relaxed)
+        .is_err()
+    {
+        as_pi_futex(futex).unlock_pi();
+    }
+}
+
+const PI_WAITERS: u32 = 1 << 31;
+const PI_OWNER_DIED: u32 = 1 << 30;
+const PI_TID_MASK: u32 = PI_OWNER_DIED - 1;
+
+/// Human readable state of a priority inheritance futex word
+pub(crate) fn describe_pi(value: u32) -> String {
+    if value == UNLOCKED {
+        return "unlocked".to_string();
+    }
+
+    let mut description = format!("locked by thread {}", value & PI_TID_MASK);
+    if value & PI_OWNER_DIED != 0 {
+        description.push_str(", owner died");
+    }
+    if value & PI_WAITERS != 0 {
+        description.push_str(", threads waiting");
+    }
+    description
+}
+
 #[derive(Default)]
-#[repr(transparent)]
-pub struct SharedFutex(Futex<Shared>);
+#[repr(C)]
+pub struct SharedFutex {
+    futex: Futex<Shared>,
+    /// Chosen when the segment is created, before the lock is ever taken
+    priority_inheritance: AtomicBool,
+}
 
 /// This code is largely taken from std::sync::Mutex
 impl SharedFutex {
+    pub fn enable_priority_inheritance(&self) {
+        self.priority_inheritance.store(true, Relaxed);
+    }
+
+    #[inline]
+    fn is_priority_inheritance(&self) -> bool {
+        self.priority_inheritance.load(Relaxed)
+    }
+
+    /// Reads the state without taking the lock, for diagnostics
+    pub fn describe(&self) -> String {
+        let state = self.futex.value.load(Relaxed);
+        if self.is_priority_inheritance() {
+            return describe_pi(state);
+        }
+
+        match state {
+            UNLOCKED => "unlocked",
+            LOCKED => "locked",
+            _ => "locked, threads waiting",
+        }
+        .to_string()
+    }
+
     #[inline]
     pub fn try_lock(&self) -> bool {
-        self.0
+        if self.is_priority_inheritance() {
+            return try_lock_pi(&self.futex);
+        }
+        self.futex
             .value
             .compare_exchange(UNLOCKED, LOCKED, Acquire, Relaxed)
             .is_ok()
@@ -22,8 +156,11 @@ impl SharedFutex {
 
     #[inline]
     pub fn lock(&self) {
+        if self.is_priority_inheritance() {
+            return lock_pi(&self.futex);
+        }
         if self
-            .0
+            .futex
             .value
             .compare_exchange(UNLOCKED, LOCKED, Acquire, Relaxed)
             .is_err()
@@ -34,13 +171,15 @@ impl SharedFutex {
 
     #[cold]
     fn lock_contended(&self) {
+        trace_span!("lock_contended");
+        let _contention = LockContention::start();
         let mut state = self.spin();
 
         // If it's unlocked now, attempt to take the lock
         // without marking it as contended.
         if state == UNLOCKED {
             match self
-                .0
+                .futex
                 .value
                 .compare_exchange(UNLOCKED, LOCKED, Acquire, Relaxed)
             {
@@ -53,13 +192,13 @@ impl SharedFutex {
             // Put the lock in contended state.
             // We avoid an unnecessary write if it as already set to CONTENDED,
             // to be friendlier for the caches.
-            if state != CONTENDED && self.0.value.swap(CONTENDED, Acquire) == UNLOCKED {
+            if state != CONTENDED && self.futex.value.swap(CONTENDED, Acquire) == UNLOCKED {
                 // We changed it from UNLOCKED to CONTENDED, so we just successfully locked it.
                 return;
             }
 
             // Wait for the futex to change state, assuming it is still CONTENDED.
-            let _ = self.0.wait(CONTENDED);
+            let _ = self.futex.wait(CONTENDED);
 
             // Get the new state
             state = self.spin();
@@ -68,12 +207,15 @@ impl SharedFutex {
 
     #[inline]
     pub unsafe fn unlock(&self) {
-        if self.0.value.swap(UNLOCKED, Release) == CONTENDED {
+        if self.is_priority_inheritance() {
+            return unlock_pi(&self.futex);
+        }
+        if self.futex.value.swap(UNLOCKED, Release) == CONTENDED {
             // We only wake up one thread. When that thread locks the mutex, it
             // will mark the mutex as CONTENDED (see lock_contended above),
             // which makes sure that any other waiting threads will also be
             // woken up eventually.
-            let _ = self.0.wake(1);
+            let _ = self.futex.wake(1);
         }
     }
 
@@ -82,7 +224,7 @@ impl SharedFutex {
         loop {
             // We only use `load` (and not `swap` or `compare_exchange`)
             // while spinning, to be easier on the caches.
-            let state = self.0.value.load(Relaxed);
+            let state = self.futex.value.load(Relaxed);
 
             // We stop spinning when the mutex is UNLOCKED,
             // but also when it's CONTENDED.
@@ -95,3 +237,31 @@ impl SharedFutex {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn take_over_the_lock_of_a_dead_owner() {
+        let lock = SharedFutex::default();
+        lock.enable_priority_inheritance();
+
+        let dead_tid = thread::spawn(current_tid).join().unwrap();
+        while is_thread_alive(dead_tid) {
+            thread::yield_now();
+        }
+        lock.futex.value.store(dead_tid, Relaxed);
+
+        lock.lock();
+        let value = lock.futex.value.load(Relaxed);
+        assert_eq!(value & PI_TID_MASK, current_tid());
+        assert_ne!(value & PI_OWNER_DIED, 0);
+
+        unsafe { lock.unlock() };
+        assert_eq!(lock.futex.value.load(Relaxed), UNLOCKED);
+        assert!(lock.try_lock());
+        unsafe { lock.unlock() };
+    }
+}
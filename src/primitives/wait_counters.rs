@@ -0,0 +1,69 @@
+//! Counts, per thread, the times a lock or a condition variable made the thread wait,
+//! and the time spent waiting for locks.
+//! The primitives do not know which segment they belong to, so the channels compare the counters
+//! before and after an operation to attribute the waits to their statistics
+
+use std::cell::Cell;
+use std::time::Instant;
+
+thread_local! {
+    static LOCK_CONTENTIONS: Cell<u64> = const { Cell::new(0) };
+    static CONDVAR_WAITS: Cell<u64> = const { Cell::new(0) };
+    static LOCK_WAIT_NS: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Started when a lock was not free on the first attempt,
+/// counts the time until it is acquired once dropped
+pub(crate) struct LockContention {
+    start: Instant,
+}
+
+impl LockContention {
+    #[inline]
+    pub(crate) fn start() -> Self {
+        LOCK_CONTENTIONS.with(|count| count.set(count.get() + 1));
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for LockContention {
+    fn drop(&mut self) {
+        let waited = self.start.elapsed().as_nanos() as u64;
+        LOCK_WAIT_NS.with(|total| total.set(total.get() + waited));
+    }
+}
+
+#[inline]
+pub(crate) fn count_condvar_wait() {
+    CONDVAR_WAITS.with(|count| count.set(count.get() + 1));
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct WaitCounters {
+    pub lock_contentions: u64,
+    pub condvar_waits: u64,
+    pub lock_wait_ns: u64,
+}
+
+impl WaitCounters {
+    /// The counters of the calling thread
+    pub(crate) fn current() -> Self {
+        Self {
+            lock_contentions: LOCK_CONTENTIONS.with(Cell::get),
+            condvar_waits: CONDVAR_WAITS.with(Cell::get),
+            lock_wait_ns: LOCK_WAIT_NS.with(Cell::get),
+        }
+    }
+
+    /// The waits of the calling thread since `self` was taken
+    pub(crate) fn elapsed(self) -> Self {
+        let now = Self::current();
+        Self {
+            lock_contentions: now.lock_contentions - self.lock_contentions,
+            condvar_waits: now.condvar_waits - self.condvar_waits,
+            lock_wait_ns: now.lock_wait_ns - self.lock_wait_ns,
+        }
+    }
+}
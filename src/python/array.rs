@@ -0,0 +1,312 @@
+use crate::helpers::bytes::RustPyBytes;
+use crate::python::message::PythonSharedMessage;
+use crate::python::pickle::{reduce, Reduced};
+use crate::python::OpenMode;
+use pyo3::buffer::PyBuffer;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use pyo3::{pyclass, pymethods, Bound, PyResult, Python};
+use std::mem::size_of;
+use std::num::NonZeroU32;
+
+const ARRAY_MAGIC: u32 = u32::from_ne_bytes(*b"rARR");
+const MAX_DTYPE_LENGTH: usize = 16;
+/// numpy 2 supports at most 64 dimensions
+const MAX_DIMENSIONS: usize = 64;
+/// Aligns the array data within the payload for every dtype. The data is only aligned in memory
+/// if the copy of the payload is, which depends on the allocator
+const HEADER_ALIGNMENT: usize = 16;
+
+// 32 - magic
+// 32 - number of dimensions
+// 128 - dtype string, padded with zeros
+// 64 * ndim - shape
+// 64 * ndim - strides
+// padding up to a multiple of 16
+// N - array data
+
+#[derive(Debug, PartialEq)]
+struct ArrayHeader {
+    dtype: String,
+    shape: Vec<usize>,
+    strides: Vec<isize>,
+}
+
+impl ArrayHeader {
+    const FIXED_SIZE: usize = 2 * size_of::<u32>() + MAX_DTYPE_LENGTH;
+    const MAX_SIZE: usize = Self::encoded_size(MAX_DIMENSIONS);
+
+    const fn encoded_size(dimensions: usize) -> usize {
+        (Self::FIXED_SIZE + dimensions * (size_of::<usize>() + size_of::<isize>()))
+            .next_multiple_of(HEADER_ALIGNMENT)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut dtype = [0u8; MAX_DTYPE_LENGTH];
+        dtype[..self.dtype.len()].copy_from_slice(self.dtype.as_bytes());
+
+        let mut bytes = Vec::with_capacity(Self::encoded_size(self.shape.len()));
+        bytes.extend_from_slice(&ARRAY_MAGIC.to_ne_bytes());
+        bytes.extend_from_slice(&(self.shape.len() as u32).to_ne_bytes());
+        bytes.extend_from_slice(&dtype);
+        for dimension in &self.shape {
+            bytes.extend_from_slice(&dimension.to_ne_bytes());
+        }
+        for stride in &self.strides {
+            bytes.extend_from_slice(&stride.to_ne_bytes());
+        }
+        bytes.resize(Self::encoded_size(self.shape.len()), 0);
+
+        bytes
+    }
+
+    /// Returns the header and the offset at which the array data starts
+    fn decode(bytes: &[u8]) -> Option<(Self, usize)> {
+        fn read<const N: usize>(bytes: &[u8], index: &mut usize) -> Option<[u8; N]> {
+            let value = bytes.get(*index..*index + N)?.try_into().ok()?;
+            *index += N;
+            Some(value)
+        }
+
+        let mut index = 0;
+        if u32::from_ne_bytes(read(bytes, &mut index)?) != ARRAY_MAGIC {
+            return None;
+        }
+        let dimensions = u32::from_ne_bytes(read(bytes, &mut index)?) as usize;
+        if dimensions > MAX_DIMENSIONS {
+            return None;
+        }
+
+        let dtype = read::<MAX_DTYPE_LENGTH>(bytes, &mut index)?;
+        let dtype_length = dtype.iter().position(|&b| b == 0).unwrap_or(dtype.len());
+        let dtype = std::str::from_utf8(&dtype[..dtype_length])
+            .ok()?
+            .to_string();
+
+        let shape = (0..dimensions)
+            .map(|_| read(bytes, &mut index).map(usize::from_ne_bytes))
+            .collect::<Option<Vec<_>>>()?;
+        let strides = (0..dimensions)
+            .map(|_| read(bytes, &mut index).map(isize::from_ne_bytes))
+            .collect::<Option<Vec<_>>>()?;
+
+        let offset = Self::encoded_size(dimensions);
+        (offset <= bytes.len()).then_some((
+            Self {
+                dtype,
+                shape,
+                strides,
+            },
+            offset,
+        ))
+    }
+}
+
+/// A [`PythonSharedMessage`] whose payload is a numpy array, prefixed by its dtype, shape and strides
+#[pyclass]
+#[pyo3(frozen, name = "SharedArray")]
+pub struct PythonSharedArray {
+    message: PythonSharedMessage,
+}
+
+impl PythonSharedArray {
+    fn to_array<'py>(py: Python<'py>, bytes: RustPyBytes) -> PyResult<Bound<'py, PyAny>> {
+        let (header, offset) = ArrayHeader::decode(&bytes.0)
+            .ok_or_else(|| PyValueError::new_err("The message does not contain an array"))?;
+
+        let kwargs = PyDict::new(py);
+        kwargs.set_item("dtype", header.dtype)?;
+        kwargs.set_item("buffer", Py::new(py, bytes)?)?;
+        kwargs.set_item("offset", offset)?;
+        kwargs.set_item("strides", header.strides)?;
+
+        py.import("numpy")?
+            .getattr("ndarray")?
+            .call((header.shape,), Some(&kwargs))
+    }
+}
+
+#[pymethods]
+impl PythonSharedArray {
+    /// `size` is the maximum number of bytes of the arrays that will be written
+    #[staticmethod]
+    #[pyo3(signature = (name, size, mode=OpenMode::ReadWrite))]
+    fn create(name: String, size: NonZeroU32, mode: OpenMode) -> PyResult<Self> {
+        let size = size
+            .checked_add(ArrayHeader::MAX_SIZE as u32)
+            .ok_or_else(|| PyValueError::new_err("Size is too large"))?;
+
+        Ok(Self {
+            message: PythonSharedMessage::create(name, size, mode, false, false, false)?,
+        })
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (name, mode=OpenMode::ReadWrite))]
+    pub(crate) fn open(name: String, mode: OpenMode) -> PyResult<Self> {
+        Ok(Self {
+            message: PythonSharedMessage::open(name, mode)?,
+        })
+    }
+
+    fn write(&self, array: &Bound<'_, PyAny>) -> PyResult<()> {
+        let py = array.py();
+        let numpy = py.import("numpy")?;
+
+        let mut array = numpy.call_method1("asarray", (array,))?;
+        if !array.getattr("flags")?.getattr("c_contiguous")?.extract()? {
+            array = array.call_method0("copy")?;
+        }
+
+        let dtype = array.getattr("dtype")?;
+        if dtype.getattr("hasobject")?.extract()? {
+            return Err(PyValueError::new_err(
+                "Arrays containing Python objects cannot be shared",
+            ));
+        }
+
+        let header = ArrayHeader {
+            dtype: dtype.getattr("str")?.extract()?,
+            shape: array.getattr("shape")?.extract()?,
+            strides: array.getattr("strides")?.extract()?,
+        };
+        if header.dtype.len() > MAX_DTYPE_LENGTH {
+            return Err(PyValueError::new_err(format!(
+                "Unsupported dtype {}",
+                header.dtype
+            )));
+        }
+
+        let bytes = array
+            .call_method1("reshape", (-1,))?
+            .call_method1("view", (numpy.getattr("uint8")?,))?;
+        let buffer = PyBuffer::<u8>::get(&bytes)?;
+        let data = unsafe {
+            std::slice::from_raw_parts(buffer.buf_ptr() as *const u8, buffer.len_bytes())
+        };
+
+        self.message.write_parts(&[&header.encode(), data], py)
+    }
+
+    fn try_read<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyAny>>> {
+        self.message
+            .try_read()?
+            .map(|bytes| Self::to_array(py, bytes))
+            .transpose()
+    }
+
+    fn blocking_read<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyAny>>> {
+        self.message
+            .blocking_read(py)?
+            .map(|bytes| Self::to_array(py, bytes))
+            .transpose()
+    }
+
+    fn is_new_version_available(&self) -> bool {
+        self.message.is_new_version_available()
+    }
+
+    fn name(&self) -> &str {
+        self.message.name()
+    }
+
+    /// Pickled as its name and mode, the process unpickling it opens the array again
+    fn __reduce__<'py>(&self, py: Python<'py>) -> PyResult<Reduced<'py>> {
+        reduce(py, "array", self.message.name(), self.message.open_mode())
+    }
+
+    fn is_closed(&self) -> bool {
+        self.message.is_closed()
+    }
+
+    fn close(&self) {
+        self.message.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_round_trip() {
+        let header = ArrayHeader {
+            dtype: "<f4".to_string(),
+            shape: vec![2, 3, 4],
+            strides: vec![48, 16, 4],
+        };
+
+        let mut bytes = header.encode();
+        assert_eq!(bytes.len(), ArrayHeader::encoded_size(3));
+        assert_eq!(bytes.len() % HEADER_ALIGNMENT, 0);
+        bytes.extend_from_slice(&[0; 96]);
+
+        let (decoded, offset) = ArrayHeader::decode(&bytes).unwrap();
+        assert_eq!(decoded, header);
+        assert_eq!(offset, ArrayHeader::encoded_size(3));
+
+        let scalar = ArrayHeader {
+            dtype: "|u1".to_string(),
+            shape: vec![],
+            strides: vec![],
+        };
+        assert_eq!(ArrayHeader::decode(&scalar.encode()).unwrap().0, scalar);
+    }
+
+    #[test]
+    fn header_rejects_invalid_payloads() {
+        assert!(ArrayHeader::decode(&[]).is_none());
+        assert!(ArrayHeader::decode(&[1, 2, 3, 4, 5, 6, 7, 8]).is_none());
+
+        let header = ArrayHeader {
+            dtype: "<i8".to_string(),
+            shape: vec![10],
+            strides: vec![8],
+        };
+        let bytes = header.encode();
+        assert!(ArrayHeader::decode(&bytes[..bytes.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn numpy_round_trip() {
+        Python::with_gil(|py| {
+            // numpy is an optional dependency of the package
+            let Ok(numpy) = py.import("numpy") else {
+                return;
+            };
+
+            let array = PythonSharedArray::create(
+                "/array_numpy_round_trip".to_string(),
+                NonZeroU32::new(1024).unwrap(),
+                OpenMode::ReadWrite,
+            )
+            .unwrap();
+
+            let values = numpy.call_method1("arange", (12,)).unwrap();
+            for dtype in ["float64", "complex128"] {
+                let written = values
+                    .call_method1("astype", (dtype,))
+                    .unwrap()
+                    .call_method1("reshape", ((3, 4),))
+                    .unwrap();
+                // Not contiguous, copied before being written
+                let written = written.call_method0("transpose").unwrap();
+                array.write(&written).unwrap();
+
+                let read = array.try_read(py).unwrap().unwrap();
+                assert!(read
+                    .getattr("dtype")
+                    .unwrap()
+                    .eq(written.getattr("dtype").unwrap())
+                    .unwrap());
+                assert!(numpy
+                    .call_method1("array_equal", (&read, &written))
+                    .unwrap()
+                    .extract::<bool>()
+                    .unwrap());
+            }
+            array.close();
+        });
+    }
+}
@@ -0,0 +1,15 @@
+use pyo3::pyclass;
+
+/// What `SharedQueue.write` does when the in-process write buffer is full
+#[pyclass(eq, eq_int)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum Backpressure {
+    /// Wait until the feeder thread makes room
+    Block = 0,
+    /// Raise `queue.Full`
+    Raise = 1,
+    /// Discard the oldest pending elements
+    DropOldest = 2,
+    /// Discard the elements that don't fit
+    DropNewest = 3,
+}
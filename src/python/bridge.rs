@@ -0,0 +1,134 @@
+use crate::bridge::{Bridge, BridgeStream, ChannelKind};
+use pyo3::exceptions::PyValueError;
+use pyo3::{pyclass, pymethods, PyResult, Python};
+use std::sync::Mutex;
+
+fn connect(address: &str, listen: bool) -> std::io::Result<BridgeStream> {
+    match listen {
+        true => BridgeStream::accept(address),
+        false => BridgeStream::connect(address),
+    }
+}
+
+#[pyclass]
+#[pyo3(frozen, name = "Bridge")]
+pub struct PythonBridge {
+    /// Taken once the bridge is stopped
+    bridge: Mutex<Option<Bridge>>,
+}
+
+impl PythonBridge {
+    fn take(&self) -> PyResult<Bridge> {
+        self.bridge
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| PyValueError::new_err("The bridge was already stopped"))
+    }
+}
+
+#[pymethods]
+impl PythonBridge {
+    #[staticmethod]
+    #[pyo3(signature = (name, address, queue = false, listen = false))]
+    fn send(
+        name: &str,
+        address: &str,
+        queue: bool,
+        listen: bool,
+        py: Python<'_>,
+    ) -> PyResult<Self> {
+        let kind = match queue {
+            true => ChannelKind::Queue,
+            false => ChannelKind::Message,
+        };
+        let bridge = py.allow_threads(|| Bridge::send(name, kind, connect(address, listen)?))?;
+
+        Ok(Self {
+            bridge: Mutex::new(Some(bridge)),
+        })
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (name, address, listen = false))]
+    fn receive(name: &str, address: &str, listen: bool, py: Python<'_>) -> PyResult<Self> {
+        let bridge = py.allow_threads(|| Bridge::receive(name, connect(address, listen)?))?;
+
+        Ok(Self {
+            bridge: Mutex::new(Some(bridge)),
+        })
+    }
+
+    /// Waits until the channel is closed, or the connection is lost
+    fn wait(&self, py: Python<'_>) -> PyResult<()> {
+        let bridge = self.take()?;
+        Ok(py.allow_threads(|| bridge.wait())?)
+    }
+
+    fn stop(&self, py: Python<'_>) -> PyResult<()> {
+        let bridge = self.take()?;
+        Ok(py.allow_threads(|| bridge.stop())?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::python::message::PythonSharedMessage;
+    use crate::python::OpenMode;
+    use std::num::NonZero;
+    use std::time::Duration;
+
+    #[test]
+    fn bridge_over_unix_socket_path() {
+        let path = std::env::temp_dir().join("ripc_bridge_over_unix_socket_path");
+        let _ = std::fs::remove_file(&path);
+        let address = format!("unix:{}", path.display());
+
+        Python::with_gil(|py| {
+            let source = PythonSharedMessage::create(
+                "/python_bridge_source".to_string(),
+                NonZero::new(64).unwrap(),
+                OpenMode::ReadWrite,
+                false,
+                false,
+                false,
+            )
+            .unwrap();
+            source.write_parts(&[b"hello"], py).unwrap();
+
+            let receiver = {
+                let address = address.clone();
+                std::thread::spawn(move || {
+                    Python::with_gil(|py| {
+                        PythonBridge::receive("/python_bridge_mirror", &address, true, py)
+                    })
+                })
+            };
+            let sender = loop {
+                match PythonBridge::send("/python_bridge_source", &address, false, false, py) {
+                    Ok(sender) => break sender,
+                    // The receiver is not listening yet
+                    Err(_) => py.allow_threads(|| std::thread::sleep(Duration::from_millis(1))),
+                }
+            };
+            let receiver = py.allow_threads(|| receiver.join().unwrap()).unwrap();
+
+            let mirror =
+                PythonSharedMessage::open("/python_bridge_mirror".to_string(), OpenMode::ReadOnly)
+                    .unwrap();
+            assert_eq!(
+                mirror.blocking_read(py).unwrap().unwrap().0.as_ref(),
+                b"hello"
+            );
+
+            source.close();
+            sender.wait(py).unwrap();
+            receiver.wait(py).unwrap();
+            assert!(mirror.is_closed());
+            assert!(receiver.stop(py).is_err());
+        });
+
+        assert!(!path.exists());
+    }
+}
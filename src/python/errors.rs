@@ -0,0 +1,19 @@
+use crate::container::message::CorruptMessage;
+use pyo3::exceptions::PyException;
+use pyo3::{create_exception, PyErr};
+
+create_exception!(
+    ripc,
+    CorruptMessageError,
+    PyException,
+    "The payload read does not match the checksum written with it"
+);
+
+impl From<CorruptMessage> for PyErr {
+    fn from(e: CorruptMessage) -> Self {
+        CorruptMessageError::new_err(format!(
+            "Message version {} is corrupt, expected checksum {:#010x} but got {:#010x}",
+            e.version, e.expected_checksum, e.actual_checksum
+        ))
+    }
+}
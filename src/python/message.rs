@@ -1,16 +1,33 @@
-use crate::container::message::SharedMessage;
+use crate::container::message::{
+    monotonic_now_ns, CorruptMessage, MessageInfo, MessageOptions, SharedMessage,
+};
 use crate::helpers::bytes::RustPyBytes;
+use crate::helpers::event_fd::EventFd;
 use crate::helpers::queue_data::SenderQueueData;
+use crate::primitives::latency::ReaderLatency;
 use crate::primitives::memory_holder::SharedMemoryHolder;
+use crate::primitives::wait_counters::WaitCounters;
+use crate::python::pickle::{reduce, Reduced};
+use crate::python::stats::{latency_dict, stats_dict};
+use crate::python::stream::{
+    create_stream_queue, open_stream_queue, PythonMessageStreamReader, PythonMessageStreamWriter,
+    StreamQueue,
+};
+use crate::python::subscription::{Poll, PythonSubscription, SUBSCRIPTION_TIMEOUT};
 use crate::python::OpenMode;
-use pyo3::exceptions::PyValueError;
-use pyo3::types::PyBytes;
-use pyo3::{pyclass, pymethods, Bound, PyResult, Python};
+use pyo3::exceptions::{PyFileNotFoundError, PyValueError};
+use pyo3::types::{PyBytes, PyDict, PyTuple};
+use pyo3::{pyclass, pymethods, Bound, PyObject, PyRef, PyResult, Python};
 use std::ffi::CString;
 use std::num::NonZeroU32;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc::{channel, Sender};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// How often the watcher thread checks if its message handle was dropped
+const WATCHER_TIMEOUT: Duration = Duration::from_millis(100);
 
 #[pyclass]
 #[pyo3(frozen, name = "SharedMessage")]
@@ -21,6 +38,17 @@ pub struct PythonSharedMessage {
     last_written_version: Arc<AtomicUsize>,
     last_read_version: AtomicUsize,
     sender: Mutex<Option<Sender<SenderQueueData>>>,
+    /// Created with the message when streaming is enabled, otherwise opened on first use
+    stream_queue: Mutex<Option<StreamQueue>>,
+    /// Created on the first call to `fileno`, together with the thread that signals it
+    event_fd: Mutex<Option<Arc<EventFd>>>,
+    /// Joined when the handle is dropped, so that the segment does not outlive the handle
+    watcher: Mutex<Option<JoinHandle<()>>>,
+    /// Only the reads of this handle, the segment also keeps those of all its readers.
+    /// Boxed, as Python does not align its objects to a cache line
+    latency: Box<ReaderLatency>,
+    /// Set by `release`, after which the handle no longer counts as a reader
+    released: AtomicBool,
 }
 
 impl PythonSharedMessage {
@@ -40,15 +68,219 @@ impl PythonSharedMessage {
             last_written_version: Arc::default(),
             last_read_version: AtomicUsize::default(),
             sender: Mutex::default(),
+            stream_queue: Mutex::default(),
+            event_fd: Mutex::default(),
+            watcher: Mutex::default(),
+            latency: Box::default(),
+            released: AtomicBool::default(),
+        }
+    }
+
+    pub(crate) fn open_mode(&self) -> OpenMode {
+        self.open_mode
+    }
+
+    /// Signals the file descriptor every time a new version is written, and once the message is closed.
+    /// Stops once the handle owning the file descriptor is dropped
+    fn start_watcher_thread(
+        shared_memory: Arc<SharedMemoryHolder<SharedMessage>>,
+        event_fd: Weak<EventFd>,
+        mut version: usize,
+    ) -> JoinHandle<()> {
+        std::thread::spawn(move || loop {
+            let new_version = shared_memory.wait_for_new_version(version, WATCHER_TIMEOUT);
+            let closed = shared_memory.is_closed();
+            let Some(event_fd) = event_fd.upgrade() else {
+                break;
+            };
+
+            if new_version != version || closed {
+                version = new_version;
+                event_fd.signal();
+            }
+            if closed {
+                break;
+            }
+        })
+    }
+
+    fn stream_queue(&self) -> PyResult<StreamQueue> {
+        let mut guard = self.stream_queue.lock().unwrap();
+        if let Some(queue) = guard.as_ref() {
+            return Ok(queue.clone());
+        }
+
+        let queue = open_stream_queue(&self.name).map_err(|e| {
+            Python::with_gil(|py| {
+                if e.is_instance_of::<PyFileNotFoundError>(py) {
+                    PyValueError::new_err("Streaming was not enabled when the message was created")
+                } else {
+                    e
+                }
+            })
+        })?;
+        Ok(guard.insert(queue).clone())
+    }
+
+    /// Writes the parts one after the other, as a single message
+    pub(crate) fn write_parts(&self, parts: &[&[u8]], py: Python<'_>) -> PyResult<()> {
+        self.open_mode.check_write_permission();
+
+        let size = parts.iter().map(|part| part.len()).sum::<usize>();
+        if size > self.payload_max_size() {
+            return Err(PyValueError::new_err(format!(
+                "Message is too large to be sent! Max size: {}. Current message size: {}",
+                self.payload_max_size(),
+                size
+            )));
+        }
+
+        py.allow_threads(|| {
+            let stats = self.shared_memory.stats();
+            let version = stats.observe_write(|| self.shared_memory.write_parts(parts));
+            stats.add_writes(1, size);
+            self.last_written_version.store(version, Ordering::Relaxed);
+        });
+
+        Ok(())
+    }
+
+    fn try_read_with_info(&self) -> Result<Option<(RustPyBytes, MessageInfo)>, CorruptMessage> {
+        self.open_mode.check_read_permission();
+        let last_read_version = self.last_read_version.load(Ordering::Relaxed);
+
+        let mut result = None;
+        let mut read_ns = 0;
+        let counters = WaitCounters::current();
+
+        self.shared_memory
+            .stats()
+            .observe_read(|| {
+                self.shared_memory
+                    .try_read(last_read_version, |info, data| {
+                        read_ns = monotonic_now_ns();
+                        result = Some((RustPyBytes::new(data), *info));
+                    })
+            })
+            .inspect_err(|e| self.skip_corrupt_message(e))?;
+
+        if let Some((data, info)) = &result {
+            self.record_read(data, info, read_ns, counters.elapsed());
+        }
+        Ok(result)
+    }
+
+    fn blocking_read_with_info(
+        &self,
+    ) -> Result<Option<(RustPyBytes, MessageInfo)>, CorruptMessage> {
+        self.open_mode.check_read_permission();
+        let last_read_version = self.last_read_version.load(Ordering::Relaxed);
+
+        let mut result = None;
+        let mut read_ns = 0;
+        let counters = WaitCounters::current();
+
+        self.shared_memory
+            .stats()
+            .observe_read(|| {
+                self.shared_memory
+                    .blocking_read(last_read_version, |info, data| {
+                        read_ns = monotonic_now_ns();
+                        result = Some((RustPyBytes::new(data), *info));
+                    })
+            })
+            .inspect_err(|e| self.skip_corrupt_message(e))?;
+
+        if let Some((data, info)) = &result {
+            self.record_read(data, info, read_ns, counters.elapsed());
+        }
+        Ok(result)
+    }
+
+    /// Counts the read in the statistics and latencies of the segment, and in those of this reader
+    fn record_read(
+        &self,
+        data: &RustPyBytes,
+        info: &MessageInfo,
+        read_ns: u64,
+        waits: WaitCounters,
+    ) {
+        self.shared_memory.stats().add_reads(1, data.0.len());
+        for latency in [self.shared_memory.latency(), &self.latency] {
+            latency.record_read(info.monotonic_ns, read_ns, waits.lock_wait_ns, 1);
         }
+        self.last_read_version
+            .store(info.version, Ordering::Relaxed);
+    }
+
+    /// The next read should not return the same corrupt message again
+    fn skip_corrupt_message(&self, e: &CorruptMessage) {
+        self.shared_memory.stats().add_corrupt_read();
+        self.last_read_version.store(e.version, Ordering::Relaxed);
+    }
+}
+
+/// Describes when and by whom a message was written
+#[pyclass]
+#[pyo3(frozen, name = "MessageInfo")]
+pub struct PythonMessageInfo(pub(crate) MessageInfo);
+
+#[pymethods]
+impl PythonMessageInfo {
+    #[getter]
+    fn version(&self) -> usize {
+        self.0.version
+    }
+
+    #[getter]
+    fn monotonic_ns(&self) -> u64 {
+        self.0.monotonic_ns
+    }
+
+    #[getter]
+    fn realtime_ns(&self) -> u64 {
+        self.0.realtime_ns
+    }
+
+    #[getter]
+    fn writer_pid(&self) -> u32 {
+        self.0.writer_pid
+    }
+
+    #[getter]
+    fn writer_tid(&self) -> u32 {
+        self.0.writer_tid
+    }
+
+    /// Nanoseconds elapsed on the monotonic clock since the message was written
+    fn age_ns(&self) -> u64 {
+        monotonic_now_ns().saturating_sub(self.0.monotonic_ns)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "MessageInfo(version={}, monotonic_ns={}, realtime_ns={}, writer_pid={}, writer_tid={})",
+            self.0.version,
+            self.0.monotonic_ns,
+            self.0.realtime_ns,
+            self.0.writer_pid,
+            self.0.writer_tid
+        )
     }
 }
 
 #[pymethods]
 impl PythonSharedMessage {
     #[staticmethod]
-    #[pyo3(signature = (name, size, mode=OpenMode::ReadWrite))]
-    fn create(name: String, size: NonZeroU32, mode: OpenMode) -> PyResult<Self> {
+    #[pyo3(signature = (name, size, mode=OpenMode::ReadWrite, checksum=false, stream=false, priority_inheritance=false))]
+    pub(crate) fn create(
+        name: String,
+        size: NonZeroU32,
+        mode: OpenMode,
+        checksum: bool,
+        stream: bool,
+        priority_inheritance: bool,
+    ) -> PyResult<Self> {
         if name.is_empty() {
             return Err(PyValueError::new_err("Name cannot be empty"));
         }
@@ -61,13 +293,23 @@ impl PythonSharedMessage {
                 SharedMessage::size_of_fields() + size.get() as usize,
             )?
         };
+        shared_memory.init(MessageOptions {
+            checksum,
+            priority_inheritance,
+        });
 
-        Ok(Self::new(shared_memory, name, mode))
+        let message = Self::new(shared_memory, name, mode);
+        if stream {
+            let queue = create_stream_queue(&message.name, size.get() as usize)?;
+            *message.stream_queue.lock().unwrap() = Some(queue);
+        }
+
+        Ok(message)
     }
 
     #[staticmethod]
     #[pyo3(signature = (name, mode=OpenMode::ReadWrite))]
-    fn open(name: String, mode: OpenMode) -> PyResult<Self> {
+    pub(crate) fn open(name: String, mode: OpenMode) -> PyResult<Self> {
         if name.is_empty() {
             return Err(PyValueError::new_err("Name cannot be empty"));
         }
@@ -89,7 +331,9 @@ impl PythonSharedMessage {
         }
 
         py.allow_threads(|| {
-            let version = self.shared_memory.write(data);
+            let stats = self.shared_memory.stats();
+            let version = stats.observe_write(|| self.shared_memory.write(data));
+            stats.add_writes(1, data.len());
             self.last_written_version.store(version, Ordering::Relaxed);
         });
 
@@ -114,9 +358,12 @@ impl PythonSharedMessage {
         }
 
         py.allow_threads(|| {
-            let version = self
-                .shared_memory
-                .write_waiting_for_readers(data, wait_for_readers);
+            let stats = self.shared_memory.stats();
+            let version = stats.observe_write(|| {
+                self.shared_memory
+                    .write_waiting_for_readers(data, wait_for_readers)
+            });
+            stats.add_writes(1, data.len());
             self.last_written_version.store(version, Ordering::Relaxed);
         });
 
@@ -150,7 +397,9 @@ impl PythonSharedMessage {
                 if shared_memory.is_closed() {
                     break;
                 }
-                let new_version = shared_memory.write(data.bytes());
+                let stats = shared_memory.stats();
+                let new_version = stats.observe_write(|| shared_memory.write(data.bytes()));
+                stats.add_writes(1, data.bytes().len());
 
                 last_written_version.store(new_version, Ordering::Relaxed);
             });
@@ -163,39 +412,83 @@ impl PythonSharedMessage {
         })
     }
 
-    pub fn try_read(&self) -> Option<RustPyBytes> {
+    pub fn try_read(&self) -> PyResult<Option<RustPyBytes>> {
+        Ok(self.try_read_with_info()?.map(|(data, _)| data))
+    }
+
+    pub(crate) fn blocking_read(&self, py: Python<'_>) -> PyResult<Option<RustPyBytes>> {
+        Ok(py
+            .allow_threads(|| self.blocking_read_with_info())?
+            .map(|(data, _)| data))
+    }
+
+    #[pyo3(signature = (blocking = false))]
+    fn read_with_info(
+        &self,
+        blocking: bool,
+        py: Python<'_>,
+    ) -> PyResult<Option<(RustPyBytes, PythonMessageInfo)>> {
+        let result = if blocking {
+            py.allow_threads(|| self.blocking_read_with_info())?
+        } else {
+            self.try_read_with_info()?
+        };
+
+        Ok(result.map(|(data, info)| (data, PythonMessageInfo(info))))
+    }
+
+    fn peek(&self) -> PyResult<Option<RustPyBytes>> {
         self.open_mode.check_read_permission();
-        let last_read_version = self.last_read_version.load(Ordering::Relaxed);
 
         let mut result = None;
+        self.shared_memory.peek(|_, data| {
+            result = Some(RustPyBytes::new(data));
+        })?;
 
-        self.shared_memory
-            .try_read(last_read_version, |new_version, data| {
-                self.last_read_version.store(new_version, Ordering::Relaxed);
-                result = Some(RustPyBytes::new(data));
-            });
+        Ok(result)
+    }
 
-        result
+    /// Returns a file-like writer, whose data is sent in chunks that fit in the message size.
+    /// Each stream is received by a single reader, and only one writer may stream at a time
+    fn open_stream(&self) -> PyResult<PythonMessageStreamWriter> {
+        self.open_mode.check_write_permission();
+        Ok(PythonMessageStreamWriter::new(self.stream_queue()?))
     }
 
-    fn blocking_read(&self, py: Python<'_>) -> Option<RustPyBytes> {
+    /// Returns a file-like reader for the next stream sent with [`Self::open_stream`]
+    fn read_stream(&self) -> PyResult<PythonMessageStreamReader> {
         self.open_mode.check_read_permission();
+        Ok(PythonMessageStreamReader::new(self.stream_queue()?))
+    }
 
-        let mut result = None;
+    /// Returns a file descriptor that becomes readable when a new version is written,
+    /// so that the message can be used with `selectors` and event loops
+    fn fileno(&self) -> PyResult<i32> {
+        self.open_mode.check_read_permission();
 
-        py.allow_threads(|| {
-            let last_read_version = self.last_read_version.load(Ordering::Relaxed);
-            self.shared_memory
-                .blocking_read(last_read_version, |new_version, data| {
-                    self.last_read_version.store(new_version, Ordering::Relaxed);
-                    result = Some(RustPyBytes::new(data));
-                });
-        });
+        let mut guard = self.event_fd.lock().unwrap();
+        if let Some(event_fd) = guard.as_ref() {
+            return Ok(event_fd.raw_fd());
+        }
 
-        result
+        let event_fd = Arc::new(EventFd::new()?);
+        let watcher = Self::start_watcher_thread(
+            self.shared_memory.clone(),
+            Arc::downgrade(&event_fd),
+            self.last_read_version.load(Ordering::Relaxed),
+        );
+        *self.watcher.lock().unwrap() = Some(watcher);
+        Ok(guard.insert(event_fd).raw_fd())
     }
 
-    fn is_new_version_available(&self) -> bool {
+    /// Makes the file descriptor returned by [`Self::fileno`] not readable, until the next version is written
+    fn clear(&self) {
+        if let Some(event_fd) = self.event_fd.lock().unwrap().as_ref() {
+            event_fd.clear();
+        }
+    }
+
+    pub(crate) fn is_new_version_available(&self) -> bool {
         self.open_mode.check_read_permission();
 
         let last_read_version = self.last_read_version.load(Ordering::Relaxed);
@@ -211,27 +504,129 @@ impl PythonSharedMessage {
         self.last_read_version.load(Ordering::Relaxed)
     }
 
-    fn name(&self) -> &str {
+    pub(crate) fn name(&self) -> &str {
         &self.name
     }
 
-    fn payload_max_size(&self) -> usize {
+    pub(crate) fn payload_max_size(&self) -> usize {
         self.shared_memory.mapped_memory_size() - SharedMessage::size_of_fields()
     }
 
-    fn is_closed(&self) -> bool {
+    /// Counters shared by every process using the message, see `reset_stats`
+    fn stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        stats_dict(self.shared_memory.stats(), py)
+    }
+
+    fn reset_stats(&self) {
+        self.shared_memory.stats().reset();
+    }
+
+    /// Calls `callback` with every new payload from a background thread, until unsubscribed or closed.
+    /// The reads are those of this handle, so it should not be read from elsewhere meanwhile
+    #[pyo3(signature = (callback, *, executor = None))]
+    pub(crate) fn subscribe(
+        slf: &Bound<'_, Self>,
+        callback: PyObject,
+        executor: Option<PyObject>,
+    ) -> PythonSubscription {
+        slf.get().open_mode.check_read_permission();
+
+        let handle = slf.clone().unbind();
+        let poll = move || {
+            let handle = handle.get();
+            let version = handle.last_read_version.load(Ordering::Relaxed);
+            handle
+                .shared_memory
+                .wait_for_new_version(version, SUBSCRIPTION_TIMEOUT);
+
+            match handle.try_read_with_info() {
+                Ok(Some((data, _))) => Poll::Payload(data),
+                Ok(None) if handle.shared_memory.is_closed() => Poll::Closed,
+                Ok(None) => Poll::Empty,
+                // Counted in the statistics of the message, as there is nobody to raise it to
+                Err(_) => Poll::Empty,
+            }
+        };
+
+        PythonSubscription::start(poll, callback, executor)
+    }
+
+    /// Percentiles of the latencies of the reads made with this handle, in nanoseconds
+    fn latency_stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        latency_dict(&self.latency, py)
+    }
+
+    fn has_checksum(&self) -> bool {
+        self.shared_memory.has_checksum()
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
         self.shared_memory.is_closed()
     }
 
-    fn close(&self) {
+    pub(crate) fn close(&self) {
         self.open_mode.check_write_permission();
         self.shared_memory.close();
+
+        // The stream queue only exists if streaming was enabled
+        let queue = self.stream_queue.lock().unwrap().clone();
+        if let Some(queue) = queue.or_else(|| open_stream_queue(&self.name).ok()) {
+            queue.close();
+        }
+    }
+
+    /// If this handle created the message, closes it when the handle can write,
+    /// and removes its name and that of its stream queue.
+    /// Handles that only opened the message leave it open for the other processes.
+    /// The handle no longer counts as a reader, so it should not be used afterwards
+    fn release(&self) -> PyResult<()> {
+        if self.shared_memory.is_creator() && self.open_mode.can_write() {
+            self.close();
+        }
+        if self.open_mode.can_read() && !self.released.swap(true, Ordering::Relaxed) {
+            self.shared_memory.remove_reader();
+        }
+        if let Some(queue) = self.stream_queue.lock().unwrap().as_ref() {
+            queue.unlink()?;
+        }
+        Ok(self.shared_memory.unlink()?)
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    #[pyo3(signature = (*_args))]
+    fn __exit__(&self, _args: &Bound<'_, PyTuple>) -> PyResult<()> {
+        self.release()
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    /// Waits for the next version, the iteration stops once the message is closed
+    fn __next__(&self, py: Python<'_>) -> PyResult<Option<RustPyBytes>> {
+        self.blocking_read(py)
+    }
+
+    /// Pickled as its name and mode, the process unpickling it opens the message again
+    fn __reduce__<'py>(&self, py: Python<'py>) -> PyResult<Reduced<'py>> {
+        reduce(py, "message", &self.name, self.open_mode)
     }
 }
 
 impl Drop for PythonSharedMessage {
     fn drop(&mut self) {
-        if self.open_mode.can_read() {
+        // The watcher stops once the file descriptor is dropped. Otherwise its copy of the segment
+        // would be the last one, and would unlink the name of the creator once it wakes up,
+        // even if a new segment was created with the same name in the meantime
+        if let Some(watcher) = self.watcher.get_mut().unwrap().take() {
+            self.event_fd.get_mut().unwrap().take();
+            let _ = watcher.join();
+        }
+
+        if self.open_mode.can_read() && !*self.released.get_mut() {
             self.shared_memory.remove_reader();
         }
     }
@@ -240,6 +635,8 @@ impl Drop for PythonSharedMessage {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::python::errors::CorruptMessageError;
+    use pyo3::types::{PyAnyMethods, PyBytesMethods, PyDictMethods};
     use std::num::NonZero;
     use std::thread;
     use std::time::Duration;
@@ -251,6 +648,9 @@ mod tests {
             name.to_string(),
             NonZero::new(size).unwrap(),
             OpenMode::ReadWrite,
+            false,
+            false,
+            false,
         )
         .unwrap()
     }
@@ -262,38 +662,130 @@ mod tests {
 
         Python::with_gil(|py| {
             let memory = init("simple_write_try_read", DEFAULT_SIZE);
-            let none = memory.try_read();
+            let none = memory.try_read().unwrap();
             assert!(none.is_none());
 
             memory.write(&data, py).unwrap();
             let version = memory.last_written_version();
 
-            let bytes = memory.try_read().unwrap();
+            let bytes = memory.try_read().unwrap().unwrap();
             assert_eq!(bytes.0.as_ref(), data);
             assert_eq!(version, memory.last_read_version());
 
-            assert!(memory.try_read().is_none());
+            assert!(memory.try_read().unwrap().is_none());
             memory.close();
             assert!(memory.is_closed());
         });
     }
 
+    #[test]
+    fn latency_of_reads() {
+        Python::with_gil(|py| {
+            let memory = init("/message_latency_of_reads", DEFAULT_SIZE);
+            let reader = PythonSharedMessage::open(
+                "/message_latency_of_reads".to_string(),
+                OpenMode::ReadOnly,
+            )
+            .unwrap();
+
+            memory.write(&[1, 2, 3], py).unwrap();
+            assert!(memory.try_read().unwrap().is_some());
+            assert!(reader.blocking_read(py).unwrap().is_some());
+
+            let latency = reader.latency_stats(py).unwrap();
+            let write_to_read = latency.get_item("write_to_read").unwrap().unwrap();
+            let value = |key| {
+                write_to_read
+                    .get_item(key)
+                    .unwrap()
+                    .extract::<u64>()
+                    .unwrap()
+            };
+            assert_eq!(value("count"), 1);
+            assert!(value("p50") <= value("max"));
+
+            let [(_, write_to_read), (_, lock_wait)] =
+                crate::inspect::latency("/message_latency_of_reads").unwrap();
+            assert_eq!((write_to_read.count, lock_wait.count), (2, 2));
+            assert!(write_to_read.max > 0);
+            memory.close();
+        });
+    }
+
+    #[test]
+    fn release_closes_and_unlinks() {
+        Python::with_gil(|py| {
+            let memory = init("/message_release_closes_and_unlinks", DEFAULT_SIZE);
+            let reader = PythonSharedMessage::open(
+                "/message_release_closes_and_unlinks".to_string(),
+                OpenMode::ReadOnly,
+            )
+            .unwrap();
+
+            memory.write(&[1], py).unwrap();
+            assert_eq!(
+                reader.__next__(py).unwrap().unwrap(),
+                RustPyBytes::new(&[1])
+            );
+
+            // Only the handle that created the message closes it
+            let other_writer = PythonSharedMessage::open(
+                "/message_release_closes_and_unlinks".to_string(),
+                OpenMode::WriteOnly,
+            )
+            .unwrap();
+            other_writer.release().unwrap();
+            assert!(!reader.is_closed());
+
+            memory.release().unwrap();
+            memory.release().unwrap();
+            assert!(reader.is_closed());
+            assert!(reader.__next__(py).unwrap().is_none());
+            assert!(PythonSharedMessage::open(
+                "/message_release_closes_and_unlinks".to_string(),
+                OpenMode::ReadOnly
+            )
+            .is_err());
+        });
+    }
+
+    #[test]
+    fn release_unlinks_stream_queue() {
+        Python::with_gil(|_| {
+            let name = "/message_release_unlinks_stream_queue";
+            let memory = PythonSharedMessage::create(
+                name.to_string(),
+                NonZeroU32::new(DEFAULT_SIZE).unwrap(),
+                OpenMode::ReadWrite,
+                false,
+                true,
+                false,
+            )
+            .unwrap();
+            let queue = open_stream_queue(name).unwrap();
+
+            memory.release().unwrap();
+            assert!(queue.is_closed());
+            assert!(open_stream_queue(name).is_err());
+        });
+    }
+
     #[test]
     fn simple_write_blocking_read() {
         let data = (0u8..255u8).collect::<Vec<_>>();
 
         Python::with_gil(|py| {
             let memory = init("simple_write_blocking_read", DEFAULT_SIZE);
-            assert!(memory.try_read().is_none());
+            assert!(memory.try_read().unwrap().is_none());
 
             memory.write(&data, py).unwrap();
             let version = memory.last_written_version();
 
-            let bytes = memory.blocking_read(py).unwrap();
+            let bytes = memory.blocking_read(py).unwrap().unwrap();
             assert_eq!(bytes.0.as_ref(), data);
             assert_eq!(version, memory.last_read_version());
 
-            assert!(memory.try_read().is_none());
+            assert!(memory.try_read().unwrap().is_none());
             memory.close();
         });
     }
@@ -304,20 +796,20 @@ mod tests {
 
         Python::with_gil(|py| {
             let memory = init("simple_write_blocking_read_close", DEFAULT_SIZE);
-            assert!(memory.try_read().is_none());
+            assert!(memory.try_read().unwrap().is_none());
 
             memory.write(&data, py).unwrap();
             let version = memory.last_written_version();
 
-            let bytes = memory.blocking_read(py).unwrap();
+            let bytes = memory.blocking_read(py).unwrap().unwrap();
             assert_eq!(bytes.0.as_ref(), data);
             assert_eq!(version, memory.last_read_version());
 
-            assert!(memory.try_read().is_none());
+            assert!(memory.try_read().unwrap().is_none());
 
             memory.close();
-            assert!(memory.blocking_read(py).is_none());
-            assert!(memory.try_read().is_none());
+            assert!(memory.blocking_read(py).unwrap().is_none());
+            assert!(memory.try_read().unwrap().is_none());
         });
     }
 
@@ -332,8 +824,216 @@ mod tests {
             memory.write_async(PyBytes::new(py, &[4])).unwrap();
             thread::sleep(Duration::from_millis(100));
             assert!(memory.is_new_version_available());
-            assert_eq!(memory.blocking_read(py).unwrap(), RustPyBytes::new(&[4]));
+            assert_eq!(
+                memory.blocking_read(py).unwrap().unwrap(),
+                RustPyBytes::new(&[4])
+            );
+            memory.close();
+        });
+    }
+
+    #[test]
+    fn peek() {
+        Python::with_gil(|py| {
+            let memory = init("peek", DEFAULT_SIZE);
+            assert!(memory.peek().unwrap().is_none());
+
+            memory.write(&[1, 2, 3], py).unwrap();
+            assert_eq!(
+                memory.peek().unwrap().unwrap(),
+                RustPyBytes::new(&[1, 2, 3])
+            );
+            assert_eq!(
+                memory.peek().unwrap().unwrap(),
+                RustPyBytes::new(&[1, 2, 3])
+            );
+            assert_eq!(memory.last_read_version(), 0);
+            assert!(memory.is_new_version_available());
+
+            assert_eq!(
+                memory.try_read().unwrap().unwrap(),
+                RustPyBytes::new(&[1, 2, 3])
+            );
+            assert_eq!(
+                memory.peek().unwrap().unwrap(),
+                RustPyBytes::new(&[1, 2, 3])
+            );
+            memory.close();
+            assert!(memory.peek().unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn read_with_info() {
+        Python::with_gil(|py| {
+            let memory = init("read_with_info", DEFAULT_SIZE);
+            assert!(memory.read_with_info(false, py).unwrap().is_none());
+
+            let before = monotonic_now_ns();
+            memory.write(&[1, 2, 3], py).unwrap();
+
+            let (bytes, info) = memory.read_with_info(true, py).unwrap().unwrap();
+            assert_eq!(bytes, RustPyBytes::new(&[1, 2, 3]));
+            assert_eq!(info.version(), memory.last_written_version());
+            assert!(info.monotonic_ns() >= before);
+            assert!(info.realtime_ns() > 0);
+            assert_eq!(info.writer_pid(), std::process::id());
+            assert_ne!(info.writer_tid(), 0);
+
+            assert!(memory.read_with_info(false, py).unwrap().is_none());
+            memory.close();
+        });
+    }
+
+    #[test]
+    fn checksum() {
+        Python::with_gil(|py| {
+            let memory = PythonSharedMessage::create(
+                "message_checksum".to_string(),
+                NonZero::new(DEFAULT_SIZE).unwrap(),
+                OpenMode::ReadWrite,
+                true,
+                false,
+                false,
+            )
+            .unwrap();
+            assert!(memory.has_checksum());
+
+            memory.write(&[1, 2, 3], py).unwrap();
+            assert_eq!(
+                memory.try_read().unwrap().unwrap(),
+                RustPyBytes::new(&[1, 2, 3])
+            );
+
+            memory.write(&[4, 5, 6], py).unwrap();
+            // Simulate a writer that crashed in the middle of a copy
+            unsafe {
+                let message = &**memory.shared_memory as *const SharedMessage as *mut u8;
+                *message.add(SharedMessage::size_of_fields() + 1) = 0;
+            }
+            let error = memory.try_read().unwrap_err();
+            assert!(error.is_instance_of::<CorruptMessageError>(py));
+            assert_eq!(memory.last_read_version(), memory.last_written_version());
+            assert!(memory.try_read().unwrap().is_none());
+            let corrupt_reads = memory.stats(py).unwrap().get_item("corrupt_reads").unwrap();
+            assert_eq!(corrupt_reads.unwrap().extract::<u64>().unwrap(), 1);
+
+            memory.close();
+        });
+    }
+
+    #[test]
+    fn fileno_signals_new_versions() {
+        use rustix::event::{poll, PollFd, PollFlags};
+        use rustix::fd::BorrowedFd;
+
+        let is_readable = |fd: i32, timeout: i32| {
+            let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+            poll(&mut [PollFd::new(&fd, PollFlags::IN)], timeout).unwrap() == 1
+        };
+
+        Python::with_gil(|py| {
+            let memory = init("message_fileno", DEFAULT_SIZE);
+            let fd = memory.fileno().unwrap();
+            assert_eq!(memory.fileno().unwrap(), fd);
+            assert!(!is_readable(fd, 10));
+
+            memory.write(&[1], py).unwrap();
+            assert!(is_readable(fd, 1000));
+            memory.clear();
+            assert!(!is_readable(fd, 10));
+
+            memory.write(&[2], py).unwrap();
+            assert!(is_readable(fd, 1000));
+            memory.clear();
+
+            memory.close();
+            assert!(is_readable(fd, 1000));
+        });
+    }
+
+    #[test]
+    fn drop_stops_the_watcher() {
+        Python::with_gil(|py| {
+            let name = "/message_drop_stops_the_watcher";
+            let memory = init(name, DEFAULT_SIZE);
+            memory.fileno().unwrap();
+            drop(memory);
+
+            // The segment of the first message must not unlink the name of the second one
+            let memory = init(name, DEFAULT_SIZE);
+            py.allow_threads(|| std::thread::sleep(WATCHER_TIMEOUT * 2));
+            assert!(PythonSharedMessage::open(name.to_string(), OpenMode::ReadOnly).is_ok());
             memory.close();
         });
     }
+
+    #[test]
+    fn priority_inheritance() {
+        Python::with_gil(|py| {
+            let writer = PythonSharedMessage::create(
+                "message_priority_inheritance".to_string(),
+                NonZero::new(DEFAULT_SIZE).unwrap(),
+                OpenMode::WriteOnly,
+                false,
+                false,
+                true,
+            )
+            .unwrap();
+            let reader = PythonSharedMessage::open(
+                "message_priority_inheritance".to_string(),
+                OpenMode::ReadOnly,
+            )
+            .unwrap();
+
+            // Versions can be skipped, so the reader stops once it sees the last one
+            let reading = thread::spawn(move || {
+                Python::with_gil(|py| {
+                    while reader.blocking_read(py).unwrap().unwrap() != RustPyBytes::new(&[99]) {}
+                })
+            });
+            for i in 0..100u8 {
+                writer.write(&[i], py).unwrap();
+            }
+
+            py.allow_threads(|| reading.join().unwrap());
+        });
+    }
+
+    #[test]
+    fn stream() {
+        let data = (0..=255u8).cycle().take(5000).collect::<Vec<_>>();
+
+        Python::with_gil(|py| {
+            let writer = PythonSharedMessage::create(
+                "message_stream".to_string(),
+                NonZero::new(64).unwrap(),
+                OpenMode::WriteOnly,
+                false,
+                true,
+                false,
+            )
+            .unwrap();
+            let reader =
+                PythonSharedMessage::open("message_stream".to_string(), OpenMode::ReadOnly)
+                    .unwrap();
+            assert!(init("message_no_stream", DEFAULT_SIZE)
+                .read_stream()
+                .is_err());
+
+            let reader_thread = {
+                let mut stream = reader.read_stream().unwrap();
+                thread::spawn(move || {
+                    Python::with_gil(|py| stream.readall(py).unwrap().as_bytes().to_vec())
+                })
+            };
+
+            let mut stream = writer.open_stream().unwrap();
+            assert_eq!(stream.write(&data, py).unwrap(), data.len());
+            stream.close(py).unwrap();
+
+            let received = py.allow_threads(|| reader_thread.join().unwrap());
+            assert_eq!(received, data);
+        });
+    }
 }
@@ -1,30 +1,86 @@
 use crate::helpers::bytes::RustPyBytes;
-use crate::python::message::PythonSharedMessage;
+use crate::python::array::PythonSharedArray;
+use crate::python::backpressure::Backpressure;
+use crate::python::bridge::PythonBridge;
+use crate::python::errors::CorruptMessageError;
+use crate::python::message::{PythonMessageInfo, PythonSharedMessage};
 use crate::python::open_mode::OpenMode;
+use crate::python::pickle::_reopen;
+use crate::python::pipe::PythonSharedPipe;
+use crate::python::priority_queue::PythonSharedPriorityQueue;
 use crate::python::queue::PythonSharedQueue;
+use crate::python::record::{PythonRecorder, PythonReplayer};
+use crate::python::rpc::{PythonServiceRequest, PythonSharedClient, PythonSharedService};
+use crate::python::stream::{PythonMessageStreamReader, PythonMessageStreamWriter};
+use crate::python::subscription::PythonSubscription;
+use crate::python::sync::{
+    PythonBarrier, PythonCondition, PythonEvent, PythonLock, PythonSemaphore,
+};
 use pyo3::prelude::*;
 use pyo3::types::PyFunction;
 use pyo3::{pymodule, Bound, PyResult};
 use rayon::prelude::*;
 
+mod array;
+mod backpressure;
+mod bridge;
+mod errors;
 mod message;
 mod open_mode;
+mod pickle;
+mod pipe;
+mod priority_queue;
 mod queue;
+mod record;
+mod rpc;
+mod stats;
+mod stream;
+mod subscription;
+mod sync;
 
 #[pymodule(gil_used = false)]
 fn ripc(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<OpenMode>()?;
+    m.add_class::<Backpressure>()?;
     m.add_class::<PythonSharedMessage>()?;
+    m.add_class::<PythonMessageInfo>()?;
     m.add_class::<PythonSharedQueue>()?;
+    m.add_class::<PythonSharedPriorityQueue>()?;
+    m.add_class::<PythonSharedArray>()?;
+    m.add_class::<PythonSharedPipe>()?;
+    m.add_class::<PythonSharedService>()?;
+    m.add_class::<PythonSharedClient>()?;
+    m.add_class::<PythonServiceRequest>()?;
+    m.add_class::<PythonLock>()?;
+    m.add_class::<PythonCondition>()?;
+    m.add_class::<PythonSemaphore>()?;
+    m.add_class::<PythonEvent>()?;
+    m.add_class::<PythonBarrier>()?;
+    m.add_class::<PythonMessageStreamWriter>()?;
+    m.add_class::<PythonMessageStreamReader>()?;
+    m.add_class::<PythonRecorder>()?;
+    m.add_class::<PythonReplayer>()?;
+    m.add_class::<PythonBridge>()?;
+    m.add_class::<PythonSubscription>()?;
+
+    m.add(
+        "CorruptMessageError",
+        m.py().get_type::<CorruptMessageError>(),
+    )?;
 
     m.add_function(wrap_pyfunction!(read_all, m)?)?;
     m.add_function(wrap_pyfunction!(read_all_map, m)?)?;
+    m.add_function(wrap_pyfunction!(cleanup, m)?)?;
+    m.add_function(wrap_pyfunction!(_reopen, m)?)?;
 
     Ok(())
 }
 
 #[pyfunction]
-fn read_all(readers: Vec<Py<PythonSharedMessage>>, py: Python<'_>) -> Vec<Option<RustPyBytes>> {
+fn read_all(
+    readers: Vec<Py<PythonSharedMessage>>,
+    py: Python<'_>,
+) -> PyResult<Vec<Option<RustPyBytes>>> {
     py.allow_threads(|| {
         readers
             .into_par_iter()
@@ -38,14 +94,24 @@ fn read_all_map(
     readers: Vec<Py<PythonSharedMessage>>,
     map_operation: Py<PyFunction>,
     py: Python<'_>,
-) -> Vec<Option<Py<PyAny>>> {
+) -> PyResult<Vec<Option<Py<PyAny>>>> {
     py.allow_threads(|| {
         readers
             .into_par_iter()
             .map(|reader| reader.get().try_read())
             .map(|bytes| {
-                bytes.map(|bytes| Python::with_gil(|py| map_operation.call1(py, (bytes,)).unwrap()))
+                bytes.map(|bytes| {
+                    bytes.map(|bytes| {
+                        Python::with_gil(|py| map_operation.call1(py, (bytes,)).unwrap())
+                    })
+                })
             })
             .collect()
     })
 }
+
+#[pyfunction]
+#[pyo3(signature=(prefix=None, dry_run=false))]
+fn cleanup(prefix: Option<String>, dry_run: bool, py: Python<'_>) -> PyResult<Vec<String>> {
+    py.allow_threads(|| Ok(crate::inspect::cleanup(prefix.as_deref(), dry_run)?))
+}
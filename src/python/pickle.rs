@@ -0,0 +1,226 @@
+//! Pickling of the handles, so that they can be passed to `multiprocessing` children.
+//! Every segment has a name, so a handle is pickled as its kind, name and mode,
+//! and the child opens the same segment again. It never owns the name of the segment
+
+use crate::python::array::PythonSharedArray;
+use crate::python::backpressure::Backpressure;
+use crate::python::message::PythonSharedMessage;
+use crate::python::open_mode::OpenMode;
+use crate::python::pipe::PythonSharedPipe;
+use crate::python::priority_queue::PythonSharedPriorityQueue;
+use crate::python::queue::PythonSharedQueue;
+use crate::python::sync::{
+    PythonBarrier, PythonCondition, PythonEvent, PythonLock, PythonSemaphore,
+};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::IntoPyObjectExt;
+use std::num::NonZeroUsize;
+
+pub(crate) type Reduced<'py, A = (&'static str, String, u8)> = (Bound<'py, PyAny>, A);
+
+/// A queue also keeps its `max_pending_writes` and its backpressure
+pub(crate) type QueueReduced<'py> =
+    Reduced<'py, (&'static str, String, u8, Option<NonZeroUsize>, u8)>;
+
+/// The value of `__reduce__` for a handle, calling [`_reopen`] when unpickled.
+/// The mode is pickled as its value, as `OpenMode` cannot be pickled itself
+pub(crate) fn reduce<'py>(
+    py: Python<'py>,
+    kind: &'static str,
+    name: &str,
+    mode: OpenMode,
+) -> PyResult<Reduced<'py>> {
+    let reopen = py.import("ripc")?.getattr("_reopen")?;
+    Ok((reopen, (kind, name.to_string(), mode as u8)))
+}
+
+/// Same as [`reduce`], the backpressure is pickled as its value too
+pub(crate) fn reduce_queue<'py>(
+    py: Python<'py>,
+    name: &str,
+    mode: OpenMode,
+    max_pending_writes: Option<NonZeroUsize>,
+    backpressure: Backpressure,
+) -> PyResult<QueueReduced<'py>> {
+    let reopen = py.import("ripc")?.getattr("_reopen")?;
+    Ok((
+        reopen,
+        (
+            "queue",
+            name.to_string(),
+            mode as u8,
+            max_pending_writes,
+            backpressure as u8,
+        ),
+    ))
+}
+
+fn open_mode(mode: u8) -> PyResult<OpenMode> {
+    match mode {
+        0 => Ok(OpenMode::ReadOnly),
+        1 => Ok(OpenMode::WriteOnly),
+        2 => Ok(OpenMode::ReadWrite),
+        _ => Err(PyValueError::new_err(format!("Invalid open mode {mode}"))),
+    }
+}
+
+fn backpressure(backpressure: u8) -> PyResult<Backpressure> {
+    match backpressure {
+        0 => Ok(Backpressure::Block),
+        1 => Ok(Backpressure::Raise),
+        2 => Ok(Backpressure::DropOldest),
+        3 => Ok(Backpressure::DropNewest),
+        _ => Err(PyValueError::new_err(format!(
+            "Invalid backpressure {backpressure}"
+        ))),
+    }
+}
+
+/// `max_pending_writes` and `backpressure` are only given for queues
+#[pyfunction]
+#[pyo3(signature = (kind, name, mode, max_pending_writes = None, backpressure = 0))]
+pub(crate) fn _reopen(
+    kind: &str,
+    name: String,
+    mode: u8,
+    max_pending_writes: Option<NonZeroUsize>,
+    backpressure: u8,
+    py: Python<'_>,
+) -> PyResult<PyObject> {
+    let mode = open_mode(mode)?;
+    match kind {
+        "message" => PythonSharedMessage::open(name, mode)?.into_py_any(py),
+        "queue" => PythonSharedQueue::open(
+            name,
+            mode,
+            max_pending_writes,
+            self::backpressure(backpressure)?,
+        )?
+        .into_py_any(py),
+        "priority_queue" => PythonSharedPriorityQueue::open(name, mode)?.into_py_any(py),
+        "pipe" => PythonSharedPipe::open(name, mode)?.into_py_any(py),
+        "array" => PythonSharedArray::open(name, mode)?.into_py_any(py),
+        "lock" => PythonLock::open(name)?.into_py_any(py),
+        "condition" => PythonCondition::open(name)?.into_py_any(py),
+        "semaphore" => PythonSemaphore::open(name)?.into_py_any(py),
+        "event" => PythonEvent::open(name)?.into_py_any(py),
+        "barrier" => PythonBarrier::open(name)?.into_py_any(py),
+        _ => Err(PyValueError::new_err(format!("Unknown handle kind {kind}"))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyDict;
+    use std::num::NonZero;
+
+    /// `ripc` is not an installed extension in the tests, so its module is built in place
+    fn register_module(py: Python<'_>) {
+        let module = PyModule::new(py, "ripc").unwrap();
+        crate::python::ripc(&module).unwrap();
+        py.import("sys")
+            .unwrap()
+            .getattr("modules")
+            .unwrap()
+            .downcast_into::<PyDict>()
+            .unwrap()
+            .set_item("ripc", module)
+            .unwrap();
+    }
+
+    #[test]
+    fn pickle_and_reopen() {
+        Python::with_gil(|py| {
+            register_module(py);
+            let pickle = py.import("pickle").unwrap();
+
+            let message = PythonSharedMessage::create(
+                "/pickle_and_reopen".to_string(),
+                NonZero::new(64).unwrap(),
+                OpenMode::WriteOnly,
+                false,
+                false,
+                false,
+            )
+            .unwrap();
+            let reader = PythonSharedMessage::open(message.name().to_string(), OpenMode::ReadOnly)
+                .unwrap()
+                .into_pyobject(py)
+                .unwrap();
+
+            let pickled = pickle.call_method1("dumps", (reader,)).unwrap();
+            let reopened = pickle.call_method1("loads", (pickled,)).unwrap();
+            let reopened = reopened.downcast::<PythonSharedMessage>().unwrap().get();
+            assert_eq!(reopened.name(), "/pickle_and_reopen");
+            assert!(reopened.open_mode() == OpenMode::ReadOnly);
+
+            message.write_parts(&[b"hello"], py).unwrap();
+            assert_eq!(reopened.try_read().unwrap().unwrap().0.as_ref(), b"hello");
+            message.close();
+        });
+    }
+
+    #[test]
+    fn pickle_queue_with_its_backpressure() {
+        Python::with_gil(|py| {
+            register_module(py);
+            let pickle = py.import("pickle").unwrap();
+
+            let queue = PythonSharedQueue::create(
+                "/pickle_queue_with_its_backpressure".to_string(),
+                NonZero::new(64).unwrap(),
+                OpenMode::WriteOnly,
+                NonZero::new(4),
+                Backpressure::DropOldest,
+                false,
+            )
+            .unwrap()
+            .into_pyobject(py)
+            .unwrap();
+
+            let pickled = pickle.call_method1("dumps", (&queue,)).unwrap();
+            let reopened = pickle.call_method1("loads", (pickled,)).unwrap();
+            let args = |queue: &Bound<'_, PyAny>| {
+                queue
+                    .call_method0("__reduce__")
+                    .unwrap()
+                    .get_item(1)
+                    .unwrap()
+                    .extract::<(String, String, u8, Option<usize>, u8)>()
+                    .unwrap()
+            };
+            assert_eq!(
+                args(&reopened),
+                (
+                    "queue".to_string(),
+                    "/pickle_queue_with_its_backpressure".to_string(),
+                    OpenMode::WriteOnly as u8,
+                    Some(4),
+                    Backpressure::DropOldest as u8
+                )
+            );
+            assert_eq!(args(&reopened), args(&queue));
+        });
+    }
+
+    #[test]
+    fn reopen_unknown_kind() {
+        Python::with_gil(|py| {
+            let reopen = |kind, mode, backpressure| {
+                _reopen(
+                    kind,
+                    "/reopen_unknown_kind".to_string(),
+                    mode,
+                    None,
+                    backpressure,
+                    py,
+                )
+            };
+            assert!(reopen("unknown", 0, 0).is_err());
+            assert!(reopen("message", 3, 0).is_err());
+            assert!(reopen("queue", 0, 4).is_err());
+        });
+    }
+}
@@ -0,0 +1,274 @@
+use crate::container::byte_ring::ByteRing;
+use crate::primitives::memory_holder::SharedMemoryHolder;
+use crate::python::pickle::{reduce, Reduced};
+use crate::python::stats::stats_dict;
+use crate::python::OpenMode;
+use pyo3::buffer::PyBuffer;
+use pyo3::exceptions::{PyBrokenPipeError, PyValueError};
+use pyo3::types::{PyBytes, PyDict, PyTuple};
+use pyo3::{pyclass, pymethods, Bound, PyRef, PyResult, Python};
+use std::ffi::CString;
+use std::num::NonZeroU32;
+
+/// Size of the reads used by `read(-1)`
+const READ_ALL_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Binary file-like object, over a byte ring shared between processes
+#[pyclass]
+#[pyo3(frozen, name = "SharedPipe")]
+pub struct PythonSharedPipe {
+    shared_memory: SharedMemoryHolder<ByteRing>,
+    name: String,
+    open_mode: OpenMode,
+}
+
+impl PythonSharedPipe {
+    fn read_into(&self, buffer: &mut [u8], py: Python<'_>) -> usize {
+        let stats = self.shared_memory.stats();
+        let count = py.allow_threads(|| stats.observe_read(|| self.shared_memory.read(buffer)));
+        if count != 0 {
+            stats.add_reads(1, count);
+        }
+        count
+    }
+}
+
+#[pymethods]
+impl PythonSharedPipe {
+    /// `capacity` is the number of bytes that can be written before the writer waits for a reader
+    #[staticmethod]
+    #[pyo3(signature = (name, capacity, mode = OpenMode::ReadWrite))]
+    fn create(name: String, capacity: NonZeroU32, mode: OpenMode) -> PyResult<Self> {
+        if name.is_empty() {
+            return Err(PyValueError::new_err("Name cannot be empty"));
+        }
+        let capacity = capacity.get() as usize;
+
+        let shared_memory = unsafe {
+            SharedMemoryHolder::<ByteRing>::create(
+                CString::new(name.clone())?,
+                ByteRing::compute_size_for(capacity),
+            )?
+        };
+        shared_memory.init(capacity);
+
+        Ok(Self {
+            shared_memory,
+            name,
+            open_mode: mode,
+        })
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (name, mode = OpenMode::ReadWrite))]
+    pub(crate) fn open(name: String, mode: OpenMode) -> PyResult<Self> {
+        if name.is_empty() {
+            return Err(PyValueError::new_err("Name cannot be empty"));
+        }
+
+        let shared_memory =
+            unsafe { SharedMemoryHolder::<ByteRing>::open(CString::new(name.clone())?)? };
+
+        Ok(Self {
+            shared_memory,
+            name,
+            open_mode: mode,
+        })
+    }
+
+    /// Waits until at least one byte is available and returns at most `size` bytes,
+    /// or reads until the pipe is closed if `size` is negative.
+    /// Returns empty bytes once the pipe is closed and drained
+    #[pyo3(signature = (size = -1))]
+    fn read<'py>(&self, size: isize, py: Python<'py>) -> Bound<'py, PyBytes> {
+        self.open_mode.check_read_permission();
+
+        if let Ok(size) = usize::try_from(size) {
+            // A single read never returns more than the pipe holds
+            let mut buffer = vec![0u8; size.min(self.shared_memory.capacity())];
+            let count = self.read_into(&mut buffer, py);
+            return PyBytes::new(py, &buffer[..count]);
+        }
+
+        let mut result = Vec::new();
+        loop {
+            let start = result.len();
+            result.resize(start + READ_ALL_CHUNK_SIZE, 0);
+            let count = self.read_into(&mut result[start..], py);
+            result.truncate(start + count);
+            if count == 0 {
+                break;
+            }
+        }
+        PyBytes::new(py, &result)
+    }
+
+    fn readall<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        self.read(-1, py)
+    }
+
+    /// Reads into a writable bytes-like object, returns the number of bytes read
+    fn readinto(&self, buffer: PyBuffer<u8>, py: Python<'_>) -> PyResult<usize> {
+        self.open_mode.check_read_permission();
+
+        if buffer.readonly() || !buffer.is_c_contiguous() {
+            return Err(PyValueError::new_err(
+                "The buffer must be writable and contiguous",
+            ));
+        }
+
+        let mut data = vec![0u8; buffer.len_bytes().min(self.shared_memory.capacity())];
+        let count = self.read_into(&mut data, py);
+        let target = unsafe { std::slice::from_raw_parts_mut(buffer.buf_ptr() as *mut u8, count) };
+        target.copy_from_slice(&data[..count]);
+
+        Ok(count)
+    }
+
+    /// Waits until all the data has been written, releasing the GIL
+    fn write(&self, data: PyBuffer<u8>, py: Python<'_>) -> PyResult<usize> {
+        self.open_mode.check_write_permission();
+
+        let data = data.to_vec(py)?;
+        let stats = self.shared_memory.stats();
+        let written = py.allow_threads(|| stats.observe_write(|| self.shared_memory.write(&data)));
+        stats.add_writes(1, written);
+        if written != data.len() {
+            return Err(PyBrokenPipeError::new_err("The pipe has been closed"));
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&self) {}
+
+    fn readable(&self) -> bool {
+        self.open_mode.can_read()
+    }
+
+    fn writable(&self) -> bool {
+        self.open_mode.can_write()
+    }
+
+    fn seekable(&self) -> bool {
+        false
+    }
+
+    fn isatty(&self) -> bool {
+        false
+    }
+
+    /// Number of bytes waiting to be read
+    fn __len__(&self) -> usize {
+        self.shared_memory.len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.shared_memory.capacity()
+    }
+
+    /// Counters shared by every process using the pipe, see `reset_stats`
+    fn stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        stats_dict(self.shared_memory.stats(), py)
+    }
+
+    fn reset_stats(&self) {
+        self.shared_memory.stats().reset();
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[getter]
+    fn closed(&self) -> bool {
+        self.shared_memory.is_closed()
+    }
+
+    /// Closes the pipe for every process, data that was already written can still be read
+    fn close(&self) {
+        self.open_mode.check_write_permission();
+        self.shared_memory.close();
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    /// Only a handle that can write closes the pipe, the readers leave it to the writers
+    #[pyo3(signature = (*_args))]
+    fn __exit__(&self, _args: &Bound<'_, PyTuple>) {
+        if self.open_mode.can_write() {
+            self.close();
+        }
+    }
+
+    /// Pickled as its name and mode, the process unpickling it opens the pipe again
+    fn __reduce__<'py>(&self, py: Python<'py>) -> PyResult<Reduced<'py>> {
+        reduce(py, "pipe", &self.name, self.open_mode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::{PyAnyMethods, PyBytesMethods, PyDictMethods};
+    use std::thread;
+
+    #[test]
+    fn pipe_write_read() {
+        let data = (0..=255u8).cycle().take(10_000).collect::<Vec<_>>();
+        let writer = PythonSharedPipe::create(
+            "pipe_write_read".to_string(),
+            NonZeroU32::new(100).unwrap(),
+            OpenMode::WriteOnly,
+        )
+        .unwrap();
+        let reader =
+            PythonSharedPipe::open("pipe_write_read".to_string(), OpenMode::ReadOnly).unwrap();
+
+        let reader_thread = thread::spawn(move || {
+            Python::with_gil(|py| {
+                let mut received = reader.read(3, py).as_bytes().to_vec();
+                assert_eq!(received.len(), 3);
+                // Capped to the capacity of the pipe
+                received.extend_from_slice(reader.read(isize::MAX, py).as_bytes());
+                assert!(received.len() <= 103);
+
+                // Leaving a `with` block of a reader does not close the pipe
+                reader.__exit__(&PyTuple::empty(py));
+                assert!(!reader.closed());
+                received.extend_from_slice(reader.readall(py).as_bytes());
+                assert!(reader.read(10, py).as_bytes().is_empty());
+                received
+            })
+        });
+
+        Python::with_gil(|py| {
+            let bytes = PyBytes::new(py, &data);
+            let buffer = PyBuffer::get(&bytes).unwrap();
+            assert_eq!(writer.write(buffer, py).unwrap(), data.len());
+            writer.close();
+
+            let received = py.allow_threads(|| reader_thread.join().unwrap());
+            assert_eq!(received, data);
+
+            let stats = writer.stats(py).unwrap();
+            let value = |key| {
+                stats
+                    .get_item(key)
+                    .unwrap()
+                    .unwrap()
+                    .extract::<u64>()
+                    .unwrap()
+            };
+            assert_eq!(value("writes"), 1);
+            assert_eq!(value("bytes_written"), data.len() as u64);
+            assert_eq!(value("bytes_read"), data.len() as u64);
+            assert_eq!(value("blocked_writes"), 1);
+
+            let buffer = PyBuffer::get(&bytes).unwrap();
+            assert!(writer.write(buffer, py).is_err());
+        });
+    }
+}
@@ -0,0 +1,262 @@
+use crate::container::priority_queue::{PriorityQueue, MAX_PRIORITY_LEVELS};
+use crate::helpers::bytes::RustPyBytes;
+use crate::primitives::memory_holder::SharedMemoryHolder;
+use crate::python::pickle::{reduce, Reduced};
+use crate::python::stats::stats_dict;
+use crate::python::OpenMode;
+use pyo3::exceptions::PyValueError;
+use pyo3::types::{PyDict, PyTuple};
+use pyo3::{pyclass, pymethods, Bound, PyRef, PyResult, Python};
+use std::ffi::CString;
+use std::num::NonZeroU32;
+
+#[pyclass]
+#[pyo3(frozen, name = "SharedPriorityQueue")]
+pub struct PythonSharedPriorityQueue {
+    shared_memory: SharedMemoryHolder<PriorityQueue>,
+    name: String,
+    open_mode: OpenMode,
+}
+
+impl PythonSharedPriorityQueue {
+    fn check_element(&self, data: &[u8], priority: usize) -> PyResult<()> {
+        let levels = self.shared_memory.levels();
+        if priority >= levels {
+            return Err(PyValueError::new_err(format!(
+                "Invalid priority {priority}, the queue only has {levels} levels"
+            )));
+        }
+
+        let max_element_size = self.shared_memory.max_element_size();
+        if data.len() > max_element_size {
+            return Err(PyValueError::new_err(format!(
+                "Element is too large to be sent! Max size: {}. Current element size: {}",
+                max_element_size,
+                data.len()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Counts the write if it succeeded, with the depth of the queue after it
+    fn record_write(&self, data: &[u8], write: impl FnOnce() -> bool) -> bool {
+        let stats = self.shared_memory.stats();
+        let written = stats.observe_write(write);
+        if written {
+            stats.add_writes(1, data.len());
+            stats.add_queue_depth(self.shared_memory.len());
+        }
+        written
+    }
+
+    fn record_read(&self, read: impl FnOnce() -> Option<RustPyBytes>) -> Option<RustPyBytes> {
+        let stats = self.shared_memory.stats();
+        let result = stats.observe_read(read);
+        if let Some(data) = &result {
+            stats.add_reads(1, data.0.len());
+        }
+        result
+    }
+}
+
+#[pymethods]
+impl PythonSharedPriorityQueue {
+    #[staticmethod]
+    #[pyo3(signature = (name, max_element_size, capacity, levels = 2, mode = OpenMode::ReadWrite, priority_inheritance = false))]
+    fn create(
+        name: String,
+        max_element_size: NonZeroU32,
+        capacity: NonZeroU32,
+        levels: usize,
+        mode: OpenMode,
+        priority_inheritance: bool,
+    ) -> PyResult<Self> {
+        if name.is_empty() {
+            return Err(PyValueError::new_err("Name cannot be empty"));
+        }
+        if levels == 0 || levels > MAX_PRIORITY_LEVELS {
+            return Err(PyValueError::new_err(format!(
+                "The number of levels must be between 1 and {MAX_PRIORITY_LEVELS}"
+            )));
+        }
+        let max_element_size = max_element_size.get() as usize;
+        let capacity = capacity.get() as usize;
+
+        let shared_memory = unsafe {
+            SharedMemoryHolder::<PriorityQueue>::create(
+                CString::new(name.clone())?,
+                PriorityQueue::compute_size_for(max_element_size, capacity, levels),
+            )?
+        };
+        shared_memory.init(max_element_size, capacity, levels, priority_inheritance);
+
+        Ok(Self {
+            shared_memory,
+            name,
+            open_mode: mode,
+        })
+    }
+
+    #[staticmethod]
+    #[pyo3(signature = (name, mode = OpenMode::ReadWrite))]
+    pub(crate) fn open(name: String, mode: OpenMode) -> PyResult<Self> {
+        if name.is_empty() {
+            return Err(PyValueError::new_err("Name cannot be empty"));
+        }
+
+        let shared_memory =
+            unsafe { SharedMemoryHolder::<PriorityQueue>::open(CString::new(name.clone())?)? };
+
+        Ok(Self {
+            shared_memory,
+            name,
+            open_mode: mode,
+        })
+    }
+
+    #[pyo3(signature = (data, priority = 0))]
+    fn write(&self, data: &[u8], priority: usize, py: Python<'_>) -> PyResult<bool> {
+        self.open_mode.check_write_permission();
+        self.check_element(data, priority)?;
+
+        Ok(py.allow_threads(|| {
+            self.record_write(data, || self.shared_memory.blocking_write(data, priority))
+        }))
+    }
+
+    #[pyo3(signature = (data, priority = 0))]
+    fn try_write(&self, data: &[u8], priority: usize) -> PyResult<bool> {
+        self.open_mode.check_write_permission();
+        self.check_element(data, priority)?;
+
+        Ok(self.record_write(data, || self.shared_memory.try_write(data, priority)))
+    }
+
+    fn try_read(&self) -> Option<RustPyBytes> {
+        self.open_mode.check_read_permission();
+
+        self.record_read(|| {
+            let mut result = None;
+            self.shared_memory
+                .try_read(|data| result = Some(RustPyBytes::new(data)));
+            result
+        })
+    }
+
+    fn blocking_read(&self, py: Python<'_>) -> Option<RustPyBytes> {
+        self.open_mode.check_read_permission();
+
+        py.allow_threads(|| {
+            self.record_read(|| {
+                let mut result = None;
+                self.shared_memory
+                    .blocking_read(|data| result = Some(RustPyBytes::new(data)));
+                result
+            })
+        })
+    }
+
+    fn __len__(&self) -> usize {
+        self.shared_memory.len()
+    }
+
+    /// Counters shared by every process using the queue, see `reset_stats`
+    fn stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        stats_dict(self.shared_memory.stats(), py)
+    }
+
+    fn reset_stats(&self) {
+        self.shared_memory.stats().reset();
+    }
+
+    fn levels(&self) -> usize {
+        self.shared_memory.levels()
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn is_closed(&self) -> bool {
+        self.shared_memory.is_closed()
+    }
+
+    fn close(&self) {
+        self.open_mode.check_write_permission();
+        self.shared_memory.close();
+    }
+
+    /// If this handle created the queue, closes it when the handle can write, and removes its name.
+    /// Handles that only opened the queue leave it open for the other processes
+    fn release(&self) -> PyResult<()> {
+        if self.shared_memory.is_creator() && self.open_mode.can_write() {
+            self.close();
+        }
+        Ok(self.shared_memory.unlink()?)
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    #[pyo3(signature = (*_args))]
+    fn __exit__(&self, _args: &Bound<'_, PyTuple>) -> PyResult<()> {
+        self.release()
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    /// Waits for the next element, the iteration stops once the queue is closed
+    fn __next__(&self, py: Python<'_>) -> Option<RustPyBytes> {
+        self.blocking_read(py)
+    }
+
+    /// Pickled as its name and mode, the process unpickling it opens the queue again
+    fn __reduce__<'py>(&self, py: Python<'py>) -> PyResult<Reduced<'py>> {
+        reduce(py, "priority_queue", &self.name, self.open_mode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::num::NonZero;
+
+    #[test]
+    fn priority_write_read() {
+        Python::with_gil(|py| {
+            let queue = PythonSharedPriorityQueue::create(
+                "priority_write_read".to_string(),
+                NonZero::new(8).unwrap(),
+                NonZero::new(4).unwrap(),
+                3,
+                OpenMode::ReadWrite,
+                false,
+            )
+            .unwrap();
+            assert!(queue.try_read().is_none());
+
+            assert!(queue.write(&[1], 0, py).unwrap());
+            assert!(queue.write(&[2], 0, py).unwrap());
+            assert!(queue.write(&[3], 2, py).unwrap());
+            assert!(queue.try_write(&[4], 1).unwrap());
+            assert!(queue.write(&[5], 3, py).is_err());
+            assert!(queue.write(&[0; 9], 0, py).is_err());
+            assert_eq!(queue.__len__(), 4);
+
+            assert_eq!(queue.blocking_read(py).unwrap(), RustPyBytes::new(&[3]));
+            assert_eq!(queue.try_read().unwrap(), RustPyBytes::new(&[4]));
+            assert_eq!(queue.try_read().unwrap(), RustPyBytes::new(&[1]));
+            assert_eq!(queue.try_read().unwrap(), RustPyBytes::new(&[2]));
+            assert!(queue.try_read().is_none());
+
+            queue.close();
+            assert!(queue.is_closed());
+            assert!(!queue.write(&[1], 0, py).unwrap());
+            assert!(queue.blocking_read(py).is_none());
+        });
+    }
+}
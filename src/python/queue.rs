@@ -1,32 +1,103 @@
-use crate::container::message::SharedMessage;
+use crate::container::message::{monotonic_now_ns, MessageOptions, SharedMessage};
 use crate::helpers::bytes::RustPyBytes;
+use crate::helpers::event_fd::EventFd;
+use crate::helpers::frames::{frame_size, read_frames, FRAME_HEADER_SIZE};
 use crate::helpers::queue_data::SenderQueueData;
+use crate::helpers::write_buffer::{PushError, WriteBuffer};
+use crate::primitives::latency::ReaderLatency;
 use crate::primitives::memory_holder::SharedMemoryHolder;
+use crate::primitives::wait_counters::WaitCounters;
+use crate::python::backpressure::Backpressure;
+use crate::python::pickle::{reduce_queue, QueueReduced};
+use crate::python::stats::{latency_dict, stats_dict};
+use crate::python::subscription::{Poll, PythonSubscription, SUBSCRIPTION_TIMEOUT};
 use crate::python::OpenMode;
 use pyo3::exceptions::PyValueError;
-use pyo3::types::PyBytes;
-use pyo3::{pyclass, pymethods, Bound, PyResult, Python};
+use pyo3::types::{PyBytes, PyDict, PyTuple};
+use pyo3::{import_exception, pyclass, pymethods, Bound, PyErr, PyObject, PyRef, PyResult, Python};
 use std::ffi::CString;
-use std::num::NonZeroU32;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::mpsc::{channel, Receiver, Sender};
-use std::sync::{Arc, Mutex};
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, Once, OnceLock};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+import_exception!(queue, Full);
+
+/// How often the reader thread checks if its queue handle was released
+const READER_TIMEOUT: Duration = Duration::from_millis(100);
 
 struct ReceiverQueueData {
     version: usize,
     data: RustPyBytes,
 }
 
+struct QueueReceiver {
+    receiver: Receiver<ReceiverQueueData>,
+    /// Element that was peeked at, but not consumed yet
+    peeked: Option<ReceiverQueueData>,
+}
+
+impl QueueReceiver {
+    fn new(receiver: Receiver<ReceiverQueueData>) -> Self {
+        Self {
+            receiver,
+            peeked: None,
+        }
+    }
+
+    fn try_recv(&mut self) -> Option<ReceiverQueueData> {
+        self.peeked.take().or_else(|| self.receiver.try_recv().ok())
+    }
+
+    fn recv(&mut self, timeout: Option<Duration>) -> Option<ReceiverQueueData> {
+        if let Some(peeked) = self.peeked.take() {
+            return Some(peeked);
+        }
+
+        match timeout {
+            Some(timeout) => self.receiver.recv_timeout(timeout).ok(),
+            None => self.receiver.recv().ok(),
+        }
+    }
+
+    fn recv_timeout(&mut self, timeout: Duration) -> Result<ReceiverQueueData, RecvTimeoutError> {
+        match self.peeked.take() {
+            Some(peeked) => Ok(peeked),
+            None => self.receiver.recv_timeout(timeout),
+        }
+    }
+
+    fn peek(&mut self) -> Option<&ReceiverQueueData> {
+        if self.peeked.is_none() {
+            self.peeked = self.receiver.try_recv().ok();
+        }
+        self.peeked.as_ref()
+    }
+}
+
 #[pyclass]
 #[pyo3(frozen, name = "SharedQueue")]
 pub struct PythonSharedQueue {
     shared_memory: Arc<SharedMemoryHolder<SharedMessage>>,
-    sender: Mutex<Option<Sender<SenderQueueData>>>,
-    receiver: Mutex<Option<Receiver<ReceiverQueueData>>>,
+    write_buffer: Arc<WriteBuffer<SenderQueueData>>,
+    backpressure: Backpressure,
+    writer_thread: Once,
+    receiver: Mutex<Option<QueueReceiver>>,
+    /// Joined once the handle is released, before it stops counting as a reader
+    reader_thread: Mutex<Option<JoinHandle<()>>>,
+    stop_reader: Arc<AtomicBool>,
     name: String,
     open_mode: OpenMode,
     last_written_version: Arc<AtomicUsize>,
     last_read_version: Arc<AtomicUsize>,
+    /// Created on the first call to `fileno`, signaled by the reader thread
+    event_fd: Arc<OnceLock<EventFd>>,
+    /// Only the reads of this handle, the segment also keeps those of all its readers
+    latency: Arc<ReaderLatency>,
+    /// Set by `release`, after which the handle no longer counts as a reader
+    released: AtomicBool,
 }
 
 impl PythonSharedQueue {
@@ -34,25 +105,47 @@ impl PythonSharedQueue {
         shared_memory: Arc<SharedMemoryHolder<SharedMessage>>,
         name: String,
         open_mode: OpenMode,
+        max_pending_writes: Option<NonZeroUsize>,
+        backpressure: Backpressure,
     ) -> Self {
         if open_mode.can_read() {
             shared_memory.add_reader();
         }
 
         let last_read_version = Arc::new(AtomicUsize::default());
+        let event_fd = Arc::new(OnceLock::new());
+        let latency = Arc::new(ReaderLatency::default());
+        let stop_reader = Arc::new(AtomicBool::default());
 
-        let receiver = open_mode
+        let (receiver, reader_thread) = open_mode
             .can_read()
-            .then(|| Self::start_reader_thread(shared_memory.clone(), last_read_version.clone()));
+            .then(|| {
+                Self::start_reader_thread(
+                    shared_memory.clone(),
+                    last_read_version.clone(),
+                    event_fd.clone(),
+                    latency.clone(),
+                    stop_reader.clone(),
+                )
+            })
+            .map(|(receiver, thread)| (QueueReceiver::new(receiver), thread))
+            .unzip();
 
         Self {
             shared_memory,
             name,
-            sender: Mutex::default(),
+            write_buffer: Arc::new(WriteBuffer::new(max_pending_writes)),
+            backpressure,
+            writer_thread: Once::new(),
             receiver: Mutex::new(receiver),
+            reader_thread: Mutex::new(reader_thread),
+            stop_reader,
             open_mode,
             last_written_version: Arc::default(),
             last_read_version,
+            event_fd,
+            latency,
+            released: AtomicBool::default(),
         }
     }
 }
@@ -60,7 +153,15 @@ impl PythonSharedQueue {
 #[pymethods]
 impl PythonSharedQueue {
     #[staticmethod]
-    fn create(name: String, max_element_size: NonZeroU32, mode: OpenMode) -> PyResult<Self> {
+    #[pyo3(signature = (name, max_element_size, mode, max_pending_writes = None, backpressure = Backpressure::Block, priority_inheritance = false))]
+    pub(crate) fn create(
+        name: String,
+        max_element_size: NonZeroU32,
+        mode: OpenMode,
+        max_pending_writes: Option<NonZeroUsize>,
+        backpressure: Backpressure,
+        priority_inheritance: bool,
+    ) -> PyResult<Self> {
         if name.is_empty() {
             return Err(PyValueError::new_err("Name cannot be empty"));
         }
@@ -69,15 +170,31 @@ impl PythonSharedQueue {
         let shared_memory = unsafe {
             Arc::new(SharedMemoryHolder::<SharedMessage>::create(
                 CString::new(name.clone())?,
-                SharedMessage::size_of_fields() + max_element_size,
+                SharedMessage::size_of_fields() + frame_size(max_element_size),
             )?)
         };
+        shared_memory.init(MessageOptions {
+            priority_inheritance,
+            ..MessageOptions::default()
+        });
 
-        Ok(Self::new(shared_memory, name, mode))
+        Ok(Self::new(
+            shared_memory,
+            name,
+            mode,
+            max_pending_writes,
+            backpressure,
+        ))
     }
 
     #[staticmethod]
-    fn open(name: String, mode: OpenMode) -> PyResult<Self> {
+    #[pyo3(signature = (name, mode, max_pending_writes = None, backpressure = Backpressure::Block))]
+    pub(crate) fn open(
+        name: String,
+        mode: OpenMode,
+        max_pending_writes: Option<NonZeroUsize>,
+        backpressure: Backpressure,
+    ) -> PyResult<Self> {
         if name.is_empty() {
             return Err(PyValueError::new_err("Name cannot be empty"));
         }
@@ -88,43 +205,82 @@ impl PythonSharedQueue {
             )?)?)
         };
 
-        Ok(Self::new(shared_memory, name, mode))
+        Ok(Self::new(
+            shared_memory,
+            name,
+            mode,
+            max_pending_writes,
+            backpressure,
+        ))
     }
 
-    fn write(&self, data: Bound<'_, PyBytes>) -> PyResult<()> {
-        self.open_mode.check_write_permission();
-        let queue_data = SenderQueueData::new(data);
-
-        let mut guard = self.sender.lock().unwrap();
-        let sender = guard.get_or_insert_with(|| {
-            let (sender, receiver) = channel::<SenderQueueData>();
+    fn write(&self, data: Bound<'_, PyBytes>, py: Python<'_>) -> PyResult<()> {
+        self.write_many(vec![data], py)
+    }
 
-            let last_written_version = self.last_written_version.clone();
-            let shared_memory = self.shared_memory.clone();
-            std::thread::spawn(move || loop {
-                let Ok(data) = receiver.recv() else {
-                    break;
-                };
-                let new_version = shared_memory.write_waiting_for_readers(data.bytes(), None);
+    fn write_many(&self, data: Vec<Bound<'_, PyBytes>>, py: Python<'_>) -> PyResult<()> {
+        self.open_mode.check_write_permission();
+        if self.shared_memory.is_closed() {
+            return Err(PyValueError::new_err("The queue has been closed"));
+        }
+        let max_element_size = self.max_element_size();
+
+        let queue_data = data
+            .into_iter()
+            .map(|data| {
+                let queue_data = SenderQueueData::new(data);
+                if queue_data.bytes().len() > max_element_size {
+                    return Err(PyValueError::new_err(format!(
+                        "Element is too large to be sent! Max size: {}. Current element size: {}",
+                        max_element_size,
+                        queue_data.bytes().len()
+                    )));
+                }
+                Ok(queue_data)
+            })
+            .collect::<PyResult<Vec<_>>>()?;
 
-                last_written_version.store(new_version, Ordering::Relaxed);
-            });
+        if queue_data.is_empty() {
+            return Ok(());
+        }
 
-            sender
+        self.writer_thread.call_once(|| {
+            Self::start_writer_thread(
+                self.shared_memory.clone(),
+                self.write_buffer.clone(),
+                self.last_written_version.clone(),
+            )
         });
 
-        sender.send(queue_data).map_err(|_| {
-            PyValueError::new_err("Failed to send data, the queue has been closed".to_string())
+        let result = match self.backpressure {
+            Backpressure::Block => py.allow_threads(|| self.write_buffer.push_blocking(queue_data)),
+            Backpressure::Raise => self.write_buffer.try_push(queue_data),
+            Backpressure::DropOldest => self.write_buffer.push_drop_oldest(queue_data),
+            Backpressure::DropNewest => self.write_buffer.push_drop_newest(queue_data),
+        };
+
+        result.map_err(|e| -> PyErr {
+            match e {
+                PushError::Full => Full::new_err("Too many pending writes"),
+                PushError::Closed => {
+                    PyValueError::new_err("Failed to send data, the queue has been closed")
+                }
+            }
         })
     }
 
+    /// The number of elements waiting to be written to shared memory
+    fn pending_writes(&self) -> usize {
+        self.write_buffer.len()
+    }
+
     fn try_read(&self) -> Option<RustPyBytes> {
         self.open_mode.check_read_permission();
 
-        let guard = self.receiver.lock().unwrap();
-        let receiver = guard.as_ref().expect("A reader must have a receiver");
+        let mut guard = self.receiver.lock().unwrap();
+        let receiver = guard.as_mut().expect("A reader must have a receiver");
 
-        receiver.try_recv().ok().map(|message| {
+        receiver.try_recv().map(|message| {
             self.last_read_version
                 .store(message.version, Ordering::Relaxed);
             message.data
@@ -135,10 +291,10 @@ impl PythonSharedQueue {
         self.open_mode.check_read_permission();
 
         py.allow_threads(|| {
-            let guard = self.receiver.lock().unwrap();
-            let receiver = guard.as_ref().expect("A reader must have a receiver");
+            let mut guard = self.receiver.lock().unwrap();
+            let receiver = guard.as_mut().expect("A reader must have a receiver");
 
-            receiver.recv().ok().map(|message| {
+            receiver.recv(None).map(|message| {
                 self.last_read_version
                     .store(message.version, Ordering::Relaxed);
                 message.data
@@ -146,6 +302,129 @@ impl PythonSharedQueue {
         })
     }
 
+    fn peek(&self) -> Option<RustPyBytes> {
+        self.open_mode.check_read_permission();
+
+        let mut guard = self.receiver.lock().unwrap();
+        let receiver = guard.as_mut().expect("A reader must have a receiver");
+
+        receiver.peek().map(|message| message.data.clone())
+    }
+
+    #[pyo3(signature = (max_count, timeout = None))]
+    fn read_many(
+        &self,
+        max_count: usize,
+        timeout: Option<f64>,
+        py: Python<'_>,
+    ) -> PyResult<Vec<RustPyBytes>> {
+        self.open_mode.check_read_permission();
+        let timeout = timeout
+            .map(Duration::try_from_secs_f64)
+            .transpose()
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+
+        Ok(py.allow_threads(|| {
+            let mut guard = self.receiver.lock().unwrap();
+            let receiver = guard.as_mut().expect("A reader must have a receiver");
+
+            let mut result = Vec::new();
+            if max_count == 0 {
+                return result;
+            }
+
+            let Some(first) = receiver.recv(timeout) else {
+                return result;
+            };
+
+            let mut last_version = first.version;
+            result.push(first.data);
+            for message in receiver.receiver.try_iter().take(max_count - 1) {
+                last_version = message.version;
+                result.push(message.data);
+            }
+
+            self.last_read_version
+                .store(last_version, Ordering::Relaxed);
+            result
+        }))
+    }
+
+    /// Returns a file descriptor that is readable while elements are waiting to be read,
+    /// so that the queue can be used with `selectors` and event loops
+    fn fileno(&self) -> PyResult<i32> {
+        self.open_mode.check_read_permission();
+
+        if let Some(event_fd) = self.event_fd.get() {
+            return Ok(event_fd.raw_fd());
+        }
+
+        let _ = self.event_fd.set(EventFd::new()?);
+        let event_fd = self.event_fd.get().unwrap();
+        // Elements received before the file descriptor existed were never signaled
+        let mut guard = self.receiver.lock().unwrap();
+        let receiver = guard.as_mut().expect("A reader must have a receiver");
+        if receiver.peek().is_some() || self.shared_memory.is_closed() {
+            event_fd.signal();
+        }
+
+        Ok(event_fd.raw_fd())
+    }
+
+    /// Makes the file descriptor returned by [`Self::fileno`] not readable, until the next element is received
+    fn clear(&self) {
+        if let Some(event_fd) = self.event_fd.get() {
+            event_fd.clear();
+        }
+    }
+
+    /// Calls `callback` with every element from a background thread, until unsubscribed or closed.
+    /// The elements are consumed from this handle, so it should not be read from elsewhere meanwhile
+    #[pyo3(signature = (callback, *, executor = None))]
+    fn subscribe(
+        slf: &Bound<'_, Self>,
+        callback: PyObject,
+        executor: Option<PyObject>,
+    ) -> PythonSubscription {
+        slf.get().open_mode.check_read_permission();
+
+        let handle = slf.clone().unbind();
+        let poll = move || {
+            let handle = handle.get();
+            let mut guard = handle.receiver.lock().unwrap();
+            let receiver = guard.as_mut().expect("A reader must have a receiver");
+
+            match receiver.recv_timeout(SUBSCRIPTION_TIMEOUT) {
+                Ok(message) => {
+                    handle
+                        .last_read_version
+                        .store(message.version, Ordering::Relaxed);
+                    Poll::Payload(message.data)
+                }
+                Err(RecvTimeoutError::Timeout) => Poll::Empty,
+                // The reader thread stops once the queue is closed
+                Err(RecvTimeoutError::Disconnected) => Poll::Closed,
+            }
+        };
+
+        PythonSubscription::start(poll, callback, executor)
+    }
+
+    /// Counters shared by every process using the queue, see `reset_stats`
+    fn stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        stats_dict(self.shared_memory.stats(), py)
+    }
+
+    fn reset_stats(&self) {
+        self.shared_memory.stats().reset();
+    }
+
+    /// Percentiles of the latencies of the elements received by this handle, in nanoseconds.
+    /// Elements are received by a background thread, before `try_read` or `blocking_read` return them
+    fn latency_stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        latency_dict(&self.latency, py)
+    }
+
     fn last_written_version(&self) -> usize {
         self.last_written_version.load(Ordering::Relaxed)
     }
@@ -170,50 +449,184 @@ impl PythonSharedQueue {
         self.open_mode.check_write_permission();
 
         self.shared_memory.close();
+        self.write_buffer.close();
+    }
+
+    /// If this handle created the queue, closes it when the handle can write, and removes its name.
+    /// Handles that only opened the queue leave it open for the other processes.
+    /// Waits for the reader thread to stop, after which the handle no longer counts as a reader,
+    /// so it should not be used afterwards
+    fn release(&self, py: Python<'_>) -> PyResult<()> {
+        if self.shared_memory.is_creator() && self.open_mode.can_write() {
+            self.close();
+        }
+        py.allow_threads(|| self.stop_reading());
+        Ok(self.shared_memory.unlink()?)
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    #[pyo3(signature = (*_args))]
+    fn __exit__(&self, _args: &Bound<'_, PyTuple>, py: Python<'_>) -> PyResult<()> {
+        self.release(py)
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    /// Waits for the next element, the iteration stops once the queue is closed and drained
+    fn __next__(&self, py: Python<'_>) -> Option<RustPyBytes> {
+        self.blocking_read(py)
+    }
+
+    /// Pickled as its name, mode, `max_pending_writes` and `backpressure`,
+    /// the process unpickling it opens the queue again
+    fn __reduce__<'py>(&self, py: Python<'py>) -> PyResult<QueueReduced<'py>> {
+        reduce_queue(
+            py,
+            &self.name,
+            self.open_mode,
+            self.write_buffer.capacity(),
+            self.backpressure,
+        )
     }
 }
 
 impl PythonSharedQueue {
+    /// Waits for the reader thread before the handle stops counting as a reader,
+    /// so that no version is read on its behalf afterwards
+    fn stop_reading(&self) {
+        if !self.open_mode.can_read() || self.released.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        self.stop_reader.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.reader_thread.lock().unwrap().take() {
+            let _ = thread.join();
+        }
+        self.shared_memory.remove_reader();
+    }
+
+    fn max_element_size(&self) -> usize {
+        self.shared_memory.mapped_memory_size()
+            - SharedMessage::size_of_fields()
+            - FRAME_HEADER_SIZE
+    }
+
+    fn start_writer_thread(
+        shared_memory: Arc<SharedMemoryHolder<SharedMessage>>,
+        write_buffer: Arc<WriteBuffer<SenderQueueData>>,
+        last_written_version: Arc<AtomicUsize>,
+    ) {
+        let payload_max_size = shared_memory.mapped_memory_size() - SharedMessage::size_of_fields();
+
+        std::thread::spawn(move || loop {
+            // Pack as many pending elements as fit into a single message
+            let mut batch_size = 0;
+            let Some(batch) = write_buffer.pop_while(|data| {
+                batch_size += frame_size(data.bytes().len());
+                batch_size <= payload_max_size
+            }) else {
+                break;
+            };
+
+            let stats = shared_memory.stats();
+            let new_version = stats.observe_write(|| {
+                shared_memory.write_frames_waiting_for_readers(
+                    batch.iter().map(SenderQueueData::bytes),
+                    None,
+                )
+            });
+            stats.add_writes(
+                batch.len(),
+                batch.iter().map(|data| data.bytes().len()).sum(),
+            );
+
+            last_written_version.store(new_version, Ordering::Relaxed);
+        });
+    }
+
     fn start_reader_thread(
         shared_memory: Arc<SharedMemoryHolder<SharedMessage>>,
         last_read_version: Arc<AtomicUsize>,
-    ) -> Receiver<ReceiverQueueData> {
+        event_fd: Arc<OnceLock<EventFd>>,
+        latency: Arc<ReaderLatency>,
+        stop: Arc<AtomicBool>,
+    ) -> (Receiver<ReceiverQueueData>, JoinHandle<()>) {
         let (sender, receiver) = channel();
         let mut local_last_reader_version = last_read_version.load(Ordering::Relaxed);
 
-        std::thread::spawn(move || {
-            while !shared_memory.is_closed() {
-                let mut queue_data = None;
-                shared_memory.blocking_read(local_last_reader_version, |new_version, data| {
-                    queue_data = Some(ReceiverQueueData {
-                        version: new_version,
-                        data: RustPyBytes::new(data),
-                    });
+        let thread = std::thread::spawn(move || {
+            while !shared_memory.is_closed() && !stop.load(Ordering::Relaxed) {
+                let mut queue_data = Vec::new();
+                let mut written_ns = 0;
+                let mut read_ns = 0;
+                let counters = WaitCounters::current();
+                let stats = shared_memory.stats();
+                let result = stats.observe_read(|| {
+                    shared_memory.blocking_read_timeout(
+                        local_last_reader_version,
+                        Some(READER_TIMEOUT),
+                        |info, data| {
+                            read_ns = monotonic_now_ns();
+                            written_ns = info.monotonic_ns;
+                            local_last_reader_version = info.version;
+                            queue_data.extend(read_frames(data).map(|data| ReceiverQueueData {
+                                version: info.version,
+                                data: RustPyBytes::new(data),
+                            }));
+                        },
+                    )
                 });
+                if let Err(e) = result {
+                    stats.add_corrupt_read();
+                    local_last_reader_version = e.version;
+                }
 
-                if let Some(queue_data) = queue_data {
-                    local_last_reader_version = queue_data.version;
-                    last_read_version.store(local_last_reader_version, Ordering::Relaxed);
+                if queue_data.is_empty() {
+                    continue;
+                }
+                stats.add_reads(
+                    queue_data.len(),
+                    queue_data.iter().map(|data| data.data.0.len()).sum(),
+                );
+                let lock_wait_ns = counters.elapsed().lock_wait_ns;
+                for latency in [shared_memory.latency(), &latency] {
+                    latency.record_read(written_ns, read_ns, lock_wait_ns, queue_data.len() as u64);
+                }
+                last_read_version.store(local_last_reader_version, Ordering::Relaxed);
+                for queue_data in queue_data {
                     let _ = sender.send(queue_data);
                 }
+                if let Some(event_fd) = event_fd.get() {
+                    event_fd.signal();
+                }
+            }
+
+            // Wake up the event loops, so that they notice the queue was closed
+            if let Some(event_fd) = event_fd.get() {
+                event_fd.signal();
             }
         });
 
-        receiver
+        (receiver, thread)
     }
 }
 
 impl Drop for PythonSharedQueue {
     fn drop(&mut self) {
-        if self.open_mode.can_read() {
-            self.shared_memory.remove_reader();
-        }
+        self.write_buffer.close();
+        self.stop_reading();
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use pyo3::types::{PyAnyMethods, PyDictMethods, PyList, PyListMethods};
     use std::num::NonZero;
     use std::thread;
     use std::time::Duration;
@@ -225,6 +638,9 @@ mod tests {
             name.to_string(),
             NonZero::new(size).unwrap(),
             OpenMode::ReadWrite,
+            None,
+            Backpressure::Block,
+            false,
         )
         .unwrap()
     }
@@ -238,7 +654,7 @@ mod tests {
             let none = memory.try_read();
             assert!(none.is_none());
 
-            memory.write(data.clone()).unwrap();
+            memory.write(data.clone(), py).unwrap();
             thread::sleep(Duration::from_millis(200));
             let version = memory.last_written_version();
 
@@ -258,7 +674,7 @@ mod tests {
 
             let memory = init("queue_simple_write_blocking_read", DEFAULT_SIZE);
 
-            memory.write(data.clone()).unwrap();
+            memory.write(data.clone(), py).unwrap();
             thread::sleep(Duration::from_millis(200));
             let version = memory.last_written_version();
 
@@ -276,13 +692,208 @@ mod tests {
             let memory = init("queue_multiple_writes", DEFAULT_SIZE);
 
             for i in 0..100 {
-                memory.write(PyBytes::new(py, &[i])).unwrap();
+                memory.write(PyBytes::new(py, &[i]), py).unwrap();
             }
 
             for i in 0..100 {
                 assert_eq!(memory.blocking_read(py).unwrap(), RustPyBytes::new(&[i]));
             }
+
+            let stats = memory.stats(py).unwrap();
+            let value = |key| {
+                stats
+                    .get_item(key)
+                    .unwrap()
+                    .unwrap()
+                    .extract::<u64>()
+                    .unwrap()
+            };
+            assert_eq!((value("writes"), value("bytes_written")), (100, 100));
+            assert_eq!((value("reads"), value("bytes_read")), (100, 100));
+            memory.close();
+        });
+    }
+
+    #[test]
+    fn write_many_read_many() {
+        Python::with_gil(|py| {
+            let memory = init("queue_write_many_read_many", 16);
+
+            let data = (0..50).map(|i| PyBytes::new(py, &[i])).collect();
+            memory.write_many(data, py).unwrap();
+
+            let mut read = Vec::new();
+            while read.len() < 50 {
+                let batch = memory.read_many(20, Some(1.0), py).unwrap();
+                assert!(!batch.is_empty() && batch.len() <= 20);
+                read.extend(batch);
+            }
+            let expected = (0..50).map(|i| RustPyBytes::new(&[i])).collect::<Vec<_>>();
+            assert_eq!(read, expected);
+
+            assert!(memory.read_many(20, Some(0.01), py).unwrap().is_empty());
+            assert!(memory.write(PyBytes::new(py, &[0; 17]), py).is_err());
             memory.close();
         });
     }
+
+    #[test]
+    fn peek() {
+        Python::with_gil(|py| {
+            let memory = init("queue_peek", DEFAULT_SIZE);
+            assert!(memory.peek().is_none());
+
+            memory.write(PyBytes::new(py, &[1]), py).unwrap();
+            memory.write(PyBytes::new(py, &[2]), py).unwrap();
+            thread::sleep(Duration::from_millis(200));
+
+            assert_eq!(memory.peek().unwrap(), RustPyBytes::new(&[1]));
+            assert_eq!(memory.peek().unwrap(), RustPyBytes::new(&[1]));
+            assert_eq!(memory.last_read_version(), memory.last_written_version());
+
+            assert_eq!(memory.try_read().unwrap(), RustPyBytes::new(&[1]));
+            assert_eq!(memory.peek().unwrap(), RustPyBytes::new(&[2]));
+            assert_eq!(
+                memory.read_many(10, Some(1.0), py).unwrap(),
+                [RustPyBytes::new(&[2])]
+            );
+            memory.close();
+        });
+    }
+
+    #[test]
+    fn subscribe_until_closed() {
+        Python::with_gil(|py| {
+            let queue = Bound::new(py, init("queue_subscribe_until_closed", DEFAULT_SIZE)).unwrap();
+            let received = PyList::empty(py);
+            let callback = received.getattr("append").unwrap().unbind();
+
+            let subscription = PythonSharedQueue::subscribe(&queue, callback, None);
+            for i in 0..10 {
+                queue.get().write(PyBytes::new(py, &[i]), py).unwrap();
+            }
+            while received.len() < 10 {
+                py.allow_threads(|| thread::sleep(Duration::from_millis(1)));
+            }
+            let expected = (0..10).map(|i| RustPyBytes::new(&[i])).collect::<Vec<_>>();
+            assert_eq!(received.extract::<Vec<RustPyBytes>>().unwrap(), expected);
+
+            queue.get().close();
+            while subscription.is_active() {
+                py.allow_threads(|| thread::sleep(Duration::from_millis(1)));
+            }
+            subscription.unsubscribe(py).unwrap();
+        });
+    }
+
+    #[test]
+    fn iterate_until_released() {
+        Python::with_gil(|py| {
+            let queue = init("/queue_iterate_until_released", DEFAULT_SIZE);
+            for i in 0..3 {
+                queue.write(PyBytes::new(py, &[i]), py).unwrap();
+            }
+
+            let received = (0..3)
+                .map(|_| queue.__next__(py).unwrap())
+                .collect::<Vec<_>>();
+            let expected = (0..3).map(|i| RustPyBytes::new(&[i])).collect::<Vec<_>>();
+            assert_eq!(received, expected);
+
+            queue.release(py).unwrap();
+            assert!(queue.__next__(py).is_none());
+            assert!(PythonSharedQueue::open(
+                "/queue_iterate_until_released".to_string(),
+                OpenMode::ReadOnly,
+                None,
+                Backpressure::Block
+            )
+            .is_err());
+        });
+    }
+
+    #[test]
+    fn release_stops_the_reader_thread() {
+        Python::with_gil(|py| {
+            let name = "/queue_release_stops_the_reader_thread";
+            let writer = init(name, DEFAULT_SIZE);
+            let reader = PythonSharedQueue::open(
+                name.to_string(),
+                OpenMode::ReadOnly,
+                None,
+                Backpressure::Block,
+            )
+            .unwrap();
+
+            reader.release(py).unwrap();
+            assert!(reader.reader_thread.lock().unwrap().is_none());
+
+            // Only the handle that created the queue closes it
+            let other_writer = PythonSharedQueue::open(
+                name.to_string(),
+                OpenMode::WriteOnly,
+                None,
+                Backpressure::Block,
+            )
+            .unwrap();
+            other_writer.release(py).unwrap();
+            assert!(!writer.is_closed());
+
+            // Only the writer still reads, the released handle no longer takes elements
+            writer.write(PyBytes::new(py, &[1]), py).unwrap();
+            assert_eq!(writer.blocking_read(py).unwrap().0.as_ref(), [1].as_slice());
+            let reads = |queue: &PythonSharedQueue| {
+                queue
+                    .stats(py)
+                    .unwrap()
+                    .get_item("reads")
+                    .unwrap()
+                    .unwrap()
+                    .extract::<u64>()
+                    .unwrap()
+            };
+            assert_eq!(reads(&writer), 1);
+        });
+    }
+
+    #[test]
+    fn write_after_close() {
+        Python::with_gil(|py| {
+            let memory = init("queue_write_after_close", DEFAULT_SIZE);
+            assert_eq!(memory.pending_writes(), 0);
+
+            memory.close();
+            assert!(memory.write(PyBytes::new(py, &[1]), py).is_err());
+            assert_eq!(memory.pending_writes(), 0);
+        });
+    }
+
+    #[test]
+    fn fileno_signals_received_elements() {
+        use rustix::event::{poll, PollFd, PollFlags};
+        use rustix::fd::BorrowedFd;
+
+        let is_readable = |fd: i32, timeout: i32| {
+            let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+            poll(&mut [PollFd::new(&fd, PollFlags::IN)], timeout).unwrap() == 1
+        };
+
+        Python::with_gil(|py| {
+            let queue = init("queue_fileno", DEFAULT_SIZE);
+            queue.write(PyBytes::new(py, &[1]), py).unwrap();
+            assert_eq!(queue.blocking_read(py), Some(RustPyBytes::new(&[1])));
+
+            let fd = queue.fileno().unwrap();
+            assert!(!is_readable(fd, 10));
+
+            queue.write(PyBytes::new(py, &[2]), py).unwrap();
+            assert!(is_readable(fd, 1000));
+            queue.clear();
+            assert_eq!(queue.try_read(), Some(RustPyBytes::new(&[2])));
+            assert!(!is_readable(fd, 10));
+
+            queue.close();
+            assert!(is_readable(fd, 1000));
+        });
+    }
 }
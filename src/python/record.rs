@@ -0,0 +1,217 @@
+use crate::helpers::bytes::RustPyBytes;
+use crate::helpers::frames::read_frames;
+use crate::python::message::PythonMessageInfo;
+use crate::record::{ChannelKind, Recorder, Replayer};
+use pyo3::exceptions::{PyIndexError, PyValueError};
+use pyo3::{pyclass, pymethods, IntoPyObjectExt, PyObject, PyResult, Python};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+#[pyclass]
+#[pyo3(frozen, name = "Recorder")]
+pub struct PythonRecorder {
+    /// Taken once the recording is stopped
+    recorder: Mutex<Option<Recorder>>,
+}
+
+impl PythonRecorder {
+    fn take(&self) -> PyResult<Recorder> {
+        self.recorder
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| PyValueError::new_err("The recording was already stopped"))
+    }
+}
+
+#[pymethods]
+impl PythonRecorder {
+    #[staticmethod]
+    #[pyo3(signature = (path, messages = Vec::new(), queues = Vec::new()))]
+    fn start(path: PathBuf, messages: Vec<String>, queues: Vec<String>) -> PyResult<Self> {
+        let channels = messages
+            .iter()
+            .map(|name| (name.as_str(), ChannelKind::Message))
+            .chain(
+                queues
+                    .iter()
+                    .map(|name| (name.as_str(), ChannelKind::Queue)),
+            )
+            .collect::<Vec<_>>();
+        if channels.is_empty() {
+            return Err(PyValueError::new_err("No channel to record"));
+        }
+
+        Ok(Self {
+            recorder: Mutex::new(Some(Recorder::start(path, &channels)?)),
+        })
+    }
+
+    /// Waits until every channel is closed, returns the number of records
+    fn wait(&self, py: Python<'_>) -> PyResult<usize> {
+        let recorder = self.take()?;
+        Ok(py.allow_threads(|| recorder.wait())?)
+    }
+
+    /// Stops recording, returns the number of records
+    fn stop(&self, py: Python<'_>) -> PyResult<usize> {
+        let recorder = self.take()?;
+        Ok(py.allow_threads(|| recorder.stop())?)
+    }
+}
+
+#[pyclass]
+#[pyo3(frozen, name = "Replayer")]
+pub struct PythonReplayer {
+    replayer: Replayer,
+}
+
+#[pymethods]
+impl PythonReplayer {
+    #[staticmethod]
+    #[pyo3(signature = (path, names = HashMap::new()))]
+    fn open(path: PathBuf, names: HashMap<String, String>) -> PyResult<Self> {
+        let replayer = Replayer::open(path, |name| {
+            names.get(name).cloned().unwrap_or_else(|| name.to_string())
+        })?;
+        Ok(Self { replayer })
+    }
+
+    /// The recorded channels, as (name, kind) pairs
+    fn channels(&self) -> Vec<(String, &'static str)> {
+        self.replayer
+            .log()
+            .channels()
+            .iter()
+            .map(|channel| (channel.name.clone(), channel.kind.name()))
+            .collect()
+    }
+
+    fn __len__(&self) -> usize {
+        self.replayer.log().len()
+    }
+
+    /// Returns the recorded name of the channel, the info of the message, and its data:
+    /// bytes for a message, the list of elements for a queue, None when the channel was closed
+    fn record(
+        &self,
+        position: usize,
+        py: Python<'_>,
+    ) -> PyResult<(String, PythonMessageInfo, PyObject)> {
+        let log = self.replayer.log();
+        if position >= log.len() {
+            return Err(PyIndexError::new_err("Record position out of range"));
+        }
+
+        let record = log.read(position)?;
+        let channel = &log.channels()[record.channel];
+        let data = match (record.closed, channel.kind) {
+            (true, _) => py.None(),
+            (false, ChannelKind::Message) => {
+                RustPyBytes(record.payload.into_boxed_slice()).into_py_any(py)?
+            }
+            (false, ChannelKind::Queue) => read_frames(&record.payload)
+                .map(RustPyBytes::new)
+                .collect::<Vec<_>>()
+                .into_py_any(py)?,
+        };
+
+        Ok((channel.name.clone(), PythonMessageInfo(record.info), data))
+    }
+
+    /// Writes the records into the segments, starting `start` seconds into the recording.
+    /// Returns the number of records written
+    #[pyo3(signature = (speed = Some(1.0), start = 0.0))]
+    fn replay(&self, speed: Option<f64>, start: f64, py: Python<'_>) -> PyResult<usize> {
+        if speed.is_some_and(|speed| !(speed > 0.0 && speed.is_finite())) {
+            return Err(PyValueError::new_err("The speed must be positive"));
+        }
+        if !(start >= 0.0 && start.is_finite()) {
+            return Err(PyValueError::new_err("The start must be positive"));
+        }
+
+        let log = self.replayer.log();
+        let position = match log.is_empty() {
+            true => 0,
+            false => log.position_at(log.monotonic_ns(0) + (start * 1e9) as u64),
+        };
+
+        Ok(py.allow_threads(|| self.replayer.replay(position, speed))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container::message::MessageInfo;
+    use crate::helpers::frames::{frame_size, write_frames};
+    use crate::helpers::record_log::{ChannelInfo, LogWriter, Record};
+
+    #[test]
+    fn replay_queue_records() {
+        let path = std::env::temp_dir().join("ripc_replay_queue_records");
+        let channels = [ChannelInfo {
+            name: "/replay_queue".to_string(),
+            kind: ChannelKind::Queue,
+            max_size: 64,
+            checksum: false,
+        }];
+        let mut frames = vec![0u8; frame_size(1) + frame_size(2)];
+        write_frames(&mut frames, [[1u8].as_slice(), &[2, 3]]);
+
+        let mut writer = LogWriter::create(&path, &channels).unwrap();
+        let info = MessageInfo {
+            version: 1,
+            ..MessageInfo::default()
+        };
+        writer
+            .append(&Record {
+                channel: 0,
+                info,
+                closed: false,
+                payload: frames,
+            })
+            .unwrap();
+        writer
+            .append(&Record {
+                channel: 0,
+                info,
+                closed: true,
+                payload: Vec::new(),
+            })
+            .unwrap();
+        writer.finish().unwrap();
+
+        Python::with_gil(|py| {
+            assert!(PythonRecorder::start(path.clone(), Vec::new(), Vec::new()).is_err());
+
+            let names = HashMap::from([(
+                "/replay_queue".to_string(),
+                "/replay_queue_renamed".to_string(),
+            )]);
+            let replayer = PythonReplayer::open(path.clone(), names).unwrap();
+            assert_eq!(
+                replayer.channels(),
+                [("/replay_queue".to_string(), "queue")]
+            );
+            assert_eq!(replayer.__len__(), 2);
+
+            let (name, info, data) = replayer.record(0, py).unwrap();
+            assert_eq!((name.as_str(), info.0.version), ("/replay_queue", 1));
+            let elements = data.extract::<Vec<RustPyBytes>>(py).unwrap();
+            assert_eq!(
+                elements,
+                [RustPyBytes::new(&[1]), RustPyBytes::new(&[2, 3])]
+            );
+            assert!(replayer.record(1, py).unwrap().2.is_none(py));
+            assert!(replayer.record(2, py).is_err());
+
+            assert!(replayer.replay(Some(0.0), 0.0, py).is_err());
+            assert_eq!(replayer.replay(None, 0.0, py).unwrap(), 2);
+            assert_eq!(replayer.replay(None, 1.0, py).unwrap(), 0);
+        });
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
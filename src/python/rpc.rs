@@ -0,0 +1,462 @@
+use crate::container::circular_queue::{CircularQueue, OverflowPolicy, WriteError};
+use crate::container::reply_slots::{ReplyError, ReplySlots};
+use crate::helpers::bytes::RustPyBytes;
+use crate::primitives::memory_holder::SharedMemoryHolder;
+use crate::python::stats::stats_dict;
+use pyo3::exceptions::{PyRuntimeError, PyTimeoutError, PyValueError};
+use pyo3::types::PyDict;
+use pyo3::{pyclass, pymethods, Bound, PyResult, Python};
+use std::ffi::CString;
+use std::mem::size_of;
+use std::num::NonZeroU32;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// 64 - correlation id
+// 32 - reply slot
+// 32 - padding
+// N - request data
+
+const REQUEST_HEADER_SIZE: usize = 2 * size_of::<u64>();
+
+const DEFAULT_CAPACITY: NonZeroU32 = NonZeroU32::new(16).unwrap();
+const DEFAULT_MAX_CLIENTS: NonZeroU32 = NonZeroU32::new(16).unwrap();
+
+fn encode_request(correlation_id: u64, slot: u32, data: &[u8]) -> Vec<u8> {
+    let mut request = Vec::with_capacity(REQUEST_HEADER_SIZE + data.len());
+    request.extend_from_slice(&correlation_id.to_ne_bytes());
+    request.extend_from_slice(&u64::from(slot).to_ne_bytes());
+    request.extend_from_slice(data);
+    request
+}
+
+/// Returns None if the request is too short to have a header
+fn decode_request(request: &[u8]) -> Option<(u64, u32, &[u8])> {
+    let (header, data) = request.split_at_checked(REQUEST_HEADER_SIZE)?;
+    let correlation_id = u64::from_ne_bytes(header[..8].try_into().unwrap());
+    let slot = u64::from_ne_bytes(header[8..].try_into().unwrap()) as u32;
+    Some((correlation_id, slot, data))
+}
+
+/// Replies are written to a second segment, named after the service
+fn replies_name(name: &str) -> PyResult<CString> {
+    Ok(CString::new(format!("{name}.replies"))?)
+}
+
+fn parse_timeout(timeout: Option<f64>) -> PyResult<Option<Duration>> {
+    timeout
+        .map(Duration::try_from_secs_f64)
+        .transpose()
+        .map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+/// Server side of a request/reply channel
+#[pyclass]
+#[pyo3(frozen, name = "SharedService")]
+pub struct PythonSharedService {
+    requests: SharedMemoryHolder<CircularQueue>,
+    replies: Arc<SharedMemoryHolder<ReplySlots>>,
+    name: String,
+}
+
+#[pymethods]
+impl PythonSharedService {
+    /// `capacity` is the number of requests that can wait to be received,
+    /// `max_clients` is the number of clients that can be connected at the same time
+    #[staticmethod]
+    #[pyo3(signature = (name, max_request_size, max_reply_size, capacity = DEFAULT_CAPACITY, max_clients = DEFAULT_MAX_CLIENTS))]
+    fn create(
+        name: String,
+        max_request_size: NonZeroU32,
+        max_reply_size: NonZeroU32,
+        capacity: NonZeroU32,
+        max_clients: NonZeroU32,
+    ) -> PyResult<Self> {
+        if name.is_empty() {
+            return Err(PyValueError::new_err("Name cannot be empty"));
+        }
+        let max_element_size = REQUEST_HEADER_SIZE + max_request_size.get() as usize;
+        let capacity = capacity.get() as usize;
+        let max_reply_size = max_reply_size.get() as usize;
+        let max_clients = max_clients.get() as usize;
+
+        let requests = unsafe {
+            SharedMemoryHolder::<CircularQueue>::create(
+                CString::new(name.clone())?,
+                CircularQueue::compute_size_for(max_element_size, capacity),
+            )?
+        };
+        requests.init(max_element_size, capacity, OverflowPolicy::Block);
+
+        let replies = unsafe {
+            SharedMemoryHolder::<ReplySlots>::create(
+                replies_name(&name)?,
+                ReplySlots::compute_size_for(max_clients, max_reply_size),
+            )?
+        };
+        replies.init(max_clients, max_reply_size);
+
+        Ok(Self {
+            requests,
+            replies: Arc::new(replies),
+            name,
+        })
+    }
+
+    /// Waits for the next request, returns None if the timeout elapsed or if the service was closed.
+    /// Malformed requests are skipped
+    #[pyo3(signature = (timeout = None))]
+    fn receive(
+        &self,
+        timeout: Option<f64>,
+        py: Python<'_>,
+    ) -> PyResult<Option<PythonServiceRequest>> {
+        let deadline = parse_timeout(timeout)?.map(|timeout| Instant::now() + timeout);
+
+        let mut request = None;
+        let stats = self.requests.stats();
+        py.allow_threads(|| loop {
+            let timeout =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            let count = stats.observe_read(|| {
+                self.requests.read_many(1, timeout, |data| {
+                    let Some((correlation_id, slot, data)) = decode_request(data) else {
+                        return;
+                    };
+                    request = Some(PythonServiceRequest {
+                        data: RustPyBytes::new(data),
+                        correlation_id,
+                        slot,
+                        replies: self.replies.clone(),
+                        replied: AtomicBool::new(false),
+                    });
+                })
+            });
+            if count == 0 || request.is_some() {
+                break;
+            }
+        });
+        if let Some(request) = &request {
+            stats.add_reads(1, request.data.0.len());
+        }
+
+        Ok(request)
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn is_closed(&self) -> bool {
+        self.requests.is_closed()
+    }
+
+    /// Counters of the requests, shared by the service and its clients, see `reset_stats`
+    fn stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        stats_dict(self.requests.stats(), py)
+    }
+
+    fn reset_stats(&self) {
+        self.requests.stats().reset();
+    }
+
+    /// Stops the service, waking up the clients waiting for replies
+    fn close(&self) {
+        self.requests.close();
+        self.replies.close();
+    }
+}
+
+/// Nobody else replies to the clients, which would otherwise wait forever
+impl Drop for PythonSharedService {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// A request received by a [`PythonSharedService`], which must be answered with `reply`
+#[pyclass]
+#[pyo3(frozen, name = "ServiceRequest")]
+pub struct PythonServiceRequest {
+    data: RustPyBytes,
+    correlation_id: u64,
+    slot: u32,
+    replies: Arc<SharedMemoryHolder<ReplySlots>>,
+    replied: AtomicBool,
+}
+
+#[pymethods]
+impl PythonServiceRequest {
+    #[getter]
+    fn data(&self) -> RustPyBytes {
+        self.data.clone()
+    }
+
+    #[getter]
+    fn correlation_id(&self) -> u64 {
+        self.correlation_id
+    }
+
+    /// Returns false if the client is no longer waiting for this reply
+    fn reply(&self, data: &[u8]) -> PyResult<bool> {
+        let max_reply_size = self.replies.max_reply_size();
+        if data.len() > max_reply_size {
+            return Err(PyValueError::new_err(format!(
+                "Reply is too large to be sent! Max size: {}. Current reply size: {}",
+                max_reply_size,
+                data.len()
+            )));
+        }
+        if self.replied.swap(true, Ordering::Relaxed) {
+            return Err(PyValueError::new_err("The request was already replied to"));
+        }
+
+        Ok(self.replies.reply(self.slot, self.correlation_id, data))
+    }
+}
+
+/// Client side of a request/reply channel, owns one of the reply slots of the service
+#[pyclass]
+#[pyo3(frozen, name = "SharedClient")]
+pub struct PythonSharedClient {
+    requests: SharedMemoryHolder<CircularQueue>,
+    replies: SharedMemoryHolder<ReplySlots>,
+    name: String,
+    slot: u32,
+    next_correlation_id: AtomicU64,
+    /// The reply slot can only wait for one request at a time
+    call_lock: Mutex<()>,
+}
+
+#[pymethods]
+impl PythonSharedClient {
+    #[staticmethod]
+    fn open(name: String) -> PyResult<Self> {
+        if name.is_empty() {
+            return Err(PyValueError::new_err("Name cannot be empty"));
+        }
+
+        let requests =
+            unsafe { SharedMemoryHolder::<CircularQueue>::open(CString::new(name.clone())?)? };
+        let replies = unsafe { SharedMemoryHolder::<ReplySlots>::open(replies_name(&name)?)? };
+        let slot = replies.acquire().ok_or_else(|| {
+            PyRuntimeError::new_err("The service has reached its maximum number of clients")
+        })?;
+
+        Ok(Self {
+            requests,
+            replies,
+            name,
+            slot,
+            next_correlation_id: AtomicU64::new(1),
+            call_lock: Mutex::new(()),
+        })
+    }
+
+    /// Sends the request and waits for its reply, returns None if the service was closed
+    #[pyo3(signature = (data, timeout = None))]
+    fn call(
+        &self,
+        data: &[u8],
+        timeout: Option<f64>,
+        py: Python<'_>,
+    ) -> PyResult<Option<RustPyBytes>> {
+        let timeout = parse_timeout(timeout)?;
+        let max_request_size = self.max_request_size();
+        if data.len() > max_request_size {
+            return Err(PyValueError::new_err(format!(
+                "Request is too large to be sent! Max size: {}. Current request size: {}",
+                max_request_size,
+                data.len()
+            )));
+        }
+
+        let correlation_id = self.next_correlation_id.fetch_add(1, Ordering::Relaxed);
+        let request = encode_request(correlation_id, self.slot, data);
+
+        let mut reply = None;
+        let result = py.allow_threads(|| {
+            let deadline = timeout.map(|timeout| Instant::now() + timeout);
+            let remaining =
+                || deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            let _guard = self.call_lock.lock().unwrap();
+
+            self.replies.expect_reply(self.slot, correlation_id);
+            let stats = self.requests.stats();
+            stats
+                .observe_write(|| self.requests.blocking_write_timeout(&request, remaining()))
+                .map_err(|e| match e {
+                    WriteError::TimedOut => ReplyError::TimedOut,
+                    WriteError::Closed => ReplyError::Closed,
+                })?;
+            stats.add_writes(1, data.len());
+            stats.add_queue_depth(self.requests.len());
+            self.replies.wait_reply(self.slot, remaining(), |data| {
+                reply = Some(RustPyBytes::new(data));
+            })
+        });
+
+        match result {
+            Ok(()) => Ok(reply),
+            Err(ReplyError::Closed) => Ok(None),
+            Err(ReplyError::TimedOut) => {
+                Err(PyTimeoutError::new_err("The service did not reply in time"))
+            }
+        }
+    }
+
+    fn max_request_size(&self) -> usize {
+        self.requests.max_element_size() - REQUEST_HEADER_SIZE
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn is_closed(&self) -> bool {
+        self.requests.is_closed()
+    }
+
+    /// Counters of the requests, shared by the service and its clients, see `reset_stats`
+    fn stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        stats_dict(self.requests.stats(), py)
+    }
+
+    fn reset_stats(&self) {
+        self.requests.stats().reset();
+    }
+}
+
+impl Drop for PythonSharedClient {
+    fn drop(&mut self) {
+        self.replies.release(self.slot);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::{PyAnyMethods, PyDictMethods};
+    use std::thread;
+
+    #[test]
+    fn call_and_reply() {
+        let service = PythonSharedService::create(
+            "rpc_call_and_reply".to_string(),
+            NonZeroU32::new(64).unwrap(),
+            NonZeroU32::new(64).unwrap(),
+            NonZeroU32::new(4).unwrap(),
+            NonZeroU32::new(2).unwrap(),
+        )
+        .unwrap();
+        let client = PythonSharedClient::open("rpc_call_and_reply".to_string()).unwrap();
+
+        let server = thread::spawn(move || {
+            Python::with_gil(|py| {
+                while let Some(request) = service.receive(None, py).unwrap() {
+                    let mut reply = request.data().0;
+                    reply.reverse();
+                    assert!(request.reply(&reply).unwrap());
+                    assert!(request.reply(&reply).is_err());
+                }
+            })
+        });
+
+        Python::with_gil(|py| {
+            for i in 0..10u8 {
+                let reply = client.call(&[i, 1, 2], Some(5.0), py).unwrap().unwrap();
+                assert_eq!(reply, RustPyBytes::new(&[2, 1, i]));
+            }
+            assert!(client.call(&[0; 65], None, py).is_err());
+
+            // The server only stops once the client closes the requests queue
+            client.requests.close();
+            py.allow_threads(|| server.join().unwrap());
+            assert!(client.call(&[1], None, py).unwrap().is_none());
+
+            let stats = client.stats(py).unwrap();
+            let value = |key| {
+                stats
+                    .get_item(key)
+                    .unwrap()
+                    .unwrap()
+                    .extract::<u64>()
+                    .unwrap()
+            };
+            assert_eq!((value("writes"), value("reads")), (10, 10));
+            assert_eq!((value("bytes_written"), value("bytes_read")), (30, 30));
+            client.reset_stats();
+            assert_eq!(
+                client
+                    .stats(py)
+                    .unwrap()
+                    .get_item("writes")
+                    .unwrap()
+                    .unwrap()
+                    .extract::<u64>()
+                    .unwrap(),
+                0
+            );
+        });
+    }
+
+    #[test]
+    fn skip_malformed_requests() {
+        let service = PythonSharedService::create(
+            "rpc_skip_malformed_requests".to_string(),
+            NonZeroU32::new(8).unwrap(),
+            NonZeroU32::new(8).unwrap(),
+            NonZeroU32::new(4).unwrap(),
+            NonZeroU32::new(1).unwrap(),
+        )
+        .unwrap();
+        let client = PythonSharedClient::open("rpc_skip_malformed_requests".to_string()).unwrap();
+
+        assert!(client.requests.try_write(&[1, 2, 3]));
+        assert!(client.requests.try_write(&encode_request(7, 0, &[4])));
+        Python::with_gil(|py| {
+            let request = service.receive(Some(0.01), py).unwrap().unwrap();
+            assert_eq!(
+                (request.correlation_id(), request.data()),
+                (7, RustPyBytes::new(&[4]))
+            );
+            assert!(service.receive(Some(0.01), py).unwrap().is_none());
+
+            // Dropping the service closes it
+            drop(service);
+            assert!(client.call(&[1], None, py).unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn call_timeout() {
+        let service = PythonSharedService::create(
+            "rpc_call_timeout".to_string(),
+            NonZeroU32::new(8).unwrap(),
+            NonZeroU32::new(8).unwrap(),
+            NonZeroU32::new(4).unwrap(),
+            NonZeroU32::new(1).unwrap(),
+        )
+        .unwrap();
+        let client = PythonSharedClient::open("rpc_call_timeout".to_string()).unwrap();
+        assert!(PythonSharedClient::open("rpc_call_timeout".to_string()).is_err());
+
+        Python::with_gil(|py| {
+            let error = client.call(&[1], Some(0.01), py).unwrap_err();
+            assert!(error.is_instance_of::<PyTimeoutError>(py));
+
+            // The late reply is discarded
+            let request = service.receive(Some(0.01), py).unwrap().unwrap();
+            assert!(!request.reply(&[2]).unwrap());
+            assert!(service.receive(Some(0.01), py).unwrap().is_none());
+
+            // The timeout also applies while the requests queue is full
+            for _ in 0..5 {
+                let error = client.call(&[1], Some(0.01), py).unwrap_err();
+                assert!(error.is_instance_of::<PyTimeoutError>(py));
+            }
+            assert_eq!(service.requests.len(), 4);
+        });
+    }
+}
@@ -0,0 +1,32 @@
+use crate::primitives::channel_stats::ChannelStats;
+use crate::primitives::latency::ReaderLatency;
+use pyo3::types::{PyDict, PyDictMethods};
+use pyo3::{Bound, PyResult, Python};
+
+/// The counters as a dict, in the order of [`ChannelStats::values`]
+pub(crate) fn stats_dict<'py>(
+    stats: &ChannelStats,
+    py: Python<'py>,
+) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    for (key, value) in stats.values() {
+        dict.set_item(key, value)?;
+    }
+    Ok(dict)
+}
+
+/// A dict of percentiles for each histogram, see [`ReaderLatency::summaries`]
+pub(crate) fn latency_dict<'py>(
+    latency: &ReaderLatency,
+    py: Python<'py>,
+) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    for (key, summary) in latency.summaries() {
+        let percentiles = PyDict::new(py);
+        for (percentile, value) in summary.values() {
+            percentiles.set_item(percentile, value)?;
+        }
+        dict.set_item(key, percentiles)?;
+    }
+    Ok(dict)
+}
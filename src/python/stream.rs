@@ -0,0 +1,512 @@
+use crate::container::circular_queue::{CircularQueue, OverflowPolicy};
+use crate::helpers::process::ProcessId;
+use crate::primitives::memory_holder::SharedMemoryHolder;
+use pyo3::exceptions::{PyEOFError, PyValueError};
+use pyo3::types::{PyBytes, PyTuple};
+use pyo3::{pyclass, pymethods, Bound, PyRef, PyRefMut, PyResult, Python};
+use std::ffi::CString;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Number of chunks that can be in flight between a stream writer and its reader
+const STREAM_CAPACITY: usize = 8;
+
+/// How long a writer dropped before closing its stream waits for room to tell the reader
+const ABORT_TIMEOUT: Duration = Duration::from_millis(100);
+/// How often a reader waiting for the next chunk checks that the writer process is still alive
+const WRITER_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+const LAST_CHUNK: u32 = 1 << 0;
+/// Sent instead of the last chunk by a writer dropped before closing its stream
+const ABORTED: u32 = 1 << 1;
+
+// 32 - stream id: pid of the writer
+// 32 - stream id: counter of the writer process
+// 32 - sequence number
+// 32 - flags
+// N - chunk data
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct ChunkHeader {
+    stream_id: u64,
+    sequence: u32,
+    flags: u32,
+}
+
+impl ChunkHeader {
+    const SIZE: usize = size_of::<u64>() + 2 * size_of::<u32>();
+
+    fn encode(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0u8; Self::SIZE];
+        bytes[..8].copy_from_slice(&self.stream_id.to_ne_bytes());
+        bytes[8..12].copy_from_slice(&self.sequence.to_ne_bytes());
+        bytes[12..].copy_from_slice(&self.flags.to_ne_bytes());
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Option<Self> {
+        let bytes = bytes.get(..Self::SIZE)?;
+        Some(Self {
+            stream_id: u64::from_ne_bytes(bytes[..8].try_into().unwrap()),
+            sequence: u32::from_ne_bytes(bytes[8..12].try_into().unwrap()),
+            flags: u32::from_ne_bytes(bytes[12..].try_into().unwrap()),
+        })
+    }
+}
+
+pub(crate) type StreamQueue = Arc<SharedMemoryHolder<CircularQueue>>;
+
+/// Streams of a message are sent through a separate segment, named after the message
+fn stream_queue_name(message_name: &str) -> PyResult<CString> {
+    Ok(CString::new(format!("{message_name}.stream"))?)
+}
+
+/// Each chunk, including its header, is at most `chunk_size` bytes
+pub(crate) fn create_stream_queue(message_name: &str, chunk_size: usize) -> PyResult<StreamQueue> {
+    if chunk_size <= ChunkHeader::SIZE {
+        return Err(PyValueError::new_err(format!(
+            "Streaming requires a size larger than {} bytes",
+            ChunkHeader::SIZE
+        )));
+    }
+
+    let shared_memory = unsafe {
+        SharedMemoryHolder::<CircularQueue>::create(
+            stream_queue_name(message_name)?,
+            CircularQueue::compute_size_for(chunk_size, STREAM_CAPACITY),
+        )?
+    };
+    shared_memory.init(chunk_size, STREAM_CAPACITY, OverflowPolicy::Block);
+
+    Ok(Arc::new(shared_memory))
+}
+
+pub(crate) fn open_stream_queue(message_name: &str) -> PyResult<StreamQueue> {
+    let shared_memory =
+        unsafe { SharedMemoryHolder::<CircularQueue>::open(stream_queue_name(message_name)?)? };
+
+    Ok(Arc::new(shared_memory))
+}
+
+/// File-like object splitting everything written into chunks, sent when full or when flushed.
+/// The chunks of concurrent writers would interleave, so only one writer may stream at a time
+#[pyclass(name = "MessageStreamWriter")]
+pub struct PythonMessageStreamWriter {
+    queue: StreamQueue,
+    stream_id: u64,
+    sequence: u32,
+    buffer: Vec<u8>,
+    closed: bool,
+}
+
+impl PythonMessageStreamWriter {
+    pub(crate) fn new(queue: StreamQueue) -> Self {
+        static NEXT_STREAM: AtomicU32 = AtomicU32::new(0);
+
+        let chunk_data_size = queue.max_element_size() - ChunkHeader::SIZE;
+        let stream_id = (u64::from(std::process::id()) << 32)
+            | u64::from(NEXT_STREAM.fetch_add(1, Ordering::Relaxed));
+
+        Self {
+            queue,
+            stream_id,
+            sequence: 0,
+            buffer: Vec::with_capacity(chunk_data_size),
+            closed: false,
+        }
+    }
+
+    fn check_open(&self) -> PyResult<()> {
+        if self.closed {
+            return Err(PyValueError::new_err("I/O operation on closed stream"));
+        }
+        Ok(())
+    }
+
+    fn chunk_data_size(&self) -> usize {
+        self.queue.max_element_size() - ChunkHeader::SIZE
+    }
+
+    fn send_chunk(&mut self, flags: u32, py: Python<'_>) -> PyResult<()> {
+        let header = ChunkHeader {
+            stream_id: self.stream_id,
+            sequence: self.sequence,
+            flags,
+        };
+        let mut chunk = Vec::with_capacity(ChunkHeader::SIZE + self.buffer.len());
+        chunk.extend_from_slice(&header.encode());
+        chunk.extend_from_slice(&self.buffer);
+
+        let queue = &self.queue;
+        if !py.allow_threads(|| queue.blocking_write(&chunk)) {
+            return Err(PyEOFError::new_err("The stream channel has been closed"));
+        }
+
+        self.sequence += 1;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+/// The reader of a stream that was started would otherwise wait forever for its last chunk
+impl Drop for PythonMessageStreamWriter {
+    fn drop(&mut self) {
+        if self.closed || self.sequence == 0 {
+            return;
+        }
+
+        let header = ChunkHeader {
+            stream_id: self.stream_id,
+            sequence: self.sequence,
+            flags: ABORTED,
+        };
+        let _ = self
+            .queue
+            .blocking_write_timeout(&header.encode(), Some(ABORT_TIMEOUT));
+    }
+}
+
+#[pymethods]
+impl PythonMessageStreamWriter {
+    pub(crate) fn write(&mut self, data: &[u8], py: Python<'_>) -> PyResult<usize> {
+        self.check_open()?;
+
+        let chunk_data_size = self.chunk_data_size();
+        let mut remaining = data;
+        while !remaining.is_empty() {
+            let free = chunk_data_size - self.buffer.len();
+            let (now, later) = remaining.split_at(free.min(remaining.len()));
+            self.buffer.extend_from_slice(now);
+            remaining = later;
+
+            if self.buffer.len() == chunk_data_size {
+                self.send_chunk(0, py)?;
+            }
+        }
+
+        Ok(data.len())
+    }
+
+    fn flush(&mut self, py: Python<'_>) -> PyResult<()> {
+        self.check_open()?;
+        if !self.buffer.is_empty() {
+            self.send_chunk(0, py)?;
+        }
+        Ok(())
+    }
+
+    /// Sends the remaining data and marks the end of the stream
+    pub(crate) fn close(&mut self, py: Python<'_>) -> PyResult<()> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+        self.send_chunk(LAST_CHUNK, py)
+    }
+
+    #[getter]
+    fn closed(&self) -> bool {
+        self.closed
+    }
+
+    fn writable(&self) -> bool {
+        true
+    }
+
+    fn readable(&self) -> bool {
+        false
+    }
+
+    fn seekable(&self) -> bool {
+        false
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    #[pyo3(signature = (*_args))]
+    fn __exit__(&mut self, _args: &Bound<'_, PyTuple>, py: Python<'_>) -> PyResult<()> {
+        self.close(py)
+    }
+}
+
+/// File-like object reassembling the chunks of a single stream
+#[pyclass(name = "MessageStreamReader")]
+pub struct PythonMessageStreamReader {
+    queue: StreamQueue,
+    /// Unknown until the first chunk of the stream is received
+    stream_id: Option<u64>,
+    sequence: u32,
+    buffer: Vec<u8>,
+    position: usize,
+    finished: bool,
+    closed: bool,
+}
+
+impl PythonMessageStreamReader {
+    pub(crate) fn new(queue: StreamQueue) -> Self {
+        Self {
+            queue,
+            stream_id: None,
+            sequence: 0,
+            buffer: Vec::new(),
+            position: 0,
+            finished: false,
+            closed: false,
+        }
+    }
+
+    /// Waits for the next chunk of this stream and makes it the current buffer
+    fn receive_chunk(&mut self, py: Python<'_>) -> PyResult<()> {
+        loop {
+            let mut chunk = None;
+            let queue = &self.queue;
+            py.allow_threads(|| {
+                queue.blocking_read_timeout(
+                    |data| chunk = Some(data.to_vec()),
+                    Some(WRITER_CHECK_INTERVAL),
+                );
+            });
+            let Some(chunk) = chunk else {
+                if queue.is_closed() {
+                    return Err(PyEOFError::new_err(
+                        "The stream was closed before it was fully received",
+                    ));
+                }
+                // A killed writer cannot send the abort chunk
+                if self.stream_id.is_some_and(|id| !is_writer_alive(id)) {
+                    self.finished = true;
+                    return Err(PyEOFError::new_err(
+                        "The stream writer exited before closing the stream",
+                    ));
+                }
+                continue;
+            };
+
+            let header = ChunkHeader::decode(&chunk)
+                .ok_or_else(|| PyValueError::new_err("Received an invalid stream chunk"))?;
+
+            match self.stream_id {
+                // Leftovers of a stream abandoned by another reader are skipped
+                None if header.sequence != 0 => continue,
+                None => self.stream_id = Some(header.stream_id),
+                Some(stream_id) => {
+                    if header.stream_id != stream_id || header.sequence != self.sequence {
+                        return Err(PyValueError::new_err(format!(
+                            "Stream chunks are out of order, expected chunk {} of stream {:#x} but got chunk {} of stream {:#x}",
+                            self.sequence, stream_id, header.sequence, header.stream_id
+                        )));
+                    }
+                }
+            }
+
+            if header.flags & ABORTED != 0 {
+                self.finished = true;
+                return Err(PyEOFError::new_err(
+                    "The stream writer was dropped before closing the stream",
+                ));
+            }
+
+            self.sequence += 1;
+            self.finished = header.flags & LAST_CHUNK != 0;
+            self.buffer = chunk;
+            self.position = ChunkHeader::SIZE;
+            return Ok(());
+        }
+    }
+
+    fn available(&self) -> &[u8] {
+        &self.buffer[self.position.min(self.buffer.len())..]
+    }
+}
+
+/// The start time of the writer is unknown, a reused PID is taken for the writer
+fn is_writer_alive(stream_id: u64) -> bool {
+    ProcessId {
+        pid: (stream_id >> 32) as u32,
+        start_time: 0,
+    }
+    .is_alive()
+}
+
+#[pymethods]
+impl PythonMessageStreamReader {
+    /// Reads at most `size` bytes, or until the end of the stream if `size` is negative.
+    /// Only returns less than `size` bytes when no more data is immediately available
+    #[pyo3(signature = (size = -1))]
+    fn read<'py>(&mut self, size: isize, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        if self.closed {
+            return Err(PyValueError::new_err("I/O operation on closed stream"));
+        }
+
+        let mut result = Vec::new();
+        loop {
+            let wanted = if size < 0 {
+                usize::MAX
+            } else {
+                size as usize - result.len()
+            };
+            let available = self.available();
+            let taken = wanted.min(available.len());
+            result.extend_from_slice(&available[..taken]);
+            self.position += taken;
+
+            let done = size >= 0 && result.len() == size as usize;
+            if done || (self.finished && self.available().is_empty()) {
+                break;
+            }
+            if size >= 0 && !result.is_empty() {
+                break;
+            }
+            self.receive_chunk(py)?;
+        }
+
+        Ok(PyBytes::new(py, &result))
+    }
+
+    pub(crate) fn readall<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        self.read(-1, py)
+    }
+
+    fn close(&mut self) {
+        self.closed = true;
+    }
+
+    #[getter]
+    fn closed(&self) -> bool {
+        self.closed
+    }
+
+    fn readable(&self) -> bool {
+        true
+    }
+
+    fn writable(&self) -> bool {
+        false
+    }
+
+    fn seekable(&self) -> bool {
+        false
+    }
+
+    fn __enter__(slf: PyRefMut<'_, Self>) -> PyRefMut<'_, Self> {
+        slf
+    }
+
+    #[pyo3(signature = (*_args))]
+    fn __exit__(&mut self, _args: &Bound<'_, PyTuple>) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyBytesMethods;
+    use std::thread;
+
+    #[test]
+    fn chunk_header_round_trip() {
+        let header = ChunkHeader {
+            stream_id: 0x1234_5678_9abc,
+            sequence: 42,
+            flags: LAST_CHUNK,
+        };
+        let mut chunk = header.encode().to_vec();
+        chunk.extend_from_slice(&[1, 2, 3]);
+
+        assert_eq!(ChunkHeader::decode(&chunk), Some(header));
+        assert_eq!(ChunkHeader::decode(&chunk[..ChunkHeader::SIZE - 1]), None);
+    }
+
+    #[test]
+    fn stream_round_trip() {
+        let queue = create_stream_queue("stream_round_trip", 32).unwrap();
+        let reader_queue = open_stream_queue("stream_round_trip").unwrap();
+        let data = (0..=255u8).cycle().take(1000).collect::<Vec<_>>();
+
+        let reader = thread::spawn(move || {
+            Python::with_gil(|py| {
+                let mut reader = PythonMessageStreamReader::new(reader_queue);
+                let mut received = reader.read(10, py).unwrap().as_bytes().to_vec();
+                received.extend_from_slice(reader.read(-1, py).unwrap().as_bytes());
+                assert!(reader.read(10, py).unwrap().as_bytes().is_empty());
+                received
+            })
+        });
+
+        Python::with_gil(|py| {
+            let mut writer = PythonMessageStreamWriter::new(queue);
+            assert_eq!(writer.write(&data[..500], py).unwrap(), 500);
+            writer.flush(py).unwrap();
+            assert_eq!(writer.write(&data[500..], py).unwrap(), 500);
+            writer.close(py).unwrap();
+            assert!(writer.write(&data, py).is_err());
+
+            let received = py.allow_threads(|| reader.join().unwrap());
+            assert_eq!(received, data);
+        });
+    }
+
+    #[test]
+    fn stream_closed_mid_way() {
+        let queue = create_stream_queue("stream_closed_mid_way", 32).unwrap();
+
+        Python::with_gil(|py| {
+            let mut writer = PythonMessageStreamWriter::new(queue.clone());
+            writer.write(&[1; 20], py).unwrap();
+            writer.flush(py).unwrap();
+            queue.close();
+
+            let mut reader = PythonMessageStreamReader::new(queue);
+            let error = reader.read(-1, py).unwrap_err();
+            assert!(error.is_instance_of::<PyEOFError>(py));
+        });
+    }
+
+    #[test]
+    fn writer_dropped_mid_way() {
+        let queue = create_stream_queue("stream_writer_dropped_mid_way", 32).unwrap();
+
+        Python::with_gil(|py| {
+            let mut writer = PythonMessageStreamWriter::new(queue.clone());
+            writer.write(&[1; 20], py).unwrap();
+            writer.flush(py).unwrap();
+            drop(writer);
+
+            let mut reader = PythonMessageStreamReader::new(queue);
+            let error = reader.read(-1, py).unwrap_err();
+            assert!(error.is_instance_of::<PyEOFError>(py));
+            assert!(reader.read(-1, py).unwrap().as_bytes().is_empty());
+        });
+    }
+
+    #[test]
+    fn writer_killed_mid_way() {
+        let queue = create_stream_queue("stream_writer_killed_mid_way", 32).unwrap();
+
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let dead_pid = child.id();
+        child.wait().unwrap();
+
+        // The first chunk of a stream whose writer died without sending anything else
+        let header = ChunkHeader {
+            stream_id: u64::from(dead_pid) << 32,
+            sequence: 0,
+            flags: 0,
+        };
+        let mut chunk = header.encode().to_vec();
+        chunk.extend_from_slice(&[1, 2, 3]);
+        assert!(queue.try_write(&chunk));
+
+        Python::with_gil(|py| {
+            let mut reader = PythonMessageStreamReader::new(queue);
+            assert_eq!(reader.read(3, py).unwrap().as_bytes(), [1, 2, 3]);
+            let error = reader.read(-1, py).unwrap_err();
+            assert!(error.is_instance_of::<PyEOFError>(py));
+        });
+    }
+}
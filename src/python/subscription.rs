@@ -0,0 +1,203 @@
+use crate::helpers::bytes::RustPyBytes;
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::{pyclass, pymethods, PyObject, PyResult, Python};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// How often the subscription thread checks if it was unsubscribed
+pub(crate) const SUBSCRIPTION_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// What the subscription thread got from its channel, after waiting at most [`SUBSCRIPTION_TIMEOUT`]
+pub(crate) enum Poll {
+    Payload(RustPyBytes),
+    Empty,
+    Closed,
+}
+
+/// Calls the callback, or submits it to the executor, with the payload.
+/// Exceptions cannot be raised to anyone, so they are reported like those of a thread
+fn deliver(py: Python<'_>, callback: &PyObject, executor: Option<&PyObject>, data: RustPyBytes) {
+    let result = match executor {
+        Some(executor) => executor.call_method1(py, "submit", (callback, data)),
+        None => callback.call1(py, (data,)),
+    };
+    if let Err(e) = result {
+        e.write_unraisable(py, Some(callback.bind(py)));
+    }
+}
+
+/// Background reader calling a Python callback with every new payload of a channel.
+/// Dropping it does not stop the thread, which holds its own reference to the channel
+#[pyclass]
+#[pyo3(frozen, name = "Subscription")]
+pub struct PythonSubscription {
+    stop: Arc<AtomicBool>,
+    /// Taken once unsubscribed
+    thread: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl PythonSubscription {
+    /// `poll` runs without the GIL, the thread stops once it returns [`Poll::Closed`]
+    pub(crate) fn start(
+        mut poll: impl FnMut() -> Poll + Send + 'static,
+        callback: PyObject,
+        executor: Option<PyObject>,
+    ) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    match poll() {
+                        Poll::Payload(data) => Python::with_gil(|py| {
+                            deliver(py, &callback, executor.as_ref(), data);
+                        }),
+                        Poll::Empty => {}
+                        Poll::Closed => break,
+                    }
+                }
+
+                // The handle of the channel captured by `poll` is a Python object as well
+                Python::with_gil(|_| drop((poll, callback, executor)));
+            })
+        };
+
+        Self {
+            stop,
+            thread: Mutex::new(Some(thread)),
+        }
+    }
+}
+
+#[pymethods]
+impl PythonSubscription {
+    /// Stops the thread and waits for the callback in progress, if any.
+    /// Does nothing if already unsubscribed
+    pub(crate) fn unsubscribe(&self, py: Python<'_>) -> PyResult<()> {
+        self.stop.store(true, Ordering::Relaxed);
+
+        let Some(thread) = self.thread.lock().unwrap().take() else {
+            return Ok(());
+        };
+        // Called from the callback, which cannot wait for itself to return
+        if thread.thread().id() == std::thread::current().id() {
+            return Ok(());
+        }
+
+        py.allow_threads(|| thread.join())
+            .map_err(|_| PyRuntimeError::new_err("The subscription thread panicked"))
+    }
+
+    /// False once unsubscribed, or once the channel was closed
+    pub(crate) fn is_active(&self) -> bool {
+        self.thread
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|thread| !thread.is_finished())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::python::message::PythonSharedMessage;
+    use crate::python::OpenMode;
+    use pyo3::types::{PyAnyMethods, PyDict, PyList, PyListMethods};
+    use pyo3::{ffi::c_str, Bound};
+    use std::num::NonZero;
+
+    fn wait_for_len(received: &Bound<'_, PyList>, len: usize) {
+        while received.len() < len {
+            received
+                .py()
+                .allow_threads(|| std::thread::sleep(Duration::from_millis(1)));
+        }
+    }
+
+    #[test]
+    fn subscribe_to_message() {
+        Python::with_gil(|py| {
+            let message = Bound::new(
+                py,
+                PythonSharedMessage::create(
+                    "/subscribe_to_message".to_string(),
+                    NonZero::new(64).unwrap(),
+                    OpenMode::ReadWrite,
+                    false,
+                    false,
+                    false,
+                )
+                .unwrap(),
+            )
+            .unwrap();
+            let received = PyList::empty(py);
+            let callback = received.getattr("append").unwrap().unbind();
+
+            let subscription = PythonSharedMessage::subscribe(&message, callback, None);
+            message.get().write_parts(&[b"first"], py).unwrap();
+            wait_for_len(&received, 1);
+            message.get().write_parts(&[b"second"], py).unwrap();
+            wait_for_len(&received, 2);
+            assert_eq!(
+                received.extract::<Vec<RustPyBytes>>().unwrap(),
+                [RustPyBytes::new(b"first"), RustPyBytes::new(b"second")]
+            );
+
+            assert!(subscription.is_active());
+            message.get().close();
+            while subscription.is_active() {
+                py.allow_threads(|| std::thread::sleep(Duration::from_millis(1)));
+            }
+            subscription.unsubscribe(py).unwrap();
+            subscription.unsubscribe(py).unwrap();
+        });
+    }
+
+    #[test]
+    fn subscribe_with_executor() {
+        Python::with_gil(|py| {
+            let message = Bound::new(
+                py,
+                PythonSharedMessage::create(
+                    "/subscribe_with_executor".to_string(),
+                    NonZero::new(64).unwrap(),
+                    OpenMode::ReadWrite,
+                    false,
+                    false,
+                    false,
+                )
+                .unwrap(),
+            )
+            .unwrap();
+            let locals = PyDict::new(py);
+            py.run(
+                c_str!(
+                    "from concurrent.futures import ThreadPoolExecutor\n\
+                     executor = ThreadPoolExecutor(max_workers=1)\n\
+                     received = []"
+                ),
+                None,
+                Some(&locals),
+            )
+            .unwrap();
+            let executor = locals.get_item("executor").unwrap();
+            let received = locals.get_item("received").unwrap();
+            let received = received.downcast::<PyList>().unwrap();
+            let callback = received.getattr("append").unwrap().unbind();
+
+            let subscription =
+                PythonSharedMessage::subscribe(&message, callback, Some(executor.clone().unbind()));
+            message.get().write_parts(&[b"data"], py).unwrap();
+            wait_for_len(received, 1);
+            subscription.unsubscribe(py).unwrap();
+            assert!(!subscription.is_active());
+
+            executor.call_method0("shutdown").unwrap();
+            message.get().close();
+        });
+    }
+}
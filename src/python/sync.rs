@@ -0,0 +1,466 @@
+use crate::container::sync::{
+    SharedBarrier, SharedCondition, SharedEvent, SharedLock, SharedSemaphore,
+};
+use crate::primitives::memory_holder::{SharedMemoryHolder, SlicePtrCast};
+use crate::python::pickle::{reduce, Reduced};
+use crate::python::OpenMode;
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::types::{PyAnyMethods, PyTuple};
+use pyo3::{import_exception, pyclass, pymethods, Bound, PyAny, PyResult, Python};
+use std::ffi::CString;
+use std::mem::size_of;
+use std::num::NonZeroU32;
+use std::time::{Duration, Instant};
+
+import_exception!(threading, BrokenBarrierError);
+
+fn create_segment<T: SlicePtrCast>(name: &str) -> PyResult<SharedMemoryHolder<T>> {
+    if name.is_empty() {
+        return Err(PyValueError::new_err("Name cannot be empty"));
+    }
+
+    Ok(unsafe { SharedMemoryHolder::<T>::create(CString::new(name)?, size_of::<T>())? })
+}
+
+fn open_segment<T: SlicePtrCast>(name: &str) -> PyResult<SharedMemoryHolder<T>> {
+    if name.is_empty() {
+        return Err(PyValueError::new_err("Name cannot be empty"));
+    }
+
+    Ok(unsafe { SharedMemoryHolder::<T>::open(CString::new(name)?)? })
+}
+
+fn parse_timeout(timeout: Option<f64>) -> PyResult<Option<Duration>> {
+    timeout
+        .map(Duration::try_from_secs_f64)
+        .transpose()
+        .map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+/// Same rules as `threading.Lock.acquire`, a timeout of -1 waits forever
+fn acquire_timeout(blocking: bool, timeout: Option<f64>) -> PyResult<Option<Duration>> {
+    let timeout = timeout.filter(|&timeout| timeout != -1.0);
+    if !blocking {
+        if timeout.is_some() {
+            return Err(PyValueError::new_err(
+                "can't specify a timeout for a non-blocking call",
+            ));
+        }
+        return Ok(Some(Duration::ZERO));
+    }
+
+    parse_timeout(timeout)
+}
+
+#[pyclass]
+#[pyo3(frozen, name = "Lock")]
+pub struct PythonLock {
+    shared_memory: SharedMemoryHolder<SharedLock>,
+    name: String,
+}
+
+#[pymethods]
+impl PythonLock {
+    #[staticmethod]
+    fn create(name: String) -> PyResult<Self> {
+        Ok(Self {
+            shared_memory: create_segment(&name)?,
+            name,
+        })
+    }
+
+    #[staticmethod]
+    pub(crate) fn open(name: String) -> PyResult<Self> {
+        Ok(Self {
+            shared_memory: open_segment(&name)?,
+            name,
+        })
+    }
+
+    /// Pickled as its name, the process unpickling it opens the lock again
+    fn __reduce__<'py>(&self, py: Python<'py>) -> PyResult<Reduced<'py>> {
+        reduce(py, "lock", &self.name, OpenMode::ReadWrite)
+    }
+
+    #[pyo3(signature = (blocking = true, timeout = -1.0))]
+    fn acquire(&self, blocking: bool, timeout: f64, py: Python<'_>) -> PyResult<bool> {
+        let timeout = acquire_timeout(blocking, Some(timeout))?;
+
+        Ok(py.allow_threads(|| self.shared_memory.acquire(timeout)))
+    }
+
+    fn release(&self) -> PyResult<()> {
+        if !self.shared_memory.release() {
+            return Err(PyRuntimeError::new_err("release unlocked lock"));
+        }
+        Ok(())
+    }
+
+    fn locked(&self) -> bool {
+        self.shared_memory.is_locked()
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn __enter__(&self, py: Python<'_>) -> bool {
+        py.allow_threads(|| self.shared_memory.acquire(None))
+    }
+
+    #[pyo3(signature = (*_args))]
+    fn __exit__(&self, _args: &Bound<'_, PyTuple>) -> PyResult<()> {
+        self.release()
+    }
+}
+
+/// Condition variable with its own lock
+#[pyclass]
+#[pyo3(frozen, name = "Condition")]
+pub struct PythonCondition {
+    shared_memory: SharedMemoryHolder<SharedCondition>,
+    name: String,
+}
+
+impl PythonCondition {
+    fn wait_for_duration(&self, timeout: Option<Duration>, py: Python<'_>) -> PyResult<bool> {
+        py.allow_threads(|| self.shared_memory.wait(timeout))
+            .ok_or_else(|| PyRuntimeError::new_err("cannot wait on un-acquired lock"))
+    }
+}
+
+#[pymethods]
+impl PythonCondition {
+    #[staticmethod]
+    fn create(name: String) -> PyResult<Self> {
+        Ok(Self {
+            shared_memory: create_segment(&name)?,
+            name,
+        })
+    }
+
+    #[staticmethod]
+    pub(crate) fn open(name: String) -> PyResult<Self> {
+        Ok(Self {
+            shared_memory: open_segment(&name)?,
+            name,
+        })
+    }
+
+    /// Pickled as its name, the process unpickling it opens the condition again
+    fn __reduce__<'py>(&self, py: Python<'py>) -> PyResult<Reduced<'py>> {
+        reduce(py, "condition", &self.name, OpenMode::ReadWrite)
+    }
+
+    #[pyo3(signature = (blocking = true, timeout = -1.0))]
+    fn acquire(&self, blocking: bool, timeout: f64, py: Python<'_>) -> PyResult<bool> {
+        let timeout = acquire_timeout(blocking, Some(timeout))?;
+
+        Ok(py.allow_threads(|| self.shared_memory.acquire(timeout)))
+    }
+
+    fn release(&self) -> PyResult<()> {
+        if !self.shared_memory.release() {
+            return Err(PyRuntimeError::new_err("release unlocked lock"));
+        }
+        Ok(())
+    }
+
+    /// Returns false if the timeout elapsed before being notified
+    #[pyo3(signature = (timeout = None))]
+    fn wait(&self, timeout: Option<f64>, py: Python<'_>) -> PyResult<bool> {
+        self.wait_for_duration(parse_timeout(timeout)?, py)
+    }
+
+    /// Waits until the predicate is true, returns the last value returned by the predicate
+    #[pyo3(signature = (predicate, timeout = None))]
+    fn wait_for<'py>(
+        &self,
+        predicate: &Bound<'py, PyAny>,
+        timeout: Option<f64>,
+        py: Python<'py>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let deadline = parse_timeout(timeout)?.map(|timeout| Instant::now() + timeout);
+
+        let mut result = predicate.call0()?;
+        while !result.is_truthy()? {
+            let remaining = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+                    Some(deadline - now)
+                }
+                None => None,
+            };
+            self.wait_for_duration(remaining, py)?;
+            result = predicate.call0()?;
+        }
+
+        Ok(result)
+    }
+
+    #[pyo3(signature = (n = 1))]
+    fn notify(&self, n: u32) -> PyResult<()> {
+        if !self.shared_memory.notify(n) {
+            return Err(PyRuntimeError::new_err("cannot notify on un-acquired lock"));
+        }
+        Ok(())
+    }
+
+    fn notify_all(&self) -> PyResult<()> {
+        if !self.shared_memory.notify_all() {
+            return Err(PyRuntimeError::new_err("cannot notify on un-acquired lock"));
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn __enter__(&self, py: Python<'_>) -> bool {
+        py.allow_threads(|| self.shared_memory.acquire(None))
+    }
+
+    #[pyo3(signature = (*_args))]
+    fn __exit__(&self, _args: &Bound<'_, PyTuple>) -> PyResult<()> {
+        self.release()
+    }
+}
+
+#[pyclass]
+#[pyo3(frozen, name = "Semaphore")]
+pub struct PythonSemaphore {
+    shared_memory: SharedMemoryHolder<SharedSemaphore>,
+    name: String,
+}
+
+#[pymethods]
+impl PythonSemaphore {
+    #[staticmethod]
+    #[pyo3(signature = (name, value = 1))]
+    fn create(name: String, value: u32) -> PyResult<Self> {
+        let shared_memory = create_segment::<SharedSemaphore>(&name)?;
+        shared_memory.init(value);
+
+        Ok(Self {
+            shared_memory,
+            name,
+        })
+    }
+
+    #[staticmethod]
+    pub(crate) fn open(name: String) -> PyResult<Self> {
+        Ok(Self {
+            shared_memory: open_segment(&name)?,
+            name,
+        })
+    }
+
+    /// Pickled as its name, the process unpickling it opens the semaphore again
+    fn __reduce__<'py>(&self, py: Python<'py>) -> PyResult<Reduced<'py>> {
+        reduce(py, "semaphore", &self.name, OpenMode::ReadWrite)
+    }
+
+    #[pyo3(signature = (blocking = true, timeout = None))]
+    fn acquire(&self, blocking: bool, timeout: Option<f64>, py: Python<'_>) -> PyResult<bool> {
+        let timeout = acquire_timeout(blocking, timeout)?;
+
+        Ok(py.allow_threads(|| self.shared_memory.acquire(timeout)))
+    }
+
+    #[pyo3(signature = (n = 1))]
+    fn release(&self, n: u32) -> PyResult<()> {
+        if n == 0 {
+            return Err(PyValueError::new_err("n must be one or more"));
+        }
+        self.shared_memory.release(n);
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn __enter__(&self, py: Python<'_>) -> bool {
+        py.allow_threads(|| self.shared_memory.acquire(None))
+    }
+
+    #[pyo3(signature = (*_args))]
+    fn __exit__(&self, _args: &Bound<'_, PyTuple>) {
+        self.shared_memory.release(1);
+    }
+}
+
+#[pyclass]
+#[pyo3(frozen, name = "Event")]
+pub struct PythonEvent {
+    shared_memory: SharedMemoryHolder<SharedEvent>,
+    name: String,
+}
+
+#[pymethods]
+impl PythonEvent {
+    #[staticmethod]
+    fn create(name: String) -> PyResult<Self> {
+        Ok(Self {
+            shared_memory: create_segment(&name)?,
+            name,
+        })
+    }
+
+    #[staticmethod]
+    pub(crate) fn open(name: String) -> PyResult<Self> {
+        Ok(Self {
+            shared_memory: open_segment(&name)?,
+            name,
+        })
+    }
+
+    /// Pickled as its name, the process unpickling it opens the event again
+    fn __reduce__<'py>(&self, py: Python<'py>) -> PyResult<Reduced<'py>> {
+        reduce(py, "event", &self.name, OpenMode::ReadWrite)
+    }
+
+    fn is_set(&self) -> bool {
+        self.shared_memory.is_set()
+    }
+
+    fn set(&self) {
+        self.shared_memory.set();
+    }
+
+    fn clear(&self) {
+        self.shared_memory.clear();
+    }
+
+    /// Returns true once the flag is set, or false if the timeout elapsed
+    #[pyo3(signature = (timeout = None))]
+    fn wait(&self, timeout: Option<f64>, py: Python<'_>) -> PyResult<bool> {
+        let timeout = parse_timeout(timeout)?;
+
+        Ok(py.allow_threads(|| self.shared_memory.wait(timeout)))
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[pyclass]
+#[pyo3(frozen, name = "Barrier")]
+pub struct PythonBarrier {
+    shared_memory: SharedMemoryHolder<SharedBarrier>,
+    name: String,
+}
+
+#[pymethods]
+impl PythonBarrier {
+    #[staticmethod]
+    fn create(name: String, parties: NonZeroU32) -> PyResult<Self> {
+        let shared_memory = create_segment::<SharedBarrier>(&name)?;
+        shared_memory.init(parties.get());
+
+        Ok(Self {
+            shared_memory,
+            name,
+        })
+    }
+
+    #[staticmethod]
+    pub(crate) fn open(name: String) -> PyResult<Self> {
+        Ok(Self {
+            shared_memory: open_segment(&name)?,
+            name,
+        })
+    }
+
+    /// Pickled as its name, the process unpickling it opens the barrier again
+    fn __reduce__<'py>(&self, py: Python<'py>) -> PyResult<Reduced<'py>> {
+        reduce(py, "barrier", &self.name, OpenMode::ReadWrite)
+    }
+
+    /// Returns the arrival index, between 0 and parties - 1
+    #[pyo3(signature = (timeout = None))]
+    fn wait(&self, timeout: Option<f64>, py: Python<'_>) -> PyResult<u32> {
+        let timeout = parse_timeout(timeout)?;
+
+        py.allow_threads(|| self.shared_memory.wait(timeout))
+            .map_err(|_| BrokenBarrierError::new_err(()))
+    }
+
+    fn reset(&self) {
+        self.shared_memory.reset();
+    }
+
+    fn abort(&self) {
+        self.shared_memory.abort();
+    }
+
+    #[getter]
+    fn parties(&self) -> u32 {
+        self.shared_memory.parties()
+    }
+
+    #[getter]
+    fn n_waiting(&self) -> u32 {
+        self.shared_memory.n_waiting()
+    }
+
+    #[getter]
+    fn broken(&self) -> bool {
+        self.shared_memory.is_broken()
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn lock_across_handles() {
+        Python::with_gil(|py| {
+            let lock = PythonLock::create("sync_lock_across_handles".to_string()).unwrap();
+            let other = PythonLock::open("sync_lock_across_handles".to_string()).unwrap();
+
+            assert!(lock.acquire(true, -1.0, py).unwrap());
+            assert!(other.locked());
+            assert!(!other.acquire(false, -1.0, py).unwrap());
+            assert!(other.acquire(false, 1.0, py).is_err());
+            assert!(!other.acquire(true, 0.01, py).unwrap());
+
+            other.release().unwrap();
+            assert!(lock.release().is_err());
+        });
+    }
+
+    #[test]
+    fn barrier_broken() {
+        let barrier = PythonBarrier::create(
+            "sync_barrier_broken".to_string(),
+            NonZeroU32::new(2).unwrap(),
+        )
+        .unwrap();
+        let other = PythonBarrier::open("sync_barrier_broken".to_string()).unwrap();
+
+        let party = thread::spawn(move || Python::with_gil(|py| other.wait(None, py).unwrap()));
+        Python::with_gil(|py| {
+            let index = barrier.wait(None, py).unwrap();
+            let other_index = py.allow_threads(|| party.join().unwrap());
+            assert_eq!(index + other_index, 1);
+
+            let error = barrier.wait(Some(0.01), py).unwrap_err();
+            assert!(error.is_instance_of::<BrokenBarrierError>(py));
+            assert!(barrier.broken());
+            barrier.reset();
+            assert!(!barrier.broken());
+        });
+    }
+}
@@ -0,0 +1,285 @@
+//! Recording of `SharedMessage` and `SharedQueue` channels to a file, and replay of the recordings
+//! into fresh segments. The format of the file is described in [`crate::helpers::record_log`].
+
+use crate::container::message::{monotonic_now_ns, MessageInfo, MessageOptions, SharedMessage};
+use crate::helpers::record_log::LogWriter;
+use crate::primitives::memory_holder::SharedMemoryHolder;
+use std::ffi::CString;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+pub use crate::helpers::record_log::{ChannelInfo, ChannelKind, LogReader, Record};
+
+/// How often the recording threads check if the recorder was stopped
+const RECORD_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Records every version written to the channels, until they are closed or the recorder is stopped.
+/// The recorder counts as a reader of each channel, so that the writers of a queue wait for it
+pub struct Recorder {
+    stop: Arc<AtomicBool>,
+    channel_threads: Vec<JoinHandle<()>>,
+    writer_thread: Option<JoinHandle<std::io::Result<usize>>>,
+}
+
+impl Recorder {
+    pub fn start(
+        path: impl AsRef<Path>,
+        channels: &[(&str, ChannelKind)],
+    ) -> std::io::Result<Self> {
+        let mut segments = Vec::with_capacity(channels.len());
+        let mut infos = Vec::with_capacity(channels.len());
+        for &(name, kind) in channels {
+            let segment =
+                unsafe { SharedMemoryHolder::<SharedMessage>::open(CString::new(name)?)? };
+            infos.push(ChannelInfo {
+                name: name.to_string(),
+                kind,
+                max_size: segment.mapped_memory_size() - SharedMessage::size_of_fields(),
+                checksum: segment.has_checksum(),
+            });
+            segments.push(segment);
+        }
+
+        let mut writer = LogWriter::create(path, &infos)?;
+        let (sender, receiver) = channel::<Record>();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let channel_threads = segments
+            .into_iter()
+            .enumerate()
+            .map(|(channel, segment)| {
+                // Registered before returning, so that no version written after `start` is missed
+                segment.add_reader();
+                let stop = stop.clone();
+                let sender = sender.clone();
+                std::thread::spawn(move || Self::record_channel(channel, segment, &stop, &sender))
+            })
+            .collect();
+
+        let writer_thread = std::thread::spawn(move || {
+            for record in receiver {
+                writer.append(&record)?;
+            }
+            writer.finish()
+        });
+
+        Ok(Self {
+            stop,
+            channel_threads,
+            writer_thread: Some(writer_thread),
+        })
+    }
+
+    fn record_channel(
+        channel: usize,
+        segment: SharedMemoryHolder<SharedMessage>,
+        stop: &AtomicBool,
+        sender: &Sender<Record>,
+    ) {
+        let mut version = 0;
+
+        while !stop.load(Ordering::Relaxed) {
+            let new_version = segment.wait_for_new_version(version, RECORD_TIMEOUT);
+            if segment.is_closed() {
+                let _ = sender.send(Record {
+                    channel,
+                    info: MessageInfo {
+                        version,
+                        monotonic_ns: monotonic_now_ns(),
+                        ..MessageInfo::default()
+                    },
+                    closed: true,
+                    payload: Vec::new(),
+                });
+                break;
+            }
+            if new_version == version {
+                continue;
+            }
+
+            let mut record = None;
+            let result = segment.try_read(version, |info, data| {
+                record = Some(Record {
+                    channel,
+                    info: *info,
+                    closed: false,
+                    payload: data.to_vec(),
+                });
+            });
+            if let Err(e) = result {
+                segment.stats().add_corrupt_read();
+                version = e.version;
+            }
+
+            if let Some(record) = record {
+                version = record.info.version;
+                // The writer thread failed, its error is returned by `stop` or `wait`
+                if sender.send(record).is_err() {
+                    break;
+                }
+            }
+        }
+
+        segment.remove_reader();
+    }
+
+    fn join(&mut self) -> std::io::Result<usize> {
+        for thread in self.channel_threads.drain(..) {
+            let _ = thread.join();
+        }
+
+        match self.writer_thread.take() {
+            Some(thread) => thread.join().expect("The writer thread panicked"),
+            None => Ok(0),
+        }
+    }
+
+    /// Waits until every channel is closed, returns the number of records
+    pub fn wait(mut self) -> std::io::Result<usize> {
+        self.join()
+    }
+
+    /// Stops recording and finishes the file, returns the number of records
+    pub fn stop(mut self) -> std::io::Result<usize> {
+        self.stop.store(true, Ordering::Relaxed);
+        self.join()
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = self.join();
+    }
+}
+
+/// Writes the records of a recording into fresh segments, created with the sizes and options of the recorded ones
+pub struct Replayer {
+    log: LogReader,
+    segments: Vec<SharedMemoryHolder<SharedMessage>>,
+}
+
+impl Replayer {
+    /// Creates a segment for every recorded channel, named `rename(recorded name)`
+    pub fn open(path: impl AsRef<Path>, rename: impl Fn(&str) -> String) -> std::io::Result<Self> {
+        let log = LogReader::open(path)?;
+
+        let segments = log
+            .channels()
+            .iter()
+            .map(|channel| {
+                let segment = unsafe {
+                    SharedMemoryHolder::<SharedMessage>::create(
+                        CString::new(rename(&channel.name))?,
+                        SharedMessage::size_of_fields() + channel.max_size,
+                    )?
+                };
+                segment.init(MessageOptions {
+                    checksum: channel.checksum,
+                    ..MessageOptions::default()
+                });
+                Ok(segment)
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        Ok(Self { log, segments })
+    }
+
+    pub fn log(&self) -> &LogReader {
+        &self.log
+    }
+
+    /// Writes the records from position `start`, returns the number of records written.
+    /// With a `speed`, the records are spaced like they were recorded, divided by the speed,
+    /// without it they are written as fast as possible. Queues wait for their readers in both cases
+    pub fn replay(&self, start: usize, speed: Option<f64>) -> std::io::Result<usize> {
+        let Some(first_ns) = (start < self.log.len()).then(|| self.log.monotonic_ns(start)) else {
+            return Ok(0);
+        };
+        let started = Instant::now();
+
+        for position in start..self.log.len() {
+            if let Some(speed) = speed {
+                let offset = self.log.monotonic_ns(position).saturating_sub(first_ns);
+                let deadline = started + Duration::from_secs_f64(offset as f64 / 1e9 / speed);
+                std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+            }
+
+            let record = self.log.read(position)?;
+            let segment = &self.segments[record.channel];
+            if record.closed {
+                segment.close();
+                continue;
+            }
+
+            match self.log.channels()[record.channel].kind {
+                ChannelKind::Message => segment.write(&record.payload),
+                ChannelKind::Queue => segment.write_waiting_for_readers(&record.payload, None),
+            };
+        }
+
+        Ok(self.log.len() - start)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create(name: &str, size: usize) -> SharedMemoryHolder<SharedMessage> {
+        let segment = unsafe {
+            SharedMemoryHolder::<SharedMessage>::create(
+                CString::new(name).unwrap(),
+                SharedMessage::size_of_fields() + size,
+            )
+            .unwrap()
+        };
+        segment.init(MessageOptions::default());
+        segment
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let path = std::env::temp_dir().join("ripc_record_and_replay");
+        let message = create("/record_and_replay", 16);
+
+        let recorder =
+            Recorder::start(&path, &[("/record_and_replay", ChannelKind::Message)]).unwrap();
+        for i in 1..=3u8 {
+            // The recorder is a reader, waiting for it guarantees that every version is recorded
+            message.write_waiting_for_readers(&[i; 4], None);
+        }
+        message.write_waiting_for_readers(&[], None);
+        // Closing drops the unread version, wait for the recorder to read it
+        while !message
+            .describe()
+            .contains(&("reads of the version", "1".to_string()))
+        {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        message.close();
+        assert_eq!(recorder.wait().unwrap(), 5);
+
+        let replayer = Replayer::open(&path, |name| format!("{name}_replay")).unwrap();
+        let log = replayer.log();
+        assert_eq!(log.channels()[0].max_size, 16);
+        assert_eq!(log.read(0).unwrap().payload, [1; 4]);
+        assert_eq!(log.read(0).unwrap().info.version, 1);
+        assert!(log.read(4).unwrap().closed);
+
+        let replayed = unsafe {
+            SharedMemoryHolder::<SharedMessage>::open(
+                CString::new("/record_and_replay_replay").unwrap(),
+            )
+            .unwrap()
+        };
+        assert_eq!(replayer.replay(2, None).unwrap(), 3);
+        assert!(replayed.is_closed());
+
+        std::fs::remove_file(path).unwrap();
+    }
+}